@@ -0,0 +1,777 @@
+//! Account entries decoded from the blob
+
+use Result;
+use Error;
+use SecureStorage;
+use blob::Items;
+use cipher;
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A single account entry
+pub struct Account {
+    /// Unique account ID
+    pub id: String,
+    /// Account name
+    pub name: String,
+    /// Group (folder) containing this account, can be empty
+    pub group: String,
+    /// Full path of the account: `group/name`, or just `name` if the
+    /// group is empty
+    pub fullname: String,
+    /// Account URL
+    pub url: String,
+    /// Login
+    pub username: String,
+    /// Password
+    pub password: SecureStorage,
+    /// Account notes
+    pub note: SecureStorage,
+    /// Last time the account was accessed, `None` if unknown
+    pub last_touch: Option<SystemTime>,
+    /// Last time the account was modified, `None` if unknown
+    pub last_modified: Option<SystemTime>,
+    /// `true` if the account is marked as a favorite
+    pub favorite: bool,
+    /// Custom form fields
+    pub fields: Vec<Field>,
+    /// ID of the shared folder containing this account, if any
+    pub share_id: Option<String>,
+    /// `true` if the account is in a read-only shared folder
+    pub readonly: bool,
+    /// Key used to encrypt the attachments, `None` if the account
+    /// never had any
+    pub attach_key: Option<SecureStorage>,
+    /// Files attached to the account
+    pub attachments: Vec<Attachment>,
+}
+
+/// Custom form field attached to an account
+pub struct Field {
+    /// Field name
+    pub name: String,
+    /// Field type ("text", "password", "checkbox"...)
+    pub field_type: String,
+    /// Field value
+    pub value: SecureStorage,
+    /// `true` if a checkbox or radio field is checked
+    pub checked: bool,
+}
+
+impl Field {
+    /// Create a new text field `name` containing `value`
+    pub fn new(name: &str, value: SecureStorage) -> Field {
+        Field {
+            name: name.to_owned(),
+            field_type: "text".to_owned(),
+            value: value,
+            checked: false,
+        }
+    }
+
+    /// Decode an `ACFL` chunk's payload `data`, decrypting the value
+    /// with `key` if necessary.
+    pub fn from_chunk(data: &[u8], key: &[u8]) -> Result<Field> {
+        let mut items = Items::new(data);
+
+        let name = try!(plain_string(&mut items));
+        let field_type = try!(plain_string(&mut items));
+
+        let value =
+            if is_encrypted_type(&field_type) {
+                try!(crypt_secure(&mut items, key))
+            } else {
+                try!(SecureStorage::from_slice(try!(items.next_or_empty())))
+            };
+
+        let checked = try!(boolean(&mut items));
+
+        Ok(Field {
+            name: name,
+            field_type: field_type,
+            value: value,
+            checked: checked,
+        })
+    }
+
+    /// Return `true` if the value of the field is stored encrypted
+    pub fn is_encrypted(&self) -> bool {
+        is_encrypted_type(&self.field_type)
+    }
+}
+
+/// File attached to an account. Only the metadata is in the blob, the
+/// contents are downloaded with `Session::get_attachment`.
+pub struct Attachment {
+    /// Unique attachment ID
+    pub id: String,
+    /// ID of the account the file is attached to
+    pub parent: String,
+    /// MIME type of the file
+    pub mime_type: String,
+    /// Identifier of the file on the server
+    pub storage_key: String,
+    /// Size of the file in bytes as reported by the server
+    pub size: u64,
+    /// Original name of the file
+    pub filename: String,
+}
+
+impl Attachment {
+    /// Decode an `ATTA` chunk's payload `data`. The file name is
+    /// decrypted with the account's `attach_key`.
+    pub fn from_chunk(data: &[u8], attach_key: &[u8]) -> Result<Attachment> {
+        let mut items = Items::new(data);
+
+        let id = try!(plain_string(&mut items));
+        let parent = try!(plain_string(&mut items));
+        let mime_type = try!(plain_string(&mut items));
+        let storage_key = try!(plain_string(&mut items));
+        let size = try!(plain_string(&mut items));
+        // Unlike the other encrypted items the name is base64-encoded
+        let filename =
+            try!(cipher::decrypt_field(attach_key,
+                                       try!(items.next_or_empty())));
+
+        let size =
+            match u64::from_str(&size) {
+                Ok(s) => s,
+                Err(_) => {
+                    let err = format!("Invalid attachment size '{}'", size);
+                    return Err(Error::BadProtocol(err));
+                }
+            };
+
+        Ok(Attachment {
+            id: id,
+            parent: parent,
+            mime_type: mime_type,
+            storage_key: storage_key,
+            size: size,
+            filename: String::from_utf8_lossy(&filename).into_owned(),
+        })
+    }
+
+    /// Return the ID of the account owning the `ATTA` chunk `data`
+    /// without decoding the rest of the chunk
+    pub fn parent_from_chunk(data: &[u8]) -> Result<String> {
+        let mut items = Items::new(data);
+
+        try!(items.skip_items(1));
+
+        plain_string(&mut items)
+    }
+}
+
+/// Shared folder. The accounts following a `SHAR` chunk in the blob
+/// belong to the share and are encrypted with its `sharing_key`.
+pub struct Share {
+    /// Unique share ID
+    pub id: String,
+    /// Share name, used as the top-level folder of its accounts
+    pub name: String,
+    /// `true` if the user can't modify the share's accounts
+    pub readonly: bool,
+    /// Key used to decrypt the share's accounts
+    pub sharing_key: SecureStorage,
+}
+
+impl Share {
+    /// Decode a `SHAR` chunk's payload `data`. The sharing key is
+    /// either encrypted with the user's crypto `key` or with their
+    /// RSA public key, in which case `private_key` is called to get
+    /// the DER private key returned by `Session::decrypt_private_key`.
+    pub fn from_chunk<F>(data: &[u8],
+                         key: &[u8],
+                         private_key: F) -> Result<Share>
+        where F: FnOnce() -> Result<SecureStorage> {
+        let mut items = Items::new(data);
+
+        let id = try!(plain_string(&mut items));
+        let rsa_key = try!(items.next_or_empty());
+        let name = try!(items.next_or_empty());
+        let readonly = try!(boolean(&mut items));
+        // give
+        try!(items.skip_items(1));
+        let aes_key = try!(items.next_or_empty());
+
+        // Both keys are hex-encoded once decrypted
+        let hex_key =
+            if !aes_key.is_empty() {
+                try!(cipher::decrypt_field(key, aes_key))
+            } else {
+                let ciphertext = try!(cipher::hex_decode(rsa_key));
+
+                try!(cipher::rsa_decrypt(&try!(private_key()), &ciphertext))
+            };
+
+        let sharing_key = try!(cipher::hex_decode(&hex_key));
+
+        if sharing_key.len() != 32 {
+            let err = format!("Invalid sharing key length ({})",
+                              sharing_key.len());
+            return Err(Error::BadProtocol(err));
+        }
+
+        let name = try!(cipher::decrypt_field(&sharing_key, name));
+
+        Ok(Share {
+            id: id,
+            name: String::from_utf8_lossy(&name).into_owned(),
+            readonly: readonly,
+            sharing_key: sharing_key,
+        })
+    }
+}
+
+impl Account {
+    /// Create a new, empty account named `fullname` (`group/name` or
+    /// just `name`). The account has the temporary ID "0" until it's
+    /// been added to the vault and the server assigned a real one.
+    pub fn new(fullname: &str) -> Account {
+        let mut account = Account {
+            id: "0".to_owned(),
+            name: String::new(),
+            group: String::new(),
+            fullname: String::new(),
+            url: String::new(),
+            username: String::new(),
+            password: SecureStorage::empty(),
+            note: SecureStorage::empty(),
+            last_touch: None,
+            last_modified: None,
+            favorite: false,
+            fields: Vec::new(),
+            share_id: None,
+            readonly: false,
+            attach_key: None,
+            attachments: Vec::new(),
+        };
+
+        account.set_fullname(fullname);
+
+        account
+    }
+
+    /// Rename the account. `fullname` is either `group/name` or just
+    /// `name`.
+    pub fn set_fullname(&mut self, fullname: &str) {
+        let (group, name) =
+            match fullname.rfind('/') {
+                Some(p) => (&fullname[..p], &fullname[p + 1..]),
+                None => ("", fullname),
+            };
+
+        self.name = name.to_owned();
+        self.group = group.to_owned();
+        self.fullname = fullname.to_owned();
+    }
+
+    /// Decode an `ACCT` chunk's payload `data`, decrypting the
+    /// encrypted fields with `key`.
+    pub fn from_chunk(data: &[u8], key: &[u8]) -> Result<Account> {
+        let mut items = Items::new(data);
+
+        // The field order is lifted from the C client
+        let id = try!(plain_string(&mut items));
+        let name = try!(crypt_string(&mut items, key));
+        let group = try!(crypt_string(&mut items, key));
+        let url = try!(hex_string(&mut items));
+        let note = try!(crypt_secure(&mut items, key));
+        let favorite = try!(boolean(&mut items));
+        // sharedfromaid
+        try!(items.skip_items(1));
+        let username = try!(crypt_string(&mut items, key));
+        let password = try!(crypt_secure(&mut items, key));
+        // pwprotect, genpw, sn
+        try!(items.skip_items(3));
+        let last_touch = try!(timestamp(&mut items));
+        // autologin, never_autofill, realm_data, fiid, custom_js,
+        // submit_id, captcha_id, urid, basic_auth, method, action,
+        // groupid, deleted
+        try!(items.skip_items(13));
+        let attach_key = try!(crypt_secure(&mut items, key));
+        // attachpresent, individualshare, notetype, noalert
+        try!(items.skip_items(4));
+        let last_modified = try!(timestamp(&mut items));
+
+        // A broken key only makes the attachments unavailable, it
+        // mustn't prevent the rest of the vault from loading
+        let attach_key =
+            if attach_key.is_empty() {
+                None
+            } else {
+                match decode_attach_key(&attach_key) {
+                    Ok(k) => Some(k),
+                    Err(e) => {
+                        warn!("Ignoring the attachment key of {} [id: {}]: \
+                               {}", name, id, e);
+                        None
+                    }
+                }
+            };
+
+        let fullname =
+            if group.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", group, name)
+            };
+
+        Ok(Account {
+            id: id,
+            name: name,
+            group: group,
+            fullname: fullname,
+            url: url,
+            username: username,
+            password: password,
+            note: note,
+            last_touch: last_touch,
+            last_modified: last_modified,
+            favorite: favorite,
+            fields: Vec::new(),
+            share_id: None,
+            readonly: false,
+            attach_key: attach_key,
+            attachments: Vec::new(),
+        })
+    }
+
+    /// Move the account into `share`: the share's name becomes the
+    /// top-level folder of the account.
+    pub fn set_share(&mut self, share: &Share) {
+        let fullname = format!("{}/{}", share.name, self.fullname);
+
+        self.set_fullname(&fullname);
+        self.share_id = Some(share.id.clone());
+        self.readonly = share.readonly;
+    }
+
+    /// Decode the `ATTA` chunks `chunks` belonging to this account and
+    /// add them to its `attachments`
+    pub fn add_attachments<T>(&mut self, chunks: &[T]) -> Result<()>
+        where T: AsRef<[u8]> {
+        let key =
+            match self.attach_key {
+                Some(ref k) => k,
+                None => {
+                    warn!("Ignoring the attachments of {} [id: {}]: \
+                           no attachment key", self.fullname, self.id);
+                    return Ok(());
+                }
+            };
+
+        // Skip the attachments we can't decode instead of failing the
+        // whole vault
+        for c in chunks {
+            match Attachment::from_chunk(c.as_ref(), key) {
+                Ok(a) => self.attachments.push(a),
+                Err(e) => warn!("Ignoring an attachment of {} [id: {}]: {}",
+                                self.fullname, self.id, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the custom form field called `name`, if any
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Return `true` if this entry is a group (folder) rather than
+    /// an actual account
+    pub fn is_group(&self) -> bool {
+        self.url == "http://group"
+    }
+
+    /// Return `true` if this entry is a secure note
+    pub fn is_secure_note(&self) -> bool {
+        self.url == "http://sn"
+    }
+
+    /// Parse the structured body of a secure note into its fields, in
+    /// order. The first field is always `NoteType`. Returns `None` if
+    /// the account is not a secure note or if its type is unknown
+    /// (for instance custom note types), in which case the raw `note`
+    /// should be used instead.
+    pub fn note_fields(&self)
+                       -> Result<Option<Vec<(String, SecureStorage)>>> {
+        if !self.is_secure_note() {
+            return Ok(None);
+        }
+
+        parse_note_fields(&self.note)
+    }
+}
+
+/// Secure note types with a known structure, lifted from the C
+/// client
+const NOTE_TYPES: &'static [&'static str] = &[
+    "Amex",
+    "Bank Account",
+    "Credit Card",
+    "Database",
+    "Driver's License",
+    "Email Account",
+    "Health Insurance",
+    "Instant Messenger",
+    "Insurance",
+    "Mastercard",
+    "Membership",
+    "Passport",
+    "Server",
+    "Software License",
+    "SSH Key",
+    "Social Security",
+    "VISA",
+    "Wi-Fi Password",
+];
+
+/// Parse a secure note body made of `Key:value` lines. The `Notes`
+/// field is always last and spans until the end of the body.
+fn parse_note_fields(note: &[u8])
+                     -> Result<Option<Vec<(String, SecureStorage)>>> {
+    let mut fields = Vec::new();
+
+    let mut pos = 0;
+
+    while pos < note.len() {
+        let end =
+            match note[pos..].iter().position(|&b| b == b'\n') {
+                Some(e) => pos + e,
+                None => note.len(),
+            };
+
+        let line = &note[pos..end];
+
+        if line.is_empty() {
+            pos = end + 1;
+            continue;
+        }
+
+        let sep =
+            match line.iter().position(|&b| b == b':') {
+                Some(p) => p,
+                // Not a structured note
+                None => return Ok(None),
+            };
+
+        let key = String::from_utf8_lossy(&line[..sep]).into_owned();
+
+        if fields.is_empty() {
+            let note_type = String::from_utf8_lossy(&line[sep + 1..]);
+
+            if key != "NoteType" || !NOTE_TYPES.contains(&&*note_type) {
+                return Ok(None);
+            }
+        }
+
+        if key == "Notes" {
+            // The notes can span multiple lines and are always last
+            let value = &note[pos + sep + 1..];
+
+            fields.push((key, try!(SecureStorage::from_slice(value))));
+
+            break;
+        }
+
+        let value = try!(SecureStorage::from_slice(&line[sep + 1..]));
+
+        fields.push((key, value));
+
+        pos = end + 1;
+    }
+
+    if fields.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(fields))
+    }
+}
+
+/// Only the text-like fields are encrypted
+fn is_encrypted_type(field_type: &str) -> bool {
+    match field_type {
+        "email" | "tel" | "text" | "password" | "textarea" => true,
+        _ => false,
+    }
+}
+
+/// Read a plaintext string field
+fn plain_string(items: &mut Items) -> Result<String> {
+    let item = try!(items.next_or_empty());
+
+    Ok(String::from_utf8_lossy(item).into_owned())
+}
+
+/// Read a timestamp in seconds since the epoch. The server sends an
+/// empty string or 0 when the time is unknown.
+fn timestamp(items: &mut Items) -> Result<Option<SystemTime>> {
+    let item = try!(plain_string(items));
+
+    Ok(parse_timestamp(&item))
+}
+
+fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    match u64::from_str(s.trim()) {
+        Ok(0) => None,
+        Ok(t) => Some(UNIX_EPOCH + Duration::from_secs(t)),
+        Err(_) => {
+            if !s.is_empty() {
+                warn!("Ignoring invalid timestamp '{}'", s);
+            }
+
+            None
+        }
+    }
+}
+
+/// Read and decrypt a secret field
+fn crypt_secure(items: &mut Items, key: &[u8]) -> Result<SecureStorage> {
+    let item = try!(items.next_or_empty());
+
+    cipher::decrypt_raw(key, item)
+}
+
+/// Read and decrypt a string field. Don't use that for secrets since
+/// the result is not stored in a `SecureStorage`.
+fn crypt_string(items: &mut Items, key: &[u8]) -> Result<String> {
+    let plaintext = try!(crypt_secure(items, key));
+
+    Ok(String::from_utf8_lossy(&plaintext).into_owned())
+}
+
+/// Decode the decrypted attachment key of an account, it's
+/// hex-encoded
+fn decode_attach_key(attach_key: &[u8]) -> Result<SecureStorage> {
+    let k = try!(cipher::hex_decode(attach_key));
+
+    if k.len() != 32 {
+        let err = format!("Invalid attachment key length ({})", k.len());
+        return Err(Error::BadProtocol(err));
+    }
+
+    Ok(k)
+}
+
+/// Read a hex-encoded string field
+fn hex_string(items: &mut Items) -> Result<String> {
+    let item = try!(items.next_or_empty());
+
+    if item.len() % 2 != 0 {
+        return Err(Error::BadProtocol("Odd hex string length".to_owned()));
+    }
+
+    let mut decoded = Vec::with_capacity(item.len() / 2);
+
+    for pair in item.chunks(2) {
+        let hi = try!(hex_digit(pair[0]));
+        let lo = try!(hex_digit(pair[1]));
+
+        decoded.push((hi << 4) | lo);
+    }
+
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn hex_digit(c: u8) -> Result<u8> {
+    match c {
+        b'0'...b'9' => Ok(c - b'0'),
+        b'a'...b'f' => Ok(c - b'a' + 10),
+        b'A'...b'F' => Ok(c - b'A' + 10),
+        _ => Err(Error::BadProtocol("Invalid hex string".to_owned())),
+    }
+}
+
+/// Read a boolean field ("1" for true, anything else for false)
+fn boolean(items: &mut Items) -> Result<bool> {
+    let item = try!(items.next_or_empty());
+
+    Ok(item == b"1")
+}
+
+#[test]
+fn test_parse_note_fields() {
+    let note = b"NoteType:Server\n\
+                 Hostname:example.com\n\
+                 Username:root\n\
+                 Password:hunter2\n\
+                 Notes:first line\n\
+                 Key:value in the notes";
+
+    let fields = parse_note_fields(note).unwrap().unwrap();
+
+    let expected: &[(&str, &[u8])] = &[
+        ("NoteType", b"Server"),
+        ("Hostname", b"example.com"),
+        ("Username", b"root"),
+        ("Password", b"hunter2"),
+        ("Notes", b"first line\nKey:value in the notes"),
+    ];
+
+    assert_eq!(fields.len(), expected.len());
+
+    for (&(ref k, ref v), &(ek, ev)) in fields.iter().zip(expected) {
+        assert_eq!(k, ek);
+        assert!(v.ct_eq(ev));
+    }
+
+    // Plain text, unknown and custom note types
+    let raw: &[&[u8]] = &[
+        b"",
+        b"just some text",
+        b"NoteType:Mystery\nFoo:bar",
+        b"NoteType:Custom_1234\nFoo:bar",
+        b"Hostname:example.com\nNoteType:Server",
+    ];
+
+    for &note in raw {
+        assert!(parse_note_fields(note).unwrap().is_none());
+    }
+}
+
+#[test]
+fn test_parse_timestamp() {
+    assert_eq!(parse_timestamp("1500000000"),
+               Some(UNIX_EPOCH + Duration::from_secs(1500000000)));
+    assert_eq!(parse_timestamp("0"), None);
+    assert_eq!(parse_timestamp(""), None);
+    assert_eq!(parse_timestamp("yesterday"), None);
+    assert_eq!(parse_timestamp("-1"), None);
+}
+
+#[test]
+fn test_field_from_chunk() {
+    let key: Vec<u8> = (0..32).collect();
+
+    // Text fields are encrypted ("lpass" in ECB mode)
+    let text = b"\x00\x00\x00\x05login\
+                 \x00\x00\x00\x04text\
+                 \x00\x00\x00\x10\
+                 \x90\xbc\x4e\xfe\xc4\x3c\xc3\x92\
+                 \x6a\xe9\x9b\x39\xa4\x1a\xbc\x3b\
+                 \x00\x00\x00\x010";
+
+    let field = Field::from_chunk(text, &key).unwrap();
+
+    assert_eq!(field.name, "login");
+    assert_eq!(field.field_type, "text");
+    assert!(field.value.ct_eq(b"lpass"));
+    assert!(!field.checked);
+
+    // Checkbox values are in plaintext
+    let checkbox = b"\x00\x00\x00\x08remember\
+                     \x00\x00\x00\x08checkbox\
+                     \x00\x00\x00\x02on\
+                     \x00\x00\x00\x011";
+
+    let field = Field::from_chunk(checkbox, &key).unwrap();
+
+    assert_eq!(field.name, "remember");
+    assert_eq!(field.field_type, "checkbox");
+    assert!(field.value.ct_eq(b"on"));
+    assert!(field.checked);
+}
+
+#[test]
+fn test_share_from_chunk() {
+    let key: Vec<u8> = (0..32).collect();
+    let sharing_key: Vec<u8> = (32..64).collect();
+
+    let hex_key: String =
+        sharing_key.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let items: &[&[u8]] = &[
+        b"42",
+        // RSA-encrypted key, unused when the AES one is present
+        b"",
+        &cipher::encrypt_field(&sharing_key, b"Shared-Team").unwrap(),
+        b"1",
+        b"0",
+        &cipher::encrypt_field(&key, hex_key.as_bytes()).unwrap(),
+    ];
+
+    let mut chunk = Vec::new();
+
+    for item in items {
+        let len = item.len();
+
+        chunk.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8,
+                                  (len >> 8) as u8, len as u8]);
+        chunk.extend_from_slice(item);
+    }
+
+    let share = Share::from_chunk(&chunk, &key, || {
+        panic!("The private key shouldn't be needed")
+    }).unwrap();
+
+    assert_eq!(share.id, "42");
+    assert_eq!(share.name, "Shared-Team");
+    assert!(share.readonly);
+    assert!(share.sharing_key.ct_eq(&sharing_key));
+
+    let mut account = Account::new("web/mail");
+
+    account.set_share(&share);
+
+    assert_eq!(account.fullname, "Shared-Team/web/mail");
+    assert_eq!(account.group, "Shared-Team/web");
+    assert_eq!(account.share_id, Some("42".to_owned()));
+    assert!(account.readonly);
+
+    // Without the AES key the RSA private key is required
+    let no_key = Share::from_chunk(&chunk[..chunk.len() - 4 - items[5].len()],
+                                   &key,
+                                   || Err(Error::Unsupported("".to_owned())));
+
+    assert!(no_key.is_err());
+}
+
+#[test]
+fn test_attachment_from_chunk() {
+    let key: Vec<u8> = (0..32).collect();
+
+    let filename = cipher::encrypt_field(&key, b"scan.pdf").unwrap();
+
+    let mut data = Vec::new();
+
+    {
+        let items: &[&[u8]] =
+            &[b"7", b"1234", b"application/pdf", b"0a1b2c", b"4096", &filename];
+
+        for item in items {
+            let len = item.len() as u32;
+
+            data.extend_from_slice(&[(len >> 24) as u8,
+                                     (len >> 16) as u8,
+                                     (len >> 8) as u8,
+                                     len as u8]);
+            data.extend_from_slice(item);
+        }
+    }
+
+    assert_eq!(Attachment::parent_from_chunk(&data).unwrap(), "1234");
+
+    let attachment = Attachment::from_chunk(&data, &key).unwrap();
+
+    assert_eq!(attachment.id, "7");
+    assert_eq!(attachment.parent, "1234");
+    assert_eq!(attachment.mime_type, "application/pdf");
+    assert_eq!(attachment.storage_key, "0a1b2c");
+    assert_eq!(attachment.size, 4096);
+    assert_eq!(attachment.filename, "scan.pdf");
+
+    // Broken attachments are skipped
+    let mut account = Account::new("scans");
+
+    account.attach_key = Some(SecureStorage::from_slice(&key).unwrap());
+    account.add_attachments(&[&data[..], &data[..data.len() - 4]]).unwrap();
+
+    assert_eq!(account.attachments.len(), 1);
+
+    assert!(decode_attach_key(b"00112233").is_err());
+    assert!(decode_attach_key(b"zz").is_err());
+}
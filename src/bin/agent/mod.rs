@@ -0,0 +1,284 @@
+/// Decryption key agent
+///
+/// The agent is a background process holding the crypto key in
+/// locked memory. It listens on a unix socket in the configuration
+/// directory and hands the key over to subsequent invocations of the
+/// CLI so that the user doesn't have to type the master password for
+/// every command.
+///
+/// The protocol is trivial: the client sends a single command byte,
+/// `k` to receive the key or `q` to stop the agent. `v` checks the
+/// pairing token of a browser extension (see `verify_pairing`)
+/// without handing the key over.
+
+use lpass::{Result, SecureStorage};
+use lpass::config;
+use lpass::kdf;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use libc;
+
+/// Name of the agent socket in the configuration directory
+const SOCKET: &'static str = "agent.sock";
+
+/// Default key timeout in seconds
+const DEFAULT_TIMEOUT: u32 = 60 * 60;
+
+/// Length of the crypto key
+const KEY_LEN: usize = 32;
+
+/// Start a new agent holding `key`, replacing the previous one if
+/// any. The agent exits after `LPASS_AGENT_TIMEOUT` seconds (0 means
+/// never).
+pub fn start(key: &SecureStorage) -> Result<()> {
+    kill();
+
+    let timeout =
+        match env::var("LPASS_AGENT_TIMEOUT") {
+            Ok(t) => {
+                match u32::from_str(&t) {
+                    Ok(t) => t,
+                    Err(_) => {
+                        warn!("Invalid LPASS_AGENT_TIMEOUT '{}', \
+                               using default", t);
+                        DEFAULT_TIMEOUT
+                    }
+                }
+            }
+            Err(_) => DEFAULT_TIMEOUT,
+        };
+
+    let path = try!(config::path(SOCKET));
+
+    // Remove any stale socket
+    let _ = fs::remove_file(&path);
+
+    // Bind before forking so that the socket is ready by the time we
+    // return
+    let listener = try!(UnixListener::bind(&path));
+
+    try!(fs::set_permissions(&path, fs::Permissions::from_mode(0o600)));
+
+    let pid = unsafe { libc::fork() };
+
+    match pid {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            // Child process
+            let status =
+                match daemonize(timeout) {
+                    Ok(_) => {
+                        // Memory locks are not inherited through
+                        // `fork`, make a new locked copy.
+                        match SecureStorage::from_slice(key) {
+                            Ok(key) => {
+                                serve(listener, &key);
+                                0
+                            }
+                            Err(_) => 1,
+                        }
+                    }
+                    Err(_) => 1,
+                };
+
+            let _ = fs::remove_file(&path);
+
+            process::exit(status);
+        }
+        _ => {
+            debug!("Started agent with PID {}", pid);
+            Ok(())
+        }
+    }
+}
+
+/// Retrieve the crypto key from the agent. Returns `None` if the
+/// agent is not running.
+pub fn get_key() -> Option<SecureStorage> {
+    match query_key() {
+        Ok(k) => Some(k),
+        Err(e) => {
+            debug!("Couldn't get the key from the agent: {}", e);
+            None
+        }
+    }
+}
+
+/// Stop the agent if it's running
+pub fn kill() {
+    if let Ok(path) = config::path(SOCKET) {
+        if let Ok(mut stream) = UnixStream::connect(&path) {
+            let _ = stream.write_all(b"q");
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+}
+
+/// Ask the agent whether `token` is the pairing token of the browser
+/// extension `extension` (see `kdf::pairing_token`). Returns `false`
+/// if the agent is not running.
+pub fn verify_pairing(extension: &str, token: &[u8]) -> bool {
+    match query_pairing(extension, token) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Couldn't verify the pairing with the agent: {}", e);
+            false
+        }
+    }
+}
+
+fn query_pairing(extension: &str, token: &[u8]) -> Result<bool> {
+    if extension.len() > 255 || token.len() != KEY_LEN {
+        return Ok(false);
+    }
+
+    let path = try!(config::path(SOCKET));
+
+    let mut stream = try!(UnixStream::connect(&path));
+
+    try!(stream.write_all(b"v"));
+    try!(stream.write_all(&[extension.len() as u8]));
+    try!(stream.write_all(extension.as_bytes()));
+    try!(stream.write_all(token));
+
+    let mut answer = [0; 1];
+
+    try!(stream.read_exact(&mut answer));
+
+    Ok(answer[0] == b'y')
+}
+
+fn query_key() -> Result<SecureStorage> {
+    let path = try!(config::path(SOCKET));
+
+    let mut stream = try!(UnixStream::connect(&path));
+
+    try!(stream.write_all(b"k"));
+
+    let mut key = try!(SecureStorage::from_vec(vec![0; KEY_LEN]));
+
+    try!(stream.read_exact(&mut key));
+
+    Ok(key)
+}
+
+/// Detach from the terminal and setup the timeout in seconds (0 for
+/// no timeout)
+pub fn daemonize(timeout: u32) -> Result<()> {
+    // Make sure we don't keep the caller's stdio open, otherwise a
+    // pipe reading our output would never see the end of file.
+    let null = try!(fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open("/dev/null"));
+
+    unsafe {
+        libc::setsid();
+
+        for fd in 0..3 {
+            libc::dup2(null.as_raw_fd(), fd);
+        }
+
+        // The default action for SIGALRM is to terminate the process
+        if timeout > 0 {
+            libc::alarm(timeout);
+        }
+    }
+
+    Ok(())
+}
+
+fn serve(listener: UnixListener, key: &SecureStorage) {
+    for stream in listener.incoming() {
+        let mut stream =
+            match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+
+        if !peer_is_trusted(&stream) {
+            continue;
+        }
+
+        let mut command = [0; 1];
+
+        if stream.read_exact(&mut command).is_err() {
+            continue;
+        }
+
+        match command[0] {
+            b'k' => { let _ = stream.write_all(key); }
+            b'v' => {
+                let answer =
+                    match check_pairing(&mut stream, key) {
+                        Ok(true) => b"y",
+                        _ => b"n",
+                    };
+
+                let _ = stream.write_all(answer);
+            }
+            b'q' => return,
+            _ => (),
+        }
+    }
+}
+
+/// Read an extension ID and a pairing token from `stream` and check
+/// that the token matches `key`
+fn check_pairing(stream: &mut UnixStream,
+                 key: &SecureStorage) -> Result<bool> {
+    // Don't let a stalled client block the agent
+    try!(stream.set_read_timeout(Some(Duration::from_secs(1))));
+
+    let mut len = [0; 1];
+
+    try!(stream.read_exact(&mut len));
+
+    let mut extension = vec![0; len[0] as usize];
+
+    try!(stream.read_exact(&mut extension));
+
+    let mut token = try!(SecureStorage::from_vec(vec![0; KEY_LEN]));
+
+    try!(stream.read_exact(&mut token));
+
+    let extension = String::from_utf8_lossy(&extension);
+    let expected = try!(kdf::pairing_token(key, &extension));
+
+    Ok(expected.ct_eq(&token))
+}
+
+/// Make sure the process on the other end of `stream` belongs to the
+/// same user
+#[cfg(target_os = "linux")]
+fn peer_is_trusted(stream: &UnixStream) -> bool {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = ::std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_PEERCRED,
+                         &mut cred as *mut _ as *mut _,
+                         &mut len)
+    };
+
+    ret == 0 && cred.uid == unsafe { libc::getuid() }
+}
+
+/// On other platforms we rely on the socket permissions
+#[cfg(not(target_os = "linux"))]
+fn peer_is_trusted(_: &UnixStream) -> bool {
+    true
+}
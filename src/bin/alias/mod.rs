@@ -0,0 +1,187 @@
+/// Command aliases
+///
+/// Aliases are defined in the `aliases` file of the configuration
+/// directory, one per line:
+///
+/// ```text
+/// # Comment
+/// l = ls -l
+/// pw = show --password
+/// ```
+///
+/// The alias is replaced by its expansion before the command is
+/// dispatched, the remaining arguments are appended. Aliases can
+/// reference other aliases but they can't shadow a builtin command.
+
+use lpass::{Result, Error};
+use lpass::config;
+
+/// Maximum number of alias expansions for a single command, to catch
+/// recursive aliases
+const MAX_DEPTH: usize = 16;
+
+/// Expand the alias used as the command in `args` (`args[1]`) using
+/// the aliases defined in the configuration directory. `is_builtin`
+/// returns `true` for the names of the builtin commands.
+pub fn expand<F>(args: &[String], is_builtin: F) -> Result<Vec<String>>
+    where F: Fn(&str) -> bool {
+
+    // The aliases aren't secret, the file only has to be protected
+    // against modifications by other users. A bad file mustn't
+    // prevent the builtin commands from running.
+    let aliases =
+        match config::read_public("aliases") {
+            Ok(Some(data)) => parse(&String::from_utf8_lossy(&data)),
+            Ok(None) => return Ok(args.to_vec()),
+            Err(e) => {
+                warn!("Ignoring the aliases file: {}", e);
+                return Ok(args.to_vec());
+            }
+        };
+
+    for &(ref name, _) in &aliases {
+        if is_builtin(name) {
+            warn!("Alias '{}' is ignored, it has the name of a builtin \
+                   command", name);
+        }
+    }
+
+    expand_with(args, &aliases, is_builtin)
+}
+
+/// Parse the contents of the aliases file into a list of (name,
+/// expansion) pairs. Invalid lines are ignored with a warning.
+fn parse(data: &str) -> Vec<(String, Vec<String>)> {
+    let mut aliases = Vec::new();
+
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, expansion) =
+            match line.find('=') {
+                Some(p) => (line[..p].trim(), &line[p + 1..]),
+                None => {
+                    warn!("Ignoring invalid alias on line {}", i + 1);
+                    continue;
+                }
+            };
+
+        let expansion: Vec<_> =
+            expansion.split_whitespace().map(|s| s.to_owned()).collect();
+
+        if name.is_empty() || expansion.is_empty() {
+            warn!("Ignoring invalid alias on line {}", i + 1);
+            continue;
+        }
+
+        aliases.push((name.to_owned(), expansion));
+    }
+
+    aliases
+}
+
+fn expand_with<F>(args: &[String],
+                  aliases: &[(String, Vec<String>)],
+                  is_builtin: F) -> Result<Vec<String>>
+    where F: Fn(&str) -> bool {
+
+    let mut args = args.to_vec();
+
+    for _ in 0..MAX_DEPTH {
+        if args.len() < 2 || is_builtin(&args[1]) {
+            return Ok(args);
+        }
+
+        let expansion =
+            match aliases.iter().find(|&&(ref n, _)| *n == args[1]) {
+                Some(&(_, ref e)) => e,
+                None => return Ok(args),
+            };
+
+        debug!("Expanding alias '{}' to '{}'", args[1], expansion.join(" "));
+
+        let rest = args.split_off(2);
+
+        args.truncate(1);
+        args.extend(expansion.iter().cloned());
+        args.extend(rest);
+    }
+
+    println!("Alias '{}' is recursive", args[1]);
+
+    Err(Error::BadUsage)
+}
+
+#[test]
+fn test_expand() {
+    let aliases = parse("# My aliases\n\
+                         l = ls -l\n\
+                         \n\
+                         ll=l\n\
+                         ls = show\n\
+                         loop = loop2 foo\n\
+                         loop2 = loop\n\
+                         bogus\n");
+
+    assert_eq!(aliases.len(), 5);
+
+    let is_builtin = |c: &str| c == "ls" || c == "show";
+
+    let args = |a: &[&str]| -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
+    };
+
+    assert_eq!(expand_with(&args(&["lpass", "l", "web"]),
+                           &aliases, is_builtin).unwrap(),
+               args(&["lpass", "ls", "-l", "web"]));
+
+    assert_eq!(expand_with(&args(&["lpass", "ll"]),
+                           &aliases, is_builtin).unwrap(),
+               args(&["lpass", "ls", "-l"]));
+
+    // Builtins can't be shadowed
+    assert_eq!(expand_with(&args(&["lpass", "ls"]),
+                           &aliases, is_builtin).unwrap(),
+               args(&["lpass", "ls"]));
+
+    assert_eq!(expand_with(&args(&["lpass", "rm", "foo"]),
+                           &aliases, is_builtin).unwrap(),
+               args(&["lpass", "rm", "foo"]));
+
+    assert!(expand_with(&args(&["lpass", "loop"]),
+                        &aliases, is_builtin).is_err());
+}
+
+#[test]
+fn test_aliases_file() {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = ::TestHome::new("aliases");
+
+    fs::create_dir_all(&home.path).unwrap();
+
+    let path = home.path.join("aliases");
+
+    let args = vec!["lpass".to_owned(), "l".to_owned()];
+    let is_builtin = |c: &str| c == "ls";
+
+    // Created by hand with the default umask
+    fs::File::create(&path).unwrap().write_all(b"l = ls -l\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let expanded = expand(&args, &is_builtin);
+
+    // Writable by other users, ignored but the command still runs
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+    let ignored = expand(&args, &is_builtin);
+
+    assert_eq!(expanded.unwrap(), ["lpass", "ls", "-l"]);
+    assert_eq!(ignored.unwrap(), args);
+}
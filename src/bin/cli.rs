@@ -4,43 +4,109 @@ extern crate libc;
 extern crate env_logger;
 extern crate lpass;
 extern crate getopts;
+#[cfg(feature = "regex")]
+extern crate regex;
 
 use getopts::{Options, Matches};
-use lpass::{Result, Error};
+use lpass::{Result, Error, SyncMode};
+use log::LogLevelFilter;
 
 use terminal::{color, Color};
 
 mod terminal;
 mod commands;
 mod password;
+mod json;
+mod csv;
+mod agent;
+mod alias;
+mod environment;
+mod clipboard;
 
 fn main() {
     // Do not remove this umask. Always keep at top.
-    unsafe {
-        // Set the file mode creation mask and return the previous
-        // value. Can't fail.
-        libc::umask(0o077);
+    lpass::platform::restrict_file_creation_mask();
+
+    disable_core_dumps();
+
+    let mut args: Vec<_> = std::env::args().collect();
+
+    // Helper process started by `clipboard::copy`
+    if args.len() > 1 && args[1] == clipboard::CLEAR_COMMAND {
+        std::process::exit(clipboard::clear_helper(&args[2..]));
     }
 
-    env_logger::init().unwrap();
+    // The global options are parsed before anything else so that the
+    // log level applies to everything. The color mode is handled
+    // here so that it applies to everything including the help
+    let global = take_global_options(&mut args);
+
+    let log_level =
+        match global {
+            Ok(ref g) => g.log_level(),
+            Err(_) => DEFAULT_LOG_LEVEL,
+        };
+
+    init_logger(log_level);
 
     // Default to have colored output if stdout is a terminal
     terminal::set_color_mode(terminal::ColorMode::Auto);
 
-    // TODO: load_saved_environment
+    // Let Ctrl-C abort stuck network requests cleanly
+    terminal::handle_interrupts();
 
-    let args: Vec<_> = std::env::args().collect();
+    if let Err(e) = environment::load() {
+        println!("{}Warning{}: couldn't load the saved environment: {}",
+                 color(Color::FgYellow),
+                 color(Color::Reset),
+                 e);
+    }
+
+    // Escape hatch for containers with a low RLIMIT_MEMLOCK, see
+    // `lpass::set_allow_unlocked_memory`
+    let allow_unlocked =
+        std::env::var_os("LPASS_ALLOW_UNLOCKED_MEM")
+        .map(|v| !v.is_empty())
+        .unwrap_or(false);
+
+    lpass::set_allow_unlocked_memory(allow_unlocked);
 
     let res =
-        if args.len() >= 2 && args[1].as_bytes()[0] != b'-' {
-            process_command(&args)
-        } else {
-            global_options(&args)
+        match global {
+            Ok(global) => {
+                if let Some(mode) = global.color {
+                    terminal::set_color_mode(mode);
+                }
+
+                let assume_yes =
+                    std::env::var_os("LPASS_ASSUME_YES")
+                    .map(|v| !v.is_empty())
+                    .unwrap_or(false);
+
+                if global.assume_yes || assume_yes {
+                    terminal::set_assume_yes(true);
+                }
+
+                if args.len() >= 2 && args[1].as_bytes()[0] != b'-' {
+                    process_command(&args)
+                } else {
+                    global_options(&args)
+                }
+            }
+            Err(e) => Err(e),
         };
 
     let exit_code =
         match res {
             Ok(_) => 0,
+            Err(Error::SessionExpired) => {
+                println!("{}Your session has expired{}, please run \
+                          `lpass login` again",
+                         color(Color::FgRed),
+                         color(Color::Reset));
+
+                1
+            }
             Err(e) => {
                 println!("{}Command failed{}: {}",
                          color(Color::FgRed),
@@ -54,6 +120,44 @@ fn main() {
     std::process::exit(exit_code);
 }
 
+/// Make the process non-dumpable so that the secrets it holds can't
+/// end up in a core dump. This also prevents other processes of the
+/// same user from attaching to it with ptrace.
+#[cfg(target_os = "linux")]
+fn disable_core_dumps() {
+    // Not exported by our version of libc
+    const PR_SET_DUMPABLE: libc::c_int = 4;
+
+    let ret = unsafe { libc::prctl(PR_SET_DUMPABLE, 0) };
+
+    if ret < 0 {
+        // Can't use the logger, it's not initialized yet
+        println!("Warning: couldn't disable the core dumps: {}",
+                 std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_core_dumps() {
+}
+
+/// Log level used when neither `-q` nor `-v` is given
+const DEFAULT_LOG_LEVEL: LogLevelFilter = LogLevelFilter::Error;
+
+/// Install the logger with the given default `level`. The `RUST_LOG`
+/// environment variable, if set, takes precedence.
+fn init_logger(level: LogLevelFilter) {
+    let mut builder = env_logger::LogBuilder::new();
+
+    builder.filter(None, level);
+
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+
+    builder.init().unwrap();
+}
+
 fn version() {
     println!("LPass-rs CLI v{}", lpass::VERSION);
 }
@@ -62,7 +166,7 @@ fn help(exe: &str) {
     println!("Usage:");
     println!("  {} --help|-h", exe);
     println!("  {} --version|-v", exe);
-    println!("  {} COMMAND [OPTION]", exe);
+    println!("  {} [-q|-v...] COMMAND [OPTION]", exe);
     println!("");
     println!("Commands:");
     println!("");
@@ -82,11 +186,14 @@ fn command_help(exe: &str, command: &Command) {
 }
 
 fn process_command(args: &[String]) -> Result<()> {
+    let is_builtin = |name: &str| COMMANDS.iter().any(|c| c.name == name);
+
+    let args = try!(alias::expand(args, is_builtin));
+
     let exe = &args[0];
     let command = &args[1];
     let options = &args[2..];
 
-    // TODO: expand_aliases
     for c in &COMMANDS {
         if c.name == command {
             let res = run_command(c, options);
@@ -109,19 +216,25 @@ fn process_command(args: &[String]) -> Result<()> {
 fn run_command(command: &Command, options: &[String]) -> Result<()> {
     match command.options().parse(options) {
         Ok(matches) => {
+            // Kept for backward compatibility, the color mode can
+            // also be set before the command
             if let Some(mode) = matches.opt_str("C") {
-                let cm =
+                terminal::set_color_mode(try!(color_mode(&mode)));
+            }
+
+            if let Some(mode) = matches.opt_str("sync") {
+                let sm =
                     match mode.as_str() {
-                        "auto" => terminal::ColorMode::Auto,
-                        "never" => terminal::ColorMode::Never,
-                        "always" => terminal::ColorMode::Always,
+                        "auto" => SyncMode::Auto,
+                        "now" => SyncMode::Now,
+                        "no" => SyncMode::No,
                         _ => {
-                            println!("Invalid color mode '{}'", mode);
+                            println!("Invalid sync mode '{}'", mode);
                             return Err(Error::BadUsage)
                         }
                     };
 
-                terminal::set_color_mode(cm);
+                commands::set_sync_mode(sm);
             }
 
             // Execute the command
@@ -134,6 +247,125 @@ fn run_command(command: &Command, options: &[String]) -> Result<()> {
     }
 }
 
+/// Parse a `--color` argument
+fn color_mode(mode: &str) -> Result<terminal::ColorMode> {
+    match mode {
+        "auto" => Ok(terminal::ColorMode::Auto),
+        "never" => Ok(terminal::ColorMode::Never),
+        "always" => Ok(terminal::ColorMode::Always),
+        _ => {
+            println!("Invalid color mode '{}'", mode);
+            Err(Error::BadUsage)
+        }
+    }
+}
+
+/// Options placed before the command
+#[derive(Debug, PartialEq, Eq)]
+struct GlobalOptions {
+    /// Last color mode requested with `-C`/`--color`, if any
+    color: Option<terminal::ColorMode>,
+    /// `-y`/`--yes`: answer yes to all the questions
+    assume_yes: bool,
+    /// `-q`/`--quiet`: only log errors
+    quiet: bool,
+    /// Number of `-v`/`--verbose`
+    verbose: usize,
+}
+
+impl GlobalOptions {
+    /// Log level requested by `-q` or `-v`
+    fn log_level(&self) -> LogLevelFilter {
+        if self.quiet {
+            return LogLevelFilter::Error;
+        }
+
+        match self.verbose {
+            0 => DEFAULT_LOG_LEVEL,
+            1 => LogLevelFilter::Debug,
+            _ => LogLevelFilter::Trace,
+        }
+    }
+}
+
+/// Remove the global options placed before the command from `args`
+/// and return them. The other options (`--help`...) are left in
+/// place.
+fn take_global_options(args: &mut Vec<String>) -> Result<GlobalOptions> {
+    let mut global = GlobalOptions {
+        color: None,
+        assume_yes: false,
+        quiet: false,
+        verbose: 0,
+    };
+
+    // Set if `-v` is used, it's also the short for `--version`
+    let mut short_verbose = false;
+
+    let mut i = 1;
+
+    // Stop at the command name
+    while i < args.len() && args[i].starts_with('-') {
+        if args[i] == "-y" || args[i] == "--yes" {
+            global.assume_yes = true;
+            args.remove(i);
+            continue;
+        }
+
+        if args[i] == "-q" || args[i] == "--quiet" {
+            global.quiet = true;
+            args.remove(i);
+            continue;
+        }
+
+        if args[i] == "--verbose" {
+            global.verbose += 1;
+            args.remove(i);
+            continue;
+        }
+
+        // `-v`, `-vv`...
+        if args[i].len() >= 2 && args[i][1..].bytes().all(|b| b == b'v') {
+            global.verbose += args[i].len() - 1;
+            short_verbose = true;
+            args.remove(i);
+            continue;
+        }
+
+        let value =
+            if args[i] == "-C" || args[i] == "--color" {
+                if i + 1 == args.len() {
+                    println!("Missing color mode");
+                    return Err(Error::BadUsage);
+                }
+
+                Some(args.remove(i + 1))
+            } else if args[i].starts_with("--color=") {
+                Some(args[i]["--color=".len()..].to_owned())
+            } else if args[i].starts_with("-C") {
+                Some(args[i][2..].to_owned())
+            } else {
+                None
+            };
+
+        match value {
+            Some(v) => {
+                global.color = Some(try!(color_mode(&v)));
+                args.remove(i);
+            }
+            None => i += 1,
+        }
+    }
+
+    // Without a command `lpass -v` still displays the version
+    if short_verbose && args.len() == 1 {
+        args.push("-v".to_owned());
+        global.verbose = 0;
+    }
+
+    Ok(global)
+}
+
 fn global_options(args: &[String]) -> Result<()> {
     let exe = &args[0];
 
@@ -141,6 +373,17 @@ fn global_options(args: &[String]) -> Result<()> {
 
     opts.optflag("v", "version", "display version information and quit");
     opts.optflag("h", "help", "display help message and quit");
+    // Only there for the usage, they're handled by
+    // `take_global_options`
+    opts.optopt("C", "color", "terminal color mode", "auto|never|always");
+    opts.optflag("y", "yes",
+                 "answer yes to all the confirmations (can also be set \
+                  with LPASS_ASSUME_YES=1)");
+    opts.optflag("q", "quiet", "only log errors");
+    opts.optflagmulti("", "verbose",
+                      "log debug messages, twice for trace messages \
+                       (-v, -vv before a command). RUST_LOG takes \
+                       precedence");
 
     match opts.parse(&args[1..]) {
         Ok(matches) => {
@@ -213,11 +456,145 @@ impl Command {
         opts.optopt("C", "color",
                     "terminal color mode",
                     "auto|never|always");
+        opts.optopt("", "sync",
+                    "blob synchronization mode (default: auto)",
+                    "auto|now|no");
 
         opts
     }
 }
 
-static COMMANDS: [Command; 1] = [
+static COMMANDS: [Command; 18] = [
     commands::login::LOGIN_COMMAND,
+    commands::logout::LOGOUT_COMMAND,
+    commands::status::STATUS_COMMAND,
+    commands::ls::LS_COMMAND,
+    commands::search::SEARCH_COMMAND,
+    commands::show::SHOW_COMMAND,
+    commands::generate::GENERATE_COMMAND,
+    commands::add::ADD_COMMAND,
+    commands::rm::RM_COMMAND,
+    commands::mv::MV_COMMAND,
+    commands::duplicate::DUPLICATE_COMMAND,
+    commands::edit::EDIT_COMMAND,
+    commands::sync::SYNC_COMMAND,
+    commands::export::EXPORT_COMMAND,
+    commands::import::IMPORT_COMMAND,
+    commands::attach::ATTACH_COMMAND,
+    commands::passwd::PASSWD_COMMAND,
+    commands::nativemsg::NATIVEMSG_COMMAND,
 ];
+
+/// Serializes the tests changing `$LPASS_HOME`, which is process-wide
+#[cfg(test)]
+static TEST_HOME_LOCK: ::std::sync::Mutex<()> = ::std::sync::Mutex::new(());
+
+/// Point `$LPASS_HOME` to an empty temporary directory until dropped,
+/// then remove it and restore the previous value.
+#[cfg(test)]
+struct TestHome {
+    path: ::std::path::PathBuf,
+    previous: Option<::std::ffi::OsString>,
+    _lock: ::std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestHome {
+    fn new(name: &str) -> TestHome {
+        use std::env;
+
+        // A failed test poisons the lock, that's no reason to fail the
+        // others
+        let lock = TEST_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = env::temp_dir().join(format!("lpass-test-{}-{}",
+                                                name,
+                                                ::std::process::id()));
+
+        let _ = ::std::fs::remove_dir_all(&path);
+
+        let previous = env::var_os("LPASS_HOME");
+
+        env::set_var("LPASS_HOME", &path);
+
+        TestHome {
+            path: path,
+            previous: previous,
+            _lock: lock,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        use std::env;
+
+        let _ = ::std::fs::remove_dir_all(&self.path);
+
+        match self.previous {
+            Some(ref p) => env::set_var("LPASS_HOME", p),
+            None => env::remove_var("LPASS_HOME"),
+        }
+    }
+}
+
+#[test]
+fn test_take_global_options() {
+    let args = |a: &[&str]| -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
+    };
+
+    let global = |c, y| GlobalOptions {
+        color: c,
+        assume_yes: y,
+        quiet: false,
+        verbose: 0,
+    };
+
+    let mut a = args(&["lpass", "--color=never", "ls", "-C", "always"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap(),
+               global(Some(terminal::ColorMode::Never), false));
+    // Options after the command are left alone
+    assert_eq!(a, args(&["lpass", "ls", "-C", "always"]));
+
+    let mut a = args(&["lpass", "-C", "always", "-y", "-Cauto", "--help"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap(),
+               global(Some(terminal::ColorMode::Auto), true));
+    assert_eq!(a, args(&["lpass", "--help"]));
+
+    let mut a = args(&["lpass", "--yes", "rm", "-y", "123"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap(), global(None, true));
+    assert_eq!(a, args(&["lpass", "rm", "-y", "123"]));
+
+    let mut a = args(&["lpass", "show", "foo"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap(), global(None, false));
+    assert_eq!(a, args(&["lpass", "show", "foo"]));
+
+    let mut a = args(&["lpass", "-vv", "--verbose", "-q", "ls"]);
+    let g = take_global_options(&mut a).unwrap();
+
+    assert_eq!(g.verbose, 3);
+    assert_eq!(g.log_level(), LogLevelFilter::Error);
+    assert_eq!(a, args(&["lpass", "ls"]));
+
+    let mut a = args(&["lpass", "-v", "show", "-v"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap().log_level(),
+               LogLevelFilter::Debug);
+    assert_eq!(a, args(&["lpass", "show", "-v"]));
+
+    // `lpass -v` alone is `lpass --version`
+    let mut a = args(&["lpass", "-v"]);
+
+    assert_eq!(take_global_options(&mut a).unwrap().log_level(),
+               DEFAULT_LOG_LEVEL);
+    assert_eq!(a, args(&["lpass", "-v"]));
+
+    assert!(take_global_options(&mut args(&["lpass", "--color"])).is_err());
+    assert!(take_global_options(&mut args(&["lpass", "-C", "red"])).is_err());
+}
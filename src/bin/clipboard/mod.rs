@@ -0,0 +1,251 @@
+/// Clipboard access
+///
+/// We don't talk to the windowing system ourselves, the data is piped
+/// into the first clipboard tool that works on this system. Secrets
+/// are always passed through the tool's stdin, never on the command
+/// line where other users could see them.
+///
+/// The clipboard is cleared after `LPASS_CLIPBOARD_TIMEOUT` seconds
+/// (0 means never) unless something else has been copied in the
+/// meantime.
+///
+/// The clearing is done by a fresh `lpass` process started with the
+/// internal `CLEAR_COMMAND` argument and receiving the data on its
+/// stdin. A forked copy of the caller would hold the whole decrypted
+/// vault in memory until the timeout expires.
+
+use lpass::{Result, Error, SecureStorage};
+
+use agent;
+
+use std::env;
+use std::io;
+use std::io::{Read, Write};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Hidden first argument of the helper process clearing the
+/// clipboard, followed by the index of the tool in `TOOLS` and the
+/// timeout
+pub const CLEAR_COMMAND: &'static str = "--internal-clear-clipboard";
+
+/// Default delay before clearing the clipboard, in seconds
+const DEFAULT_TIMEOUT: u32 = 45;
+
+/// External clipboard tool
+struct Tool {
+    /// Command copying stdin to the clipboard
+    copy: &'static [&'static str],
+    /// Command writing the contents of the clipboard to stdout
+    paste: &'static [&'static str],
+}
+
+#[cfg(target_os = "macos")]
+const TOOLS: &'static [Tool] = &[
+    Tool {
+        copy: &["pbcopy"],
+        paste: &["pbpaste"],
+    },
+];
+
+#[cfg(not(target_os = "macos"))]
+const TOOLS: &'static [Tool] = &[
+    Tool {
+        copy: &["wl-copy"],
+        paste: &["wl-paste", "--no-newline"],
+    },
+    Tool {
+        copy: &["xclip", "-selection", "clipboard", "-in"],
+        paste: &["xclip", "-selection", "clipboard", "-out"],
+    },
+    Tool {
+        copy: &["xsel", "--clipboard", "--input"],
+        paste: &["xsel", "--clipboard", "--output"],
+    },
+];
+
+/// Copy `data` to the clipboard and schedule its removal
+pub fn copy(data: &[u8]) -> Result<()> {
+    let tool =
+        TOOLS.iter().position(|t| match copy_with(t, data) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Couldn't copy with {}: {}", t.copy[0], e);
+                false
+            }
+        });
+
+    let tool =
+        match tool {
+            Some(t) => t,
+            None => {
+                let err = "Couldn't copy to the clipboard, make sure \
+                           xclip, xsel or wl-copy is installed".to_owned();
+                return Err(Error::Unsupported(err));
+            }
+        };
+
+    let timeout = timeout(|v| env::var(v).ok());
+
+    if timeout == 0 {
+        return Ok(());
+    }
+
+    let exe = try!(env::current_exe());
+
+    let mut child = try!(process::Command::new(exe)
+                         .arg(CLEAR_COMMAND)
+                         .arg(tool.to_string())
+                         .arg(timeout.to_string())
+                         .stdin(process::Stdio::piped())
+                         .stdout(process::Stdio::null())
+                         .stderr(process::Stdio::null())
+                         .spawn());
+
+    // The helper detaches itself once it has read the data, we don't
+    // wait for it
+    if let Some(mut stdin) = child.stdin.take() {
+        try!(stdin.write_all(data));
+    }
+
+    debug!("Clearing the clipboard in {}s (PID {})", timeout, child.id());
+
+    Ok(())
+}
+
+/// Entry point of the helper process started by `copy` with `args`
+/// following `CLEAR_COMMAND`. Returns the exit status.
+pub fn clear_helper(args: &[String]) -> i32 {
+    let params =
+        match (args.get(0), args.get(1)) {
+            (Some(t), Some(s)) => (usize::from_str(t), u32::from_str(s)),
+            _ => return 1,
+        };
+
+    let res =
+        match params {
+            (Ok(t), Ok(s)) if t < TOOLS.len() => clear_after(&TOOLS[t], s),
+            _ => return 1,
+        };
+
+    match res {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Return the clipboard timeout based on the environment variables
+/// returned by `getenv`
+fn timeout<F>(getenv: F) -> u32
+    where F: Fn(&str) -> Option<String> {
+
+    match getenv("LPASS_CLIPBOARD_TIMEOUT") {
+        Some(t) => {
+            match u32::from_str(&t) {
+                Ok(t) => t,
+                Err(_) => {
+                    warn!("Invalid LPASS_CLIPBOARD_TIMEOUT '{}', \
+                           using default", t);
+                    DEFAULT_TIMEOUT
+                }
+            }
+        }
+        None => DEFAULT_TIMEOUT,
+    }
+}
+
+/// Read the copied data from stdin, wait for `timeout` seconds in
+/// the background then clear the clipboard if it still contains the
+/// data
+fn clear_after(tool: &Tool, timeout: u32) -> Result<()> {
+    let mut data = try!(SecureStorage::with_capacity(256));
+
+    // Must be done before daemonizing, which closes stdin
+    for b in io::stdin().bytes() {
+        try!(data.push(try!(b)));
+    }
+
+    try!(agent::daemonize(0));
+
+    thread::sleep(Duration::from_secs(timeout as u64));
+
+    // Don't clobber whatever the user copied since
+    let current = try!(paste_with(tool));
+
+    if current.ct_eq(&data) {
+        try!(copy_with(tool, b""));
+    }
+
+    Ok(())
+}
+
+fn copy_with(tool: &Tool, data: &[u8]) -> Result<()> {
+    let mut child = try!(process::Command::new(tool.copy[0])
+                         .args(&tool.copy[1..])
+                         .stdin(process::Stdio::piped())
+                         .stdout(process::Stdio::null())
+                         .stderr(process::Stdio::null())
+                         .spawn());
+
+    let written =
+        match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(data),
+            None => Ok(()),
+        };
+
+    // stdin has been closed above, the tool sees the end of file
+    let status = try!(child.wait());
+
+    try!(written);
+
+    if status.success() {
+        Ok(())
+    } else {
+        let err = format!("{} failed ({})", tool.copy[0], status);
+        Err(Error::Unsupported(err))
+    }
+}
+
+fn paste_with(tool: &Tool) -> Result<SecureStorage> {
+    let mut child = try!(process::Command::new(tool.paste[0])
+                         .args(&tool.paste[1..])
+                         .stdin(process::Stdio::null())
+                         .stdout(process::Stdio::piped())
+                         .stderr(process::Stdio::null())
+                         .spawn());
+
+    let mut data = try!(SecureStorage::with_capacity(256));
+
+    if let Some(stdout) = child.stdout.take() {
+        for b in stdout.bytes() {
+            try!(data.push(try!(b)));
+        }
+    }
+
+    let status = try!(child.wait());
+
+    if status.success() {
+        Ok(data)
+    } else {
+        let err = format!("{} failed ({})", tool.paste[0], status);
+        Err(Error::Unsupported(err))
+    }
+}
+
+#[test]
+fn test_timeout() {
+    let env = |value: Option<&'static str>| {
+        move |v: &str| {
+            assert_eq!(v, "LPASS_CLIPBOARD_TIMEOUT");
+            value.map(|s| s.to_owned())
+        }
+    };
+
+    assert_eq!(timeout(env(None)), DEFAULT_TIMEOUT);
+    assert_eq!(timeout(env(Some("10"))), 10);
+    assert_eq!(timeout(env(Some("0"))), 0);
+    assert_eq!(timeout(env(Some("soon"))), DEFAULT_TIMEOUT);
+    assert_eq!(timeout(env(Some("-1"))), DEFAULT_TIMEOUT);
+}
@@ -0,0 +1,81 @@
+use lpass::{Result, Error, Account, SecureStorage, Session};
+
+use CommandOption;
+
+use terminal;
+use password;
+use commands::{open_session, read_field, with_relogin};
+
+use getopts::Matches;
+
+use std::convert::TryFrom;
+
+pub const ADD_COMMAND: ::Command = ::Command {
+    name: "add",
+    options: &[
+        CommandOption {
+            short_name: "",
+            long_name: "field",
+            description: "only set FIELD (username, password, url, notes \
+                          or the name of a custom field), read from the \
+                          standard input",
+            argument: Some("FIELD"),
+        },
+    ],
+    free_args: "NAME",
+    command: add,
+};
+
+pub fn add(options: &Matches) -> Result<()> {
+    let name =
+        match options.free.get(0) {
+            Some(n) => n,
+            None => {
+                println!("Missing NAME");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let mut account = Account::new(name);
+
+    if account.name.is_empty() {
+        println!("Invalid account name '{}'", name);
+        return Err(Error::BadUsage);
+    }
+
+    match options.opt_str("field") {
+        Some(field) => try!(read_field(&mut account, &field)),
+        None => try!(prompt_fields(&mut account)),
+    }
+
+    let mut session = try!(open_session());
+
+    add_account(&mut session, &account)
+}
+
+/// Add `account` to the vault and display its new ID
+pub fn add_account(session: &mut Session,
+                   account: &Account) -> Result<()> {
+    let id = try!(with_relogin(session, |s| s.add_account(account)));
+
+    println!("Added {} [id: {}]", account.fullname, id);
+
+    Ok(())
+}
+
+/// Interactively ask for the account's fields
+fn prompt_fields(account: &mut Account) -> Result<()> {
+    account.url = try!(terminal::ask("URL: "));
+    account.username = try!(terminal::ask("Username: "));
+
+    let desc = format!("Please enter the password for {}",
+                       account.fullname);
+
+    account.password = try!(password::prompt("Password", &desc, None));
+
+    let notes = try!(terminal::ask("Notes: "));
+
+    account.note = try!(SecureStorage::try_from(notes));
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use lpass::{Result, Error, Account, Attachment};
+
+use CommandOption;
+
+use commands::{open_session, find_account, with_relogin};
+
+use getopts::Matches;
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+pub const ATTACH_COMMAND: ::Command = ::Command {
+    name: "attach",
+    options: &[
+        CommandOption {
+            short_name: "o",
+            long_name: "output",
+            description: "save the attachment to FILE instead of its \
+                          original name, '-' for the standard output",
+            argument: Some("FILE"),
+        },
+    ],
+    free_args: "UNIQUENAME|UNIQUEID [ATTACHID]",
+    command: attach,
+};
+
+pub fn attach(options: &Matches) -> Result<()> {
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing UNIQUENAME|UNIQUEID");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    let account = try!(find_account(&vault, query));
+
+    let id =
+        match options.free.get(1) {
+            Some(id) => id,
+            None => {
+                list(account);
+                return Ok(());
+            }
+        };
+
+    let attachment =
+        match account.attachments.iter().find(|a| a.id == *id) {
+            Some(a) => a,
+            None => {
+                println!("{} has no attachment with ID {}",
+                         account.fullname, id);
+                return Err(Error::BadUsage);
+            }
+        };
+
+    let output =
+        match options.opt_str("o") {
+            Some(o) => o,
+            None => try!(default_output(attachment)),
+        };
+
+    if output == "-" {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        return with_relogin(&mut session, |s| {
+            s.get_attachment(account, attachment, &mut stdout)
+        }).map(|_| ());
+    }
+
+    // Don't clobber existing files and make sure that nobody else can
+    // read the attachment
+    let mut file = try!(fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .mode(0o600)
+                        .open(&output));
+
+    let res = with_relogin(&mut session, |s| {
+        s.get_attachment(account, attachment, &mut file)
+    });
+
+    let res = res.and_then(|len| {
+        try!(file.flush());
+        Ok(len)
+    });
+
+    match res {
+        Ok(len) => {
+            println!("Saved {} ({} bytes)", output, len);
+            Ok(())
+        }
+        Err(e) => {
+            if let Err(e) = fs::remove_file(&output) {
+                warn!("Couldn't remove {}: {}", output, e);
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// Display the attachments of `account`
+fn list(account: &Account) {
+    if account.attachments.is_empty() {
+        println!("{} has no attachments", account.fullname);
+        return;
+    }
+
+    for a in &account.attachments {
+        println!("[id: {}] {} ({} bytes, {})",
+                 a.id, a.filename, a.size, a.mime_type);
+    }
+}
+
+/// Return the name the attachment is saved as by default: its
+/// original name without any directory
+fn default_output(attachment: &Attachment) -> Result<String> {
+    let name =
+        Path::new(&attachment.filename)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.to_owned());
+
+    match name {
+        Some(ref n) if !n.starts_with('.') => Ok(n.clone()),
+        _ => {
+            println!("Invalid attachment name '{}', use --output",
+                     attachment.filename);
+            Err(Error::BadUsage)
+        }
+    }
+}
+
+#[test]
+fn test_default_output() {
+    let attachment = |name: &str| Attachment {
+        id: "1".to_owned(),
+        parent: "2".to_owned(),
+        mime_type: "text/plain".to_owned(),
+        storage_key: "0123".to_owned(),
+        size: 0,
+        filename: name.to_owned(),
+    };
+
+    assert_eq!(default_output(&attachment("notes.txt")).unwrap(),
+               "notes.txt");
+    assert!(default_output(&attachment("../../.bashrc")).is_err());
+    assert_eq!(default_output(&attachment("/etc/passwd")).unwrap(),
+               "passwd");
+    assert!(default_output(&attachment("")).is_err());
+    assert!(default_output(&attachment("..")).is_err());
+}
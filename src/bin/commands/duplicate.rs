@@ -0,0 +1,50 @@
+use lpass::{Result, Error};
+
+use terminal::{color, Color};
+use commands::{open_session, find_account, with_relogin};
+use commands::add::add_account;
+
+use getopts::Matches;
+
+pub const DUPLICATE_COMMAND: ::Command = ::Command {
+    name: "duplicate",
+    options: &[],
+    free_args: "UNIQUENAME|UNIQUEID",
+    command: duplicate,
+};
+
+pub fn duplicate(options: &Matches) -> Result<()> {
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing UNIQUENAME|UNIQUEID");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    let id = try!(find_account(&vault, query)).id.clone();
+
+    let mut account =
+        vault.into_accounts().into_iter().find(|a| a.id == id).unwrap();
+
+    let fullname = format!("{} (clone)", account.fullname);
+
+    account.set_fullname(&fullname);
+    // The server assigns a new ID to the copy
+    account.id = "0".to_owned();
+
+    // The files would have to be downloaded and uploaded again
+    for a in &account.attachments {
+        println!("{}Warning{}: attachment '{}' can't be copied",
+                 color(Color::FgYellow),
+                 color(Color::Reset),
+                 a.filename);
+    }
+
+    add_account(&mut session, &account)
+}
@@ -0,0 +1,277 @@
+use lpass::{Result, Error, Account, SecureStorage};
+use lpass::config;
+
+use CommandOption;
+
+use commands::{open_session, find_account, get_field, set_field, read_field};
+use commands::{with_relogin, custom_field_name};
+
+use getopts::Matches;
+
+use libc;
+
+use std::process;
+
+pub const EDIT_COMMAND: ::Command = ::Command {
+    name: "edit",
+    options: &[
+        CommandOption {
+            short_name: "",
+            long_name: "field",
+            description: "only edit FIELD (name, username, password, url, \
+                          notes or the name of a custom field)",
+            argument: Some("FIELD"),
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "non-interactive",
+            description: "read the new value of the field from the \
+                          standard input instead of using $EDITOR",
+            argument: None,
+        },
+    ],
+    free_args: "UNIQUENAME|UNIQUEID",
+    command: edit,
+};
+
+/// Header of the notes section when editing the whole account. All
+/// the lines after this one are part of the notes.
+const NOTES_HEADER: &'static [u8] = b"Notes:    # Add notes below this line.";
+
+pub fn edit(options: &Matches) -> Result<()> {
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing UNIQUENAME|UNIQUEID");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let field = options.opt_str("field");
+    let non_interactive = options.opt_present("non-interactive");
+
+    if non_interactive && field.is_none() {
+        println!("--non-interactive requires --field");
+        return Err(Error::BadUsage);
+    }
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    let id = try!(find_account(&vault, query)).id.clone();
+
+    let mut account =
+        vault.into_accounts().into_iter().find(|a| a.id == id).unwrap();
+
+    match (field, non_interactive) {
+        (Some(ref f), true) => try!(read_field(&mut account, f)),
+        (Some(ref f), false) => {
+            let value = {
+                // New custom fields start empty
+                let current =
+                    match custom_field_name(f) {
+                        Some(n) if account.field(n).is_none() => &[],
+                        _ => try!(get_field(&account, f)),
+                    };
+                let edited = try!(run_editor(current));
+
+                strip_trailing_newline(edited)
+            };
+
+            try!(set_field(&mut account, f, value));
+        }
+        (None, _) => {
+            let current = try!(format_account(&account));
+            let edited = try!(run_editor(&current));
+
+            try!(parse_account(&mut account, &edited));
+        }
+    }
+
+    if account.name.is_empty() {
+        println!("Invalid empty account name");
+        return Err(Error::BadUsage);
+    }
+
+    try!(with_relogin(&mut session, |s| s.update_account(&account)));
+
+    Ok(())
+}
+
+/// Let the user edit `data` in `$EDITOR` and return the result. The
+/// temporary file lives in the configuration directory, it's only
+/// readable by the user and is overwritten before being removed.
+fn run_editor(data: &[u8]) -> Result<SecureStorage> {
+    let name = format!("edit.{}", unsafe { libc::getpid() });
+
+    try!(config::write(&name, data));
+
+    let res = spawn_editor(&name);
+
+    let edited =
+        match res {
+            Ok(()) => config::read(&name),
+            Err(e) => Err(e),
+        };
+
+    if let Err(e) = config::shred(&name) {
+        warn!("Couldn't remove the temporary file {}: {}", name, e);
+    }
+
+    match try!(edited) {
+        Some(e) => Ok(e),
+        None => {
+            println!("The temporary file has been removed");
+            Err(Error::UserAbort)
+        }
+    }
+}
+
+fn spawn_editor(name: &str) -> Result<()> {
+    let path = try!(config::path(name));
+
+    // Go through the shell since $EDITOR can contain arguments
+    let status = try!(process::Command::new("sh")
+                      .arg("-c")
+                      .arg("${EDITOR:-vi} \"$1\"")
+                      .arg("sh")
+                      .arg(&path)
+                      .status());
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::UserAbort)
+    }
+}
+
+/// Remove the newline most editors add at the end of the file
+fn strip_trailing_newline(mut data: SecureStorage) -> SecureStorage {
+    if data.last() == Some(&b'\n') {
+        let len = data.len() - 1;
+
+        data.truncate(len);
+    }
+
+    data
+}
+
+/// Format `account` for editing as `Key: value` lines followed by the
+/// notes
+fn format_account(account: &Account) -> Result<SecureStorage> {
+    let mut data = try!(SecureStorage::with_capacity(256));
+
+    let fields: &[(&str, &[u8])] = &[
+        ("Name", account.fullname.as_bytes()),
+        ("URL", account.url.as_bytes()),
+        ("Username", account.username.as_bytes()),
+        ("Password", &account.password),
+    ];
+
+    for &(key, value) in fields {
+        try!(data.extend_from_slice(key.as_bytes()));
+        try!(data.extend_from_slice(b": "));
+        try!(data.extend_from_slice(value));
+        try!(data.push(b'\n'));
+    }
+
+    try!(data.extend_from_slice(NOTES_HEADER));
+    try!(data.push(b'\n'));
+    try!(data.extend_from_slice(&account.note));
+
+    Ok(data)
+}
+
+/// Parse the output of `format_account` back into `account`
+fn parse_account(account: &mut Account, data: &[u8]) -> Result<()> {
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+
+        let line = &rest[..end];
+
+        rest = if end < rest.len() { &rest[end + 1..] } else { &[] };
+
+        if line == NOTES_HEADER {
+            let notes = try!(SecureStorage::from_slice(rest));
+
+            return set_field(account, "notes", strip_trailing_newline(notes));
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let sep =
+            match line.iter().position(|&b| b == b':') {
+                Some(p) => p,
+                None => {
+                    println!("Invalid line: {}",
+                             String::from_utf8_lossy(line));
+                    return Err(Error::BadUsage);
+                }
+            };
+
+        let key = &line[..sep];
+        let mut value = &line[sep + 1..];
+
+        if value.first() == Some(&b' ') {
+            value = &value[1..];
+        }
+
+        let field =
+            match key {
+                b"Name" => "name",
+                b"URL" => "url",
+                b"Username" => "username",
+                b"Password" => "password",
+                _ => {
+                    println!("Unknown field '{}'",
+                             String::from_utf8_lossy(key));
+                    return Err(Error::BadUsage);
+                }
+            };
+
+        try!(set_field(account, field, try!(SecureStorage::from_slice(value))));
+    }
+
+    // No notes section, clear the notes
+    set_field(account, "notes", SecureStorage::empty())
+}
+
+#[test]
+fn test_parse_account() {
+    let mut account = Account::new("group/name");
+
+    account.url = "https://example.com".to_owned();
+    account.username = "user".to_owned();
+    account.password = SecureStorage::from_slice(b"p4ss: word").unwrap();
+    account.note = SecureStorage::from_slice(b"line 1\nline 2").unwrap();
+
+    let formatted = format_account(&account).unwrap();
+
+    let mut parsed = Account::new("");
+
+    parse_account(&mut parsed, &formatted).unwrap();
+
+    assert_eq!(parsed.fullname, "group/name");
+    assert_eq!(parsed.group, "group");
+    assert_eq!(parsed.name, "name");
+    assert_eq!(parsed.url, "https://example.com");
+    assert_eq!(parsed.username, "user");
+    assert!(parsed.password.ct_eq(b"p4ss: word"));
+    assert!(parsed.note.ct_eq(b"line 1\nline 2"));
+
+    // Editors usually add a newline at the end of the file
+    let mut edited = formatted.to_vec();
+    edited.push(b'\n');
+
+    parse_account(&mut parsed, &edited).unwrap();
+
+    assert!(parsed.note.ct_eq(b"line 1\nline 2"));
+
+    assert!(parse_account(&mut parsed, b"Name: foo\nbogus line\n").is_err());
+}
@@ -0,0 +1,115 @@
+use lpass::{Result, Error, SecureStorage};
+
+use CommandOption;
+
+use terminal;
+use commands::{open_session, with_relogin};
+use json;
+use csv;
+
+use getopts::Matches;
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+pub const EXPORT_COMMAND: ::Command = ::Command {
+    name: "export",
+    options: &[
+        CommandOption {
+            short_name: "j",
+            long_name: "json",
+            description: "export the vault as JSON instead of CSV",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "o",
+            long_name: "out",
+            description: "write the export to FILE instead of the \
+                          standard output",
+            argument: Some("FILE"),
+        },
+        CommandOption {
+            short_name: "f",
+            long_name: "force",
+            description: "allow writing the plaintext secrets to a \
+                          terminal",
+            argument: None,
+        },
+    ],
+    free_args: "",
+    command: export,
+};
+
+pub fn export(options: &Matches) -> Result<()> {
+    let out = options.opt_str("o");
+
+    if out.is_none() &&
+        terminal::stdout_is_a_tty() &&
+        !options.opt_present("f") {
+        println!("Refusing to write the plaintext vault to a terminal, \
+                  use --force to override");
+        return Err(Error::BadUsage);
+    }
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    let accounts: Vec<_> =
+        vault.accounts().iter().filter(|a| !a.is_group()).collect();
+
+    // Serialize in locked memory, the buffer is zeroed when dropped
+    let mut data = try!(SecureStorage::with_capacity(4096));
+
+    if options.opt_present("j") {
+        try!(data.write_all(b"[\n"));
+
+        for (i, a) in accounts.iter().enumerate() {
+            if i > 0 {
+                try!(data.write_all(b",\n"));
+            }
+
+            try!(json::write_account(&mut data, a));
+        }
+
+        try!(data.write_all(b"\n]\n"));
+    } else {
+        try!(csv::write_header(&mut data));
+
+        for a in &accounts {
+            try!(csv::write_account(&mut data, a));
+        }
+    }
+
+    write_output(out.as_ref().map(|o| &**o), &data)
+}
+
+/// Write `data` to the file `out` (only readable by the user) or to
+/// stdout if `out` is `None`
+pub fn write_output(out: Option<&str>, data: &[u8]) -> Result<()> {
+    match out {
+        Some(path) => {
+            let mut file = try!(fs::OpenOptions::new()
+                                .write(true)
+                                .create(true)
+                                .truncate(true)
+                                .mode(0o600)
+                                .open(path));
+
+            // `mode` only applies if the file is created
+            try!(file.set_permissions(fs::Permissions::from_mode(0o600)));
+
+            try!(file.write_all(data));
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+
+            try!(stdout.write_all(data));
+        }
+    }
+
+    Ok(())
+}
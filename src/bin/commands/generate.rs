@@ -0,0 +1,97 @@
+use lpass::{Result, Error, Account};
+use lpass::pwgen;
+
+use CommandOption;
+
+use commands::open_session;
+use commands::add::add_account;
+
+use getopts::Matches;
+
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Password length used when none is given on the command line
+const DEFAULT_LENGTH: usize = 20;
+
+pub const GENERATE_COMMAND: ::Command = ::Command {
+    name: "generate",
+    options: &[
+        CommandOption {
+            short_name: "",
+            long_name: "no-symbols",
+            description: "don't use symbols in the password",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "no-digits",
+            description: "don't use digits in the password",
+            argument: None,
+        },
+    ],
+    free_args: "[UNIQUENAME] [LENGTH]",
+    command: generate,
+};
+
+pub fn generate(options: &Matches) -> Result<()> {
+    let (name, length) =
+        match options.free.len() {
+            0 => (None, DEFAULT_LENGTH),
+            1 => {
+                let arg = &options.free[0];
+
+                match usize::from_str(arg) {
+                    Ok(l) => (None, l),
+                    Err(_) => (Some(arg), DEFAULT_LENGTH),
+                }
+            }
+            2 => {
+                let length = try!(parse_length(&options.free[1]));
+
+                (Some(&options.free[0]), length)
+            }
+            _ => {
+                println!("Too many arguments");
+                return Err(Error::BadUsage);
+            }
+        };
+
+    if length == 0 {
+        println!("Invalid password length");
+        return Err(Error::BadUsage);
+    }
+
+    let password = try!(pwgen::generate(length,
+                                        !options.opt_present("no-digits"),
+                                        !options.opt_present("no-symbols")));
+
+    if let Some(name) = name {
+        let mut account = Account::new(name);
+
+        account.password = password;
+
+        let mut session = try!(open_session());
+
+        return add_account(&mut session, &account);
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    try!(stdout.write_all(&password));
+    try!(stdout.write_all(b"\n"));
+
+    Ok(())
+}
+
+fn parse_length(length: &str) -> Result<usize> {
+    match usize::from_str(length) {
+        Ok(l) => Ok(l),
+        Err(_) => {
+            println!("Invalid password length '{}'", length);
+            Err(Error::BadUsage)
+        }
+    }
+}
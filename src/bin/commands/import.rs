@@ -0,0 +1,165 @@
+use lpass::{Result, Error, Account, SecureStorage};
+
+use CommandOption;
+
+use terminal::{color, Color};
+use commands::{open_session, set_field, with_relogin};
+use csv;
+
+use getopts::Matches;
+
+use std::fs;
+use std::io;
+use std::io::Read;
+
+pub const IMPORT_COMMAND: ::Command = ::Command {
+    name: "import",
+    options: &[
+        CommandOption {
+            short_name: "n",
+            long_name: "dry-run",
+            description: "only display the accounts that would be created",
+            argument: None,
+        },
+    ],
+    free_args: "[CSV_FILE]",
+    command: import,
+};
+
+/// Map the CSV columns to account fields. Both the LastPass and the
+/// Bitwarden column names are supported.
+const COLUMNS: &'static [(&'static str, &'static str)] = &[
+    ("url", "url"),
+    ("login_uri", "url"),
+    ("username", "username"),
+    ("login_username", "username"),
+    ("password", "password"),
+    ("login_password", "password"),
+    ("extra", "notes"),
+    ("notes", "notes"),
+    ("name", "name"),
+    ("grouping", "group"),
+    ("folder", "group"),
+];
+
+pub fn import(options: &Matches) -> Result<()> {
+    let dry_run = options.opt_present("n");
+
+    let mut data = try!(SecureStorage::with_capacity(4096));
+
+    match options.free.get(0) {
+        Some(path) => {
+            let mut file = try!(fs::File::open(path));
+
+            try!(read_all(&mut file, &mut data));
+        }
+        None => {
+            let stdin = io::stdin();
+
+            try!(read_all(&mut stdin.lock(), &mut data));
+        }
+    }
+
+    let mut records = try!(csv::parse(&data)).into_iter();
+
+    let columns: Vec<Option<&str>> =
+        match records.next() {
+            Some(header) =>
+                header.iter()
+                .map(|h| {
+                    COLUMNS.iter()
+                        .find(|&&(c, _)| h.ct_eq(c.as_bytes()))
+                        .map(|&(_, f)| f)
+                })
+                .collect(),
+            None => {
+                println!("Empty CSV file");
+                return Err(Error::BadUsage);
+            }
+        };
+
+    if !columns.contains(&Some("name")) {
+        println!("Missing 'name' column");
+        return Err(Error::BadUsage);
+    }
+
+    let mut accounts = Vec::new();
+
+    for (line, record) in records.enumerate() {
+        let account = try!(build_account(&columns, record));
+
+        if account.name.is_empty() {
+            // Line 1 is the header
+            println!("{}Warning{}: skipping record {} without a name",
+                     color(Color::FgYellow),
+                     color(Color::Reset),
+                     line + 2);
+            continue;
+        }
+
+        accounts.push(account);
+    }
+
+    if dry_run {
+        for a in &accounts {
+            println!("Would create {}", a.fullname);
+        }
+
+        return Ok(());
+    }
+
+    let mut session = try!(open_session());
+
+    for a in &accounts {
+        try!(with_relogin(&mut session, |s| s.update_account(a)));
+
+        println!("Created {}", a.fullname);
+    }
+
+    println!("Imported {} accounts.", accounts.len());
+
+    Ok(())
+}
+
+/// Build a new account from a CSV `record` whose fields are described
+/// by `columns`
+fn build_account(columns: &[Option<&str>],
+                 record: Vec<SecureStorage>) -> Result<Account> {
+    let mut account = Account::new("");
+
+    let mut name = String::new();
+    let mut group = String::new();
+
+    for (&column, value) in columns.iter().zip(record) {
+        match column {
+            Some("name") => name = String::from_utf8_lossy(&value).into_owned(),
+            Some("group") =>
+                group = String::from_utf8_lossy(&value).into_owned(),
+            Some(field) => try!(set_field(&mut account, field, value)),
+            None => (),
+        }
+    }
+
+    // Set the name and group directly since the name could contain
+    // slashes
+    if !name.is_empty() {
+        account.fullname =
+            if group.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", group, name)
+            };
+        account.name = name;
+        account.group = group;
+    }
+
+    Ok(account)
+}
+
+fn read_all<R: Read>(reader: &mut R, data: &mut SecureStorage) -> Result<()> {
+    for b in reader.bytes() {
+        try!(data.push(try!(b)));
+    }
+
+    Ok(())
+}
@@ -3,11 +3,14 @@ use lpass;
 
 use CommandOption;
 
-use terminal::ask_yes_no;
+use terminal::{ask_yes_no_always, color, Color};
 use password;
+use commands::{start_agent, http_config, sync_mode};
 
 use getopts::Matches;
 
+use std::env;
+
 pub const LOGIN_COMMAND: ::Command = ::Command {
     name: "login",
     options: &[
@@ -17,6 +20,13 @@ pub const LOGIN_COMMAND: ::Command = ::Command {
             description: "subsequent logins won't require 2FA",
             argument: None,
         },
+        CommandOption {
+            short_name: "",
+            long_name: "server",
+            description: "server to log into (default: $LPASS_SERVER or \
+                          lastpass.com)",
+            argument: Some("HOSTNAME"),
+        },
         CommandOption {
             short_name: "P",
             long_name: "plaintext-key",
@@ -41,6 +51,18 @@ pub fn login(options: &Matches) -> Result<()> {
     let plaintext_key = options.opt_present("P");
     let force = options.opt_present("f");
 
+    let server =
+        match options.opt_str("server") {
+            Some(s) => s,
+            None => default_server(),
+        };
+
+    if !is_hostname(&server) {
+        println!("Invalid server '{}', expected a hostname \
+                  such as 'lastpass.eu'", server);
+        return Err(Error::BadUsage);
+    }
+
     let login =
         match options.free.get(0) {
             Some(l) => l,
@@ -50,17 +72,45 @@ pub fn login(options: &Matches) -> Result<()> {
             }
         };
 
+    // This one must be explicitly confirmed, `--yes` is not enough
     if plaintext_key && !force {
-        try!(ask_yes_no(false,
-                        "You have used the --plaintext-key option. \
-                         This option will greatly reduce the security \
-                         of your passwords. You are advised, instead, \
-                         to use the agent, whose timeout can be disabled \
-                         by settting LPASS_AGENT_TIMEOUT=0. Are you sure \
-                         you would like to do this?"))
+        try!(ask_yes_no_always(false,
+                               "You have used the --plaintext-key option. \
+                                This option will greatly reduce the \
+                                security of your passwords. You are \
+                                advised, instead, to use the agent, whose \
+                                timeout can be disabled by settting \
+                                LPASS_AGENT_TIMEOUT=0. Are you sure you \
+                                would like to do this?"))
     }
 
-    let mut session = lpass::Session::new(&login);
+    let session = try!(authenticate(login, &server, trust));
+
+    try!(session.save());
+
+    start_agent(&session);
+
+    Ok(())
+}
+
+/// Return the server used when none is given on the command line:
+/// `LPASS_SERVER` or `lpass::DEFAULT_SERVER`
+pub fn default_server() -> String {
+    match env::var("LPASS_SERVER") {
+        Ok(ref s) if !s.is_empty() => s.clone(),
+        _ => lpass::DEFAULT_SERVER.to_owned(),
+    }
+}
+
+/// Prompt for the master password of `login` and authenticate on
+/// `server`.
+pub fn authenticate(login: &str,
+                    server: &str,
+                    trust: bool) -> Result<lpass::Session> {
+    let mut session = lpass::Session::with_server(login, server);
+
+    session.set_http_config(http_config());
+    session.set_sync_mode(sync_mode());
 
     let desc = format!("Please enter the master password for <{}>", login);
 
@@ -71,10 +121,39 @@ pub fn login(options: &Matches) -> Result<()> {
         try!(session.login(password, trust, otp_query));
     }
 
-    Ok(())
+    Ok(session)
 }
 
-fn otp_query(method: lpass::OtpMethod) -> Option<lpass::SecureStorage> {
+/// Return `true` if `server` looks like a bare hostname (no scheme,
+/// port or path)
+fn is_hostname(server: &str) -> bool {
+    !server.is_empty() &&
+        !server.starts_with('.') &&
+        !server.ends_with('.') &&
+        !server.contains("..") &&
+        server.chars().all(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => true,
+            _ => false,
+        })
+}
+
+/// Prompt for the OTP during the login, see `lpass::Session::login`
+pub fn otp_query(method: lpass::OtpMethod,
+                 rejected: Option<&str>) -> Option<lpass::SecureStorage> {
+
+    if method == lpass::OtpMethod::OutOfBand {
+        println!("Waiting for approval, please approve the login \
+                  on your phone...");
+
+        return Some(lpass::SecureStorage::empty());
+    }
+
+    if let Some(msg) = rejected {
+        println!("{}Previous code was rejected, try again{} ({})",
+                 color(Color::FgRed),
+                 color(Color::Reset),
+                 msg);
+    }
 
     let desc = format!("Please provide your {} OTP", method);
 
@@ -93,3 +172,16 @@ fn otp_query(method: lpass::OtpMethod) -> Option<lpass::SecureStorage> {
         }
     }
 }
+
+#[test]
+fn test_is_hostname() {
+    assert!(is_hostname("lastpass.com"));
+    assert!(is_hostname("lastpass.eu"));
+    assert!(is_hostname("lp-1.example.com"));
+    assert!(!is_hostname(""));
+    assert!(!is_hostname(".lastpass.com"));
+    assert!(!is_hostname("lastpass.com."));
+    assert!(!is_hostname("lastpass..com"));
+    assert!(!is_hostname("https://lastpass.com"));
+    assert!(!is_hostname("lastpass.com:443"));
+}
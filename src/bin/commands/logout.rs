@@ -0,0 +1,61 @@
+use lpass::Result;
+use lpass;
+
+use CommandOption;
+
+use terminal::{ask_yes_no, color, Color};
+use agent;
+use commands::load_session;
+
+use getopts::Matches;
+
+pub const LOGOUT_COMMAND: ::Command = ::Command {
+    name: "logout",
+    options: &[
+        CommandOption {
+            short_name: "f",
+            long_name: "force",
+            description: "don't ask for confirmation",
+            argument: None,
+        },
+    ],
+    free_args: "",
+    command: logout,
+};
+
+pub fn logout(options: &Matches) -> Result<()> {
+    let force = options.opt_present("f");
+
+    let session = try!(load_session());
+
+    let mut session =
+        match session {
+            Some(s) => s,
+            None => {
+                println!("Not logged in.");
+                return Ok(());
+            }
+        };
+
+    if !force {
+        try!(ask_yes_no(true, "Are you sure you would like to log out? "));
+    }
+
+    // Remove the local state even if we can't reach the server,
+    // otherwise the user would be stuck.
+    if let Err(e) = session.logout() {
+        println!("{}Warning{}: server-side logout failed, the session \
+                  may not have been revoked: {}",
+                 color(Color::FgYellow),
+                 color(Color::Reset),
+                 e);
+    }
+
+    agent::kill();
+
+    try!(lpass::Session::remove_saved());
+
+    println!("Log out: complete.");
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use lpass::{Result, Error};
+use lpass::Account;
+
+use CommandOption;
+
+use terminal::{color, Color};
+use commands::{open_session, with_relogin};
+
+use getopts::Matches;
+
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+use libc;
+
+pub const LS_COMMAND: ::Command = ::Command {
+    name: "ls",
+    options: &[
+        CommandOption {
+            short_name: "l",
+            long_name: "long",
+            description: "also display the username and modification time",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "sort",
+            description: "sort by folder and name (default), or most \
+                          recently modified or accessed first",
+            argument: Some("name|modified|touch"),
+        },
+    ],
+    free_args: "",
+    command: ls,
+};
+
+/// Order of the listing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Sort {
+    Name,
+    Modified,
+    Touch,
+}
+
+pub fn ls(options: &Matches) -> Result<()> {
+    let long = options.opt_present("l");
+
+    let sort =
+        match options.opt_str("sort").as_ref().map(|s| &**s) {
+            None | Some("name") => Sort::Name,
+            Some("modified") => Sort::Modified,
+            Some("touch") => Sort::Touch,
+            Some(s) => {
+                println!("Unknown sort order '{}'", s);
+                return Err(Error::BadUsage);
+            }
+        };
+
+    let mut session = try!(open_session());
+
+    let mut accounts: Vec<_> =
+        try!(with_relogin(&mut session, |s| s.get_accounts()))
+        .into_iter()
+        .filter(|a| !a.is_group())
+        .collect();
+
+    // Sort by folder and then by name. Accounts without a group end
+    // up at the top level.
+    let by_name = |a: &Account, b: &Account| {
+        (&a.group, &a.name).cmp(&(&b.group, &b.name))
+    };
+
+    // Most recent first, the accounts with an unknown time last
+    match sort {
+        Sort::Name => accounts.sort_by(|a, b| by_name(a, b)),
+        Sort::Modified => accounts.sort_by(|a, b| {
+            b.last_modified.cmp(&a.last_modified)
+                .then_with(|| by_name(a, b))
+        }),
+        Sort::Touch => accounts.sort_by(|a, b| {
+            b.last_touch.cmp(&a.last_touch)
+                .then_with(|| by_name(a, b))
+        }),
+    }
+
+    for a in &accounts {
+        // Display the time we sorted by
+        let time =
+            if sort == Sort::Touch {
+                a.last_touch
+            } else {
+                a.last_modified
+            };
+
+        print_account(a, long, time);
+    }
+
+    Ok(())
+}
+
+/// Display `account` on a single line. In `long` mode `time` and the
+/// username are displayed as well.
+pub fn print_account(account: &Account, long: bool, time: Option<SystemTime>) {
+    if long {
+        print!("{:16} ", format_time(time));
+    }
+
+    if !account.group.is_empty() {
+        print!("{}{}{}/{}",
+               color(Color::Bold),
+               color(Color::FgBlue),
+               account.group,
+               color(Color::Reset));
+    }
+
+    print!("{} [id: {}]", account.name, account.id);
+
+    if long {
+        print!(" [username: {}]", account.username);
+    }
+
+    println!("");
+}
+
+/// Format `time` as local time. Returns an empty string if it's
+/// unknown.
+fn format_time(time: Option<SystemTime>) -> String {
+    let t =
+        match time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            Some(d) => d.as_secs() as libc::time_t,
+            None => return String::new(),
+        };
+
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+
+    let ret = unsafe { libc::localtime_r(&t, &mut tm) };
+
+    if ret.is_null() {
+        return String::new();
+    }
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min)
+}
@@ -1 +1,387 @@
 pub mod login;
+pub mod logout;
+pub mod status;
+pub mod ls;
+pub mod show;
+pub mod generate;
+pub mod add;
+pub mod rm;
+pub mod mv;
+pub mod duplicate;
+pub mod edit;
+pub mod sync;
+pub mod export;
+pub mod import;
+pub mod nativemsg;
+pub mod passwd;
+pub mod search;
+pub mod attach;
+
+use lpass::{Result, Error, Session, Account, HttpConfig, SecureStorage};
+use lpass::Field;
+use lpass::{SyncMode, Vault};
+
+use terminal::{self, color, Color};
+use password;
+use agent;
+
+use std::env;
+use std::io;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+/// Return a session authenticated on the server with its decryption
+/// key. The saved session is reused if there's one, otherwise we log
+/// in.
+pub fn open_session() -> Result<Session> {
+    if let Some(mut session) = try!(load_session()) {
+        if let Some(key) = agent::get_key() {
+            session.set_crypto_key(key);
+
+            return Ok(session);
+        }
+
+        let desc = format!("Please enter the master password for <{}>",
+                           session.username());
+
+        let password =
+            try!(password::prompt("Master password", &desc, None));
+
+        try!(session.unlock(password));
+
+        start_agent(&session);
+
+        return Ok(session);
+    }
+
+    let username = try!(terminal::ask("Username: "));
+
+    let session =
+        try!(login::authenticate(&username, &login::default_server(), false));
+
+    try!(session.save());
+
+    start_agent(&session);
+
+    Ok(session)
+}
+
+/// Run `f` on `session`. If it fails because the server doesn't
+/// recognize the session anymore (it expired or was revoked) we log
+/// in again and retry once.
+pub fn with_relogin<T, F>(session: &mut Session, mut f: F) -> Result<T>
+    where F: FnMut(&mut Session) -> Result<T> {
+
+    match f(session) {
+        Err(Error::SessionExpired) => (),
+        res => return res,
+    }
+
+    println!("{}Session expired{}, logging in again",
+             color(Color::FgYellow),
+             color(Color::Reset));
+
+    let desc = format!("Please enter the master password for <{}>",
+                       session.username());
+
+    let password = try!(password::prompt("Master password", &desc, None));
+
+    try!(session.relogin(password, login::otp_query));
+
+    start_agent(session);
+
+    f(session)
+}
+
+/// Sync mode selected on the command line, stored as an integer so
+/// that it can live in a static
+static SYNC_MODE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set the `SyncMode` used by the sessions returned by `load_session`
+pub fn set_sync_mode(mode: SyncMode) {
+    let mode =
+        match mode {
+            SyncMode::Auto => 0,
+            SyncMode::Now => 1,
+            SyncMode::No => 2,
+        };
+
+    SYNC_MODE.store(mode, Ordering::Relaxed);
+}
+
+fn sync_mode() -> SyncMode {
+    match SYNC_MODE.load(Ordering::Relaxed) {
+        1 => SyncMode::Now,
+        2 => SyncMode::No,
+        _ => SyncMode::Auto,
+    }
+}
+
+/// Load the saved session, if any, and configure it
+pub fn load_session() -> Result<Option<Session>> {
+    let mut session = try!(Session::load());
+
+    if let Some(ref mut s) = session {
+        s.set_http_config(http_config());
+        s.set_sync_mode(sync_mode());
+    }
+
+    Ok(session)
+}
+
+/// Build the HTTP configuration from the environment
+pub fn http_config() -> HttpConfig {
+    let mut config = HttpConfig::default();
+
+    // Escape hatch for users behind TLS-inspecting proxies. This is
+    // insecure, see `HttpConfig::disable_pinning`, so it has to be
+    // asked for explicitly.
+    match env::var("LPASS_DISABLE_CERT_PIN") {
+        Ok(ref v) if v == "1" || v == "true" =>
+            config.disable_pinning = true,
+        Ok(v) => warn!("Ignoring LPASS_DISABLE_CERT_PIN '{}', set it to 1 \
+                        to disable the certificate pinning", v),
+        Err(_) => (),
+    }
+
+    if let Ok(t) = env::var("LPASS_HTTP_TIMEOUT") {
+        match u64::from_str(&t) {
+            Ok(t) => config.timeout = Duration::from_secs(t),
+            Err(_) => warn!("Invalid LPASS_HTTP_TIMEOUT '{}'", t),
+        }
+    }
+
+    config
+}
+
+/// Start the agent with `session`'s crypto key. Failing to start the
+/// agent is not fatal, we'll just have to ask for the password again
+/// next time.
+pub fn start_agent(session: &Session) {
+    if let Some(key) = session.crypto_key() {
+        if let Err(e) = agent::start(key) {
+            warn!("Couldn't start the agent: {}", e);
+        }
+    }
+}
+
+/// Find the account matching `query`, which can be either an account
+/// ID or a path (optionally including the group). If the query is
+/// ambiguous the matching accounts are listed and `BadUsage` is
+/// returned.
+pub fn find_account<'a>(vault: &'a Vault,
+                        query: &str) -> Result<&'a Account> {
+    let matches = vault.matches(query);
+
+    match matches.len() {
+        0 => {
+            println!("Could not find specified account '{}'", query);
+            Err(Error::BadUsage)
+        }
+        1 => Ok(matches[0]),
+        _ => {
+            println!("Multiple matches found:");
+
+            for a in matches {
+                println!("{} [id: {}]", a.fullname, a.id);
+            }
+
+            Err(Error::BadUsage)
+        }
+    }
+}
+
+/// Prefix selecting a custom form field, for instance
+/// "custom:password" for a form field called "password"
+const CUSTOM_FIELD_PREFIX: &'static str = "custom:";
+
+/// Return the name of the custom form field designated by `field`:
+/// either prefixed with `CUSTOM_FIELD_PREFIX` or any name which isn't
+/// one of the standard fields. Returns `None` for a standard field.
+pub fn custom_field_name(field: &str) -> Option<&str> {
+    if field.starts_with(CUSTOM_FIELD_PREFIX) {
+        return Some(&field[CUSTOM_FIELD_PREFIX.len()..]);
+    }
+
+    match field {
+        "name" | "username" | "url" | "password" | "notes" => None,
+        _ => Some(field),
+    }
+}
+
+/// Return the value of `field` ("name", "username", "password", "url",
+/// "notes" or the name of a custom form field, see
+/// `custom_field_name`) in `account`
+pub fn get_field<'a>(account: &'a Account, field: &str) -> Result<&'a [u8]> {
+    if let Some(name) = custom_field_name(field) {
+        return match account.field(name) {
+            Some(f) => Ok(&f.value),
+            None => {
+                println!("Unknown field '{}'", field);
+                Err(Error::BadUsage)
+            }
+        };
+    }
+
+    match field {
+        "name" => Ok(account.fullname.as_bytes()),
+        "username" => Ok(account.username.as_bytes()),
+        "url" => Ok(account.url.as_bytes()),
+        "password" => Ok(&account.password),
+        "notes" => Ok(&account.note),
+        _ => unreachable!(),
+    }
+}
+
+/// Set `field` in `account` to `value`. See `get_field` for the list
+/// of fields. Missing custom form fields are added to the account,
+/// secure notes can't have any.
+pub fn set_field(account: &mut Account,
+                 field: &str,
+                 value: SecureStorage) -> Result<()> {
+    if let Some(name) = custom_field_name(field) {
+        return set_custom_field(account, name, value);
+    }
+
+    let string = || String::from_utf8_lossy(&value).into_owned();
+
+    match field {
+        "name" => account.set_fullname(&string()),
+        "username" => account.username = string(),
+        "url" => account.url = string(),
+        "password" => account.password = value,
+        "notes" => account.note = value,
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn set_custom_field(account: &mut Account,
+                    name: &str,
+                    value: SecureStorage) -> Result<()> {
+    if name.is_empty() {
+        println!("Invalid empty field name");
+        return Err(Error::BadUsage);
+    }
+
+    if account.is_secure_note() {
+        println!("Unknown field '{}', secure notes can't have custom \
+                  fields", name);
+        return Err(Error::BadUsage);
+    }
+
+    match account.fields.iter_mut().find(|f| f.name == name) {
+        Some(f) => {
+            f.value = value;
+            return Ok(());
+        }
+        None => (),
+    }
+
+    account.fields.push(Field::new(name, value));
+
+    Ok(())
+}
+
+/// Set `field` in `account` from the contents of stdin. Only the
+/// first line is used except for the notes.
+pub fn read_field(account: &mut Account, field: &str) -> Result<()> {
+    let mut data = try!(SecureStorage::with_capacity(256));
+
+    {
+        let stdin = io::stdin();
+
+        for b in stdin.lock().bytes() {
+            try!(data.push(try!(b)));
+        }
+    }
+
+    if field != "notes" {
+        let end = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+
+        data.truncate(end);
+    }
+
+    set_field(account, field, data)
+}
+
+/// Describe how long before `now` `time` is in a human-friendly way,
+/// for instance "3 days ago"
+pub fn relative_time(time: SystemTime, now: SystemTime) -> String {
+    // The clocks of the server and of this machine can disagree a
+    // little
+    let secs =
+        match now.duration_since(time) {
+            Ok(d) => d.as_secs(),
+            Err(_) => 0,
+        };
+
+    let units: &[(u64, &str)] = &[
+        (365 * 24 * 3600, "year"),
+        (30 * 24 * 3600, "month"),
+        (24 * 3600, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+
+    for &(len, name) in units {
+        let n = secs / len;
+
+        if n > 0 {
+            let plural = if n > 1 { "s" } else { "" };
+
+            return format!("{} {}{} ago", n, name, plural);
+        }
+    }
+
+    "just now".to_owned()
+}
+
+#[test]
+fn test_relative_time() {
+    let now = SystemTime::now();
+    let ago = |secs| relative_time(now - Duration::from_secs(secs), now);
+
+    assert_eq!(ago(0), "just now");
+    assert_eq!(ago(59), "just now");
+    assert_eq!(ago(60), "1 minute ago");
+    assert_eq!(ago(3 * 3600 + 1800), "3 hours ago");
+    assert_eq!(ago(24 * 3600), "1 day ago");
+    assert_eq!(ago(45 * 24 * 3600), "1 month ago");
+    assert_eq!(ago(800 * 24 * 3600), "2 years ago");
+
+    assert_eq!(relative_time(now + Duration::from_secs(30), now),
+               "just now");
+}
+
+#[test]
+fn test_custom_fields() {
+    assert_eq!(custom_field_name("password"), None);
+    assert_eq!(custom_field_name("custom:password"), Some("password"));
+    assert_eq!(custom_field_name("apikey"), Some("apikey"));
+
+    let value = |v: &[u8]| SecureStorage::from_slice(v).unwrap();
+
+    let mut account = Account::new("site");
+
+    set_field(&mut account, "password", value(b"hunter2")).unwrap();
+    set_field(&mut account, "custom:password", value(b"1234")).unwrap();
+    set_field(&mut account, "apikey", value(b"abc")).unwrap();
+    set_field(&mut account, "apikey", value(b"def")).unwrap();
+
+    assert!(account.password.ct_eq(b"hunter2"));
+    assert_eq!(account.fields.len(), 2);
+    assert_eq!(get_field(&account, "custom:password").unwrap(), b"1234");
+    assert_eq!(get_field(&account, "apikey").unwrap(), b"def");
+    assert!(get_field(&account, "missing").is_err());
+    assert!(set_field(&mut account, "custom:", value(b"x")).is_err());
+
+    let mut note = Account::new("note");
+
+    note.url = "http://sn".to_owned();
+
+    assert!(set_field(&mut note, "apikey", value(b"abc")).is_err());
+}
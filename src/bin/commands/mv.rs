@@ -0,0 +1,215 @@
+use lpass::{Result, Error, Vault};
+#[cfg(test)]
+use lpass::Account;
+
+use CommandOption;
+
+use commands::{open_session, find_account, with_relogin};
+
+use getopts::Matches;
+
+pub const MV_COMMAND: ::Command = ::Command {
+    name: "mv",
+    options: &[
+        CommandOption {
+            short_name: "f",
+            long_name: "force",
+            description: "move even if an account with the same name \
+                          exists in the destination",
+            argument: None,
+        },
+    ],
+    free_args: "UNIQUENAME|UNIQUEID|FOLDER/ DESTINATION",
+    command: mv,
+};
+
+/// Move or rename an account or a whole folder. When moving an
+/// account into an existing folder (or if `DESTINATION` ends with a
+/// slash) the account keeps its name, otherwise `DESTINATION` is its
+/// new full name. When moving a folder `DESTINATION` is its new path.
+pub fn mv(options: &Matches) -> Result<()> {
+    let force = options.opt_present("f");
+
+    let (query, dest) =
+        match (options.free.get(0), options.free.get(1)) {
+            (Some(q), Some(d)) => (q, d),
+            _ => {
+                println!("Missing UNIQUENAME|UNIQUEID|FOLDER/ or DESTINATION");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    let folder = query.trim_right_matches('/');
+
+    let is_folder =
+        (query.ends_with('/') || vault.matches(query).is_empty()) &&
+        vault.folders().contains(&folder);
+
+    // List of (ID, new full name) of the accounts to move
+    let moves =
+        if is_folder {
+            try!(folder_moves(&vault, folder, dest))
+        } else {
+            try!(account_moves(&vault, query, dest))
+        };
+
+    try!(check_collisions(&vault, &moves, force));
+
+    for mut account in vault.into_accounts() {
+        let fullname =
+            match moves.iter().find(|&&(ref id, _)| *id == account.id) {
+                Some(&(_, ref f)) => f,
+                None => continue,
+            };
+
+        let old = account.fullname.clone();
+
+        account.set_fullname(fullname);
+
+        try!(with_relogin(&mut session, |s| s.update_account(&account)));
+
+        println!("Moved {} to {} [id: {}]", old, fullname, account.id);
+    }
+
+    Ok(())
+}
+
+fn account_moves(vault: &Vault,
+                 query: &str,
+                 dest: &str) -> Result<Vec<(String, String)>> {
+    let account = try!(find_account(vault, query));
+
+    let dest_folder = dest.trim_right_matches('/');
+
+    let fullname =
+        if dest.ends_with('/') || vault.folders().contains(&dest_folder) {
+            if dest_folder.is_empty() {
+                account.name.clone()
+            } else {
+                format!("{}/{}", dest_folder, account.name)
+            }
+        } else {
+            dest.to_owned()
+        };
+
+    Ok(vec![(account.id.clone(), fullname)])
+}
+
+fn folder_moves(vault: &Vault,
+                folder: &str,
+                dest: &str) -> Result<Vec<(String, String)>> {
+    let dest = dest.trim_right_matches('/');
+
+    if dest.is_empty() {
+        println!("Invalid destination folder");
+        return Err(Error::BadUsage);
+    }
+
+    if dest == folder || dest.starts_with(&format!("{}/", folder)) {
+        println!("Can't move folder '{}' into itself", folder);
+        return Err(Error::BadUsage);
+    }
+
+    let mut moves = Vec::new();
+
+    for a in vault.accounts() {
+        let group = &*a.group;
+
+        // Only rewrite whole path components: moving "web" shouldn't
+        // touch "website"
+        let in_folder =
+            group == folder ||
+            (group.starts_with(folder) &&
+             group.as_bytes()[folder.len()] == b'/');
+
+        if in_folder {
+            let group = format!("{}{}", dest, &group[folder.len()..]);
+
+            moves.push((a.id.clone(), format!("{}/{}", group, a.name)));
+        }
+    }
+
+    Ok(moves)
+}
+
+/// Make sure that none of the `moves` would result in two accounts
+/// with the same full name unless `force` is set
+fn check_collisions(vault: &Vault,
+                    moves: &[(String, String)],
+                    force: bool) -> Result<()> {
+    let mut collision = false;
+
+    for &(_, ref fullname) in moves {
+        let conflicts =
+            vault.accounts().iter()
+            .filter(|a| !a.is_group())
+            // Accounts being moved away don't conflict
+            .filter(|a| moves.iter().all(|&(ref i, _)| *i != a.id))
+            .filter(|a| a.fullname == *fullname);
+
+        for a in conflicts {
+            println!("{} already exists [id: {}]", a.fullname, a.id);
+            collision = true;
+        }
+    }
+
+    if collision && !force {
+        println!("Use --force to move anyway");
+        return Err(Error::BadUsage);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_moves() {
+    let entries = [
+        ("1", "web/mail"),
+        ("2", "web/social/forum"),
+        ("3", "website/blog"),
+        ("4", "archive/mail"),
+        ("5", "archive/"),
+    ];
+
+    let accounts =
+        entries.iter().map(|&(id, fullname)| {
+            let mut a = Account::new(fullname);
+            a.id = id.to_owned();
+            if a.name.is_empty() {
+                a.url = "http://group".to_owned();
+            }
+            a
+        }).collect();
+
+    let vault = Vault::new(accounts);
+
+    let moves = folder_moves(&vault, "web", "archive/web/").unwrap();
+
+    assert_eq!(moves,
+               vec![("1".to_owned(), "archive/web/mail".to_owned()),
+                    ("2".to_owned(), "archive/web/social/forum".to_owned())]);
+
+    assert!(folder_moves(&vault, "web", "web/sub").is_err());
+    assert!(folder_moves(&vault, "web", "").is_err());
+
+    // Moving into an existing folder keeps the name
+    let moves = account_moves(&vault, "web/mail", "archive").unwrap();
+
+    assert_eq!(moves, vec![("1".to_owned(), "archive/mail".to_owned())]);
+    assert!(check_collisions(&vault, &moves, false).is_err());
+    assert!(check_collisions(&vault, &moves, true).is_ok());
+
+    let moves = account_moves(&vault, "web/mail", "/").unwrap();
+
+    assert_eq!(moves, vec![("1".to_owned(), "mail".to_owned())]);
+
+    // Otherwise it's a rename
+    let moves = account_moves(&vault, "blog", "website/news").unwrap();
+
+    assert_eq!(moves, vec![("3".to_owned(), "website/news".to_owned())]);
+    assert!(check_collisions(&vault, &moves, false).is_ok());
+}
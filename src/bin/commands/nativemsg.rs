@@ -0,0 +1,369 @@
+/// Native messaging host for browser extensions
+///
+/// Chrome and Firefox start the host with the caller's identity as
+/// arguments and exchange messages on stdin/stdout, each made of a
+/// 4-byte little endian length followed by a JSON object. The browser
+/// manifest must point to a wrapper script since it can't pass the
+/// command name:
+///
+/// ```text
+/// #!/bin/sh
+/// exec lpass nativemsg "$@"
+/// ```
+///
+/// The supported requests are:
+///
+/// - `{"type": "status"}`
+/// - `{"type": "get_logins_for_url", "url": "https://..."}`, the
+///   passwords are not included
+/// - `{"type": "get_password", "id": "<account ID>"}`
+///
+/// Every request must also contain a `"token"` field holding the
+/// pairing token of the extension in hex. The token is derived from
+/// the decryption key and the extension ID, it is displayed by
+/// `lpass nativemsg --pair ORIGIN|EXTENSION-ID` and must be entered
+/// in the extension's settings. Each request is checked by the agent,
+/// which holds the key, so that only the paired extensions are
+/// answered. This doesn't protect against the other processes of the
+/// same user: they're trusted, like for every other command, since
+/// they can ask the agent for the key itself. The master password is
+/// never prompted for: if the agent isn't running the requests are
+/// rejected.
+
+use lpass::{Result, Error, SecureStorage, Session, Vault};
+use lpass::cipher;
+use lpass::kdf;
+
+use CommandOption;
+
+use agent;
+use json;
+use commands::{load_session, open_session};
+
+use getopts::Matches;
+
+use std::io;
+use std::io::{Read, Write};
+use std::process;
+
+pub const NATIVEMSG_COMMAND: ::Command = ::Command {
+    name: "nativemsg",
+    options: &[
+        CommandOption {
+            short_name: "",
+            long_name: "pair",
+            description: "display the pairing token of the extension",
+            argument: None,
+        },
+    ],
+    free_args: "ORIGIN|MANIFEST [EXTENSION-ID]",
+    command: nativemsg,
+};
+
+/// Maximum length of a request. They're tiny, this only protects
+/// against garbage.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+pub fn nativemsg(options: &Matches) -> Result<()> {
+    if options.opt_present("pair") {
+        return match options.free.get(0) {
+            Some(e) => pair(e),
+            None => {
+                println!("Missing extension origin or ID");
+                Err(Error::BadUsage)
+            }
+        };
+    }
+
+    // Chrome passes the origin of the extension
+    // ("chrome-extension://ID/"), Firefox the path to the host
+    // manifest followed by the ID of the extension
+    let caller =
+        match (options.free.get(0), options.free.get(1)) {
+            (Some(o), _) if o.starts_with("chrome-extension://") => o,
+            (_, Some(id)) => id,
+            _ => {
+                println!("Missing caller origin or extension ID");
+                return Err(Error::BadUsage);
+            }
+        };
+
+    // From now on stdout belongs to the browser, errors are only
+    // logged
+    if let Err(e) = serve(caller) {
+        error!("Native messaging host failed: {}", e);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Display the pairing token of `extension`
+fn pair(extension: &str) -> Result<()> {
+    let session = try!(open_session());
+
+    let key =
+        match session.crypto_key() {
+            Some(k) => k,
+            None => return Err(Error::NotAuthenticated),
+        };
+
+    let token = try!(kdf::pairing_token(key, extension_id(extension)));
+
+    let hex: String = token.iter().map(|b| format!("{:02x}", b)).collect();
+
+    println!("{}", hex);
+
+    Ok(())
+}
+
+/// Normalize the identity of an extension, the trailing slash of the
+/// Chrome origins is optional
+fn extension_id(caller: &str) -> &str {
+    caller.trim_right_matches('/')
+}
+
+/// Answer the requests of `caller` until stdin is closed
+fn serve(caller: &str) -> Result<()> {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let caller = extension_id(caller);
+
+    while let Some(request) = try!(read_message(&mut stdin)) {
+        let mut response = try!(SecureStorage::with_capacity(256));
+
+        let res =
+            authenticate(caller, &request)
+            .and_then(|_| handle_request(&request, &mut response));
+
+        if let Err(e) = res {
+            response.clear();
+
+            try!(json::write_object(&mut response, &[
+                ("type", b"error"),
+                ("message", e.to_string().as_bytes()),
+            ]));
+        }
+
+        try!(write_message(&mut stdout, &response));
+    }
+
+    Ok(())
+}
+
+/// Have the agent check the pairing token of `request`
+fn authenticate(caller: &str, request: &[u8]) -> Result<()> {
+    let fields = try!(json::parse_object(request));
+
+    let token =
+        match fields.iter().find(|&&(ref n, _)| n == "token") {
+            Some(&(_, ref t)) => try!(cipher::hex_decode(t.as_bytes())),
+            None => SecureStorage::empty(),
+        };
+
+    if agent::verify_pairing(caller, &token) {
+        Ok(())
+    } else {
+        let err = format!("Invalid pairing token for {}, run `lpass \
+                           nativemsg --pair {}` to get it (the vault \
+                           must be unlocked)", caller, caller);
+
+        Err(Error::Unsupported(err))
+    }
+}
+
+fn handle_request(request: &[u8], response: &mut SecureStorage) -> Result<()> {
+    let fields = try!(json::parse_object(request));
+
+    let get = |name: &str| -> Result<&str> {
+        match fields.iter().find(|&&(ref n, _)| n == name) {
+            Some(&(_, ref v)) => Ok(v),
+            None => {
+                let err = format!("Missing '{}' in the request", name);
+                Err(Error::BadProtocol(err))
+            }
+        }
+    };
+
+    match try!(get("type")) {
+        "status" => status(response),
+        "get_logins_for_url" => {
+            let vault = try!(load_vault());
+
+            try!(response.write_all(b"{\"type\":\"logins\",\"logins\":["));
+
+            let logins = vault.find_by_url(try!(get("url")));
+
+            for (i, a) in logins.iter().enumerate() {
+                if i > 0 {
+                    try!(response.write_all(b","));
+                }
+
+                try!(json::write_object(response, &[
+                    ("id", a.id.as_bytes()),
+                    ("name", a.fullname.as_bytes()),
+                    ("username", a.username.as_bytes()),
+                    ("url", a.url.as_bytes()),
+                ]));
+            }
+
+            try!(response.write_all(b"]}"));
+
+            Ok(())
+        }
+        "get_password" => {
+            let mut session = try!(unlocked_session());
+            let id = try!(get("id"));
+
+            // Stop decrypting the vault as soon as the account is found
+            let found =
+                try!(session.accounts_iter())
+                .find(|a| a.as_ref().map(|a| a.id == id).unwrap_or(true));
+
+            let account =
+                match found {
+                    Some(a) => try!(a),
+                    None => {
+                        let err = format!("No account with ID {}", id);
+                        return Err(Error::Unsupported(err));
+                    }
+                };
+
+            try!(json::write_object(response, &[
+                ("type", b"password"),
+                ("id", account.id.as_bytes()),
+                ("password", &account.password),
+            ]));
+
+            Ok(())
+        }
+        t => Err(Error::Unsupported(format!("Unknown request '{}'", t))),
+    }
+}
+
+fn status(response: &mut SecureStorage) -> Result<()> {
+    let session = try!(load_session());
+
+    let locked = agent::get_key().is_none();
+
+    match session {
+        Some(s) => {
+            try!(write!(response,
+                        "{{\"type\":\"status\",\"logged_in\":true,\
+                         \"locked\":{},\"username\":",
+                        locked));
+            try!(json::write_string(response, s.username().as_bytes()));
+            try!(response.write_all(b"}"));
+        }
+        None => {
+            try!(response.write_all(b"{\"type\":\"status\",\
+                                       \"logged_in\":false}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the vault with the key held by the agent, see
+/// `unlocked_session`
+fn load_vault() -> Result<Vault> {
+    let mut session = try!(unlocked_session());
+
+    session.get_vault()
+}
+
+/// Load the session with the key held by the agent. We can't prompt
+/// for the master password, stdin belongs to the browser.
+fn unlocked_session() -> Result<Session> {
+    let mut session =
+        match try!(load_session()) {
+            Some(s) => s,
+            None => return Err(Error::NotAuthenticated),
+        };
+
+    match agent::get_key() {
+        Some(key) => session.set_crypto_key(key),
+        None => {
+            let err = "Vault is locked, run any lpass command to unlock \
+                       it".to_owned();
+            return Err(Error::Unsupported(err));
+        }
+    }
+
+    Ok(session)
+}
+
+/// Read a message from `r`. Returns `None` if the browser closed the
+/// stream.
+fn read_message<R: Read>(r: &mut R) -> Result<Option<SecureStorage>> {
+    let mut header = [0; 4];
+
+    match r.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+            return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len =
+        (header[0] as usize) |
+        (header[1] as usize) << 8 |
+        (header[2] as usize) << 16 |
+        (header[3] as usize) << 24;
+
+    if len > MAX_REQUEST_LEN {
+        let err = format!("Request too long ({} bytes)", len);
+        return Err(Error::BadProtocol(err));
+    }
+
+    let mut message = try!(SecureStorage::from_vec(vec![0; len]));
+
+    try!(r.read_exact(&mut message));
+
+    Ok(Some(message))
+}
+
+fn write_message<W: Write>(w: &mut W, message: &[u8]) -> Result<()> {
+    let len = message.len();
+
+    let header = [
+        len as u8,
+        (len >> 8) as u8,
+        (len >> 16) as u8,
+        (len >> 24) as u8,
+    ];
+
+    try!(w.write_all(&header));
+    try!(w.write_all(message));
+    try!(w.flush());
+
+    Ok(())
+}
+
+#[test]
+fn test_messages() {
+    let mut stream = Vec::new();
+
+    write_message(&mut stream, b"{\"type\":\"status\"}").unwrap();
+    write_message(&mut stream, b"").unwrap();
+
+    assert_eq!(&stream[..4], &[17, 0, 0, 0]);
+
+    let mut r = io::Cursor::new(stream);
+
+    assert_eq!(&read_message(&mut r).unwrap().unwrap()[..],
+               b"{\"type\":\"status\"}");
+    assert!(read_message(&mut r).unwrap().unwrap().is_empty());
+    assert!(read_message(&mut r).unwrap().is_none());
+
+    let mut r = io::Cursor::new(vec![0xff, 0xff, 0xff, 0x7f, b'{']);
+
+    assert!(read_message(&mut r).is_err());
+
+    assert_eq!(extension_id("chrome-extension://abcdef/"),
+               "chrome-extension://abcdef");
+    assert_eq!(extension_id("lpass@example.com"), "lpass@example.com");
+}
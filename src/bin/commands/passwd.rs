@@ -0,0 +1,106 @@
+use lpass::{Result, Error, SecureStorage};
+
+use terminal::{ask_yes_no_always, color, Color};
+use password;
+use agent;
+use commands::{load_session, login, start_agent};
+
+use getopts::Matches;
+
+use std::io;
+use std::io::Write;
+
+pub const PASSWD_COMMAND: ::Command = ::Command {
+    name: "passwd",
+    options: &[],
+    free_args: "",
+    command: passwd,
+};
+
+pub fn passwd(_: &Matches) -> Result<()> {
+    let mut session =
+        match try!(load_session()) {
+            Some(s) => s,
+            None => return Err(Error::NotAuthenticated),
+        };
+
+    let desc = format!("Please enter the current master password for <{}>",
+                       session.username());
+
+    let password = try!(password::prompt("Master password", &desc, None));
+
+    let new_password = try!(prompt_new_password(session.username()));
+
+    if new_password.ct_eq(&password) {
+        println!("The new password is the same as the current one");
+        return Err(Error::BadUsage);
+    }
+
+    try!(ask_yes_no_always(false,
+                           "Changing the master password re-encrypts the \
+                            whole vault and can take a while, your other \
+                            sessions will be logged out. Continue? "));
+
+    // Needed to log in again once the password is changed
+    let login_password = try!(SecureStorage::from_slice(&new_password));
+
+    {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        try!(session.change_password(password, new_password, |done, total| {
+            let _ = write!(stdout, "\rRe-encrypting the vault: {}/{}",
+                           done, total);
+            let _ = stdout.flush();
+        }));
+
+        try!(writeln!(stdout, ""));
+    }
+
+    // The agent holds the old key
+    agent::kill();
+
+    println!("{}Master password changed{}, logging in again",
+             color(Color::FgGreen),
+             color(Color::Reset));
+
+    if let Err(e) = session.relogin(login_password, login::otp_query) {
+        println!("Couldn't log in with the new password ({}), please \
+                  run `lpass login`", e);
+
+        return Err(e);
+    }
+
+    start_agent(&session);
+
+    Ok(())
+}
+
+/// Ask for the new master password twice until both match
+fn prompt_new_password(username: &str) -> Result<SecureStorage> {
+    let desc = format!("Please enter the new master password for <{}>",
+                       username);
+
+    let mut error = None;
+
+    loop {
+        let password =
+            try!(password::prompt("New master password", &desc, error));
+
+        if password.is_empty() {
+            error = Some("The master password can't be empty");
+            continue;
+        }
+
+        let confirm =
+            try!(password::prompt("New master password",
+                                  "Please retype the new master password",
+                                  None));
+
+        if confirm.ct_eq(&password) {
+            return Ok(password);
+        }
+
+        error = Some("The passwords don't match, try again");
+    }
+}
@@ -0,0 +1,57 @@
+use lpass::{Result, Error};
+
+use CommandOption;
+
+use terminal::ask_yes_no;
+use commands::{open_session, find_account, with_relogin};
+
+use getopts::Matches;
+
+pub const RM_COMMAND: ::Command = ::Command {
+    name: "rm",
+    options: &[
+        CommandOption {
+            short_name: "f",
+            long_name: "force",
+            description: "don't ask for confirmation",
+            argument: None,
+        },
+    ],
+    free_args: "UNIQUENAME|UNIQUEID",
+    command: rm,
+};
+
+pub fn rm(options: &Matches) -> Result<()> {
+    let force = options.opt_present("f");
+
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing UNIQUENAME|UNIQUEID");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    // Fails with BadUsage if the account doesn't exist so we don't
+    // send bogus requests to the server
+    let account = try!(find_account(&vault, query));
+
+    if !force {
+        let prompt = format!("Are you sure you would like to delete {} \
+                              [id: {}]? ",
+                             account.fullname, account.id);
+
+        try!(ask_yes_no(false, &prompt));
+    }
+
+    try!(with_relogin(&mut session, |s| s.delete_account(&account.id)));
+
+    println!("Removed {} [id: {}]", account.fullname, account.id);
+
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use lpass::{Result, Error};
+
+use CommandOption;
+
+use commands::{open_session, with_relogin};
+use commands::ls::print_account;
+
+use getopts::Matches;
+
+pub const SEARCH_COMMAND: ::Command = ::Command {
+    name: "search",
+    options: &[
+        CommandOption {
+            short_name: "l",
+            long_name: "long",
+            description: "also display the username and modification time",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "G",
+            long_name: "basic-regexp",
+            description: "interpret QUERY as a regular expression",
+            argument: None,
+        },
+    ],
+    free_args: "QUERY",
+    command: search,
+};
+
+/// Predicate deciding if a string matches the query
+type Matcher = Box<Fn(&str) -> bool>;
+
+pub fn search(options: &Matches) -> Result<()> {
+    let long = options.opt_present("l");
+
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing QUERY");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let matches = try!(matcher(query, options.opt_present("G")));
+
+    let mut session = try!(open_session());
+
+    let mut accounts: Vec<_> =
+        try!(with_relogin(&mut session, |s| s.get_accounts()))
+        .into_iter()
+        .filter(|a| !a.is_group())
+        .filter(|a| {
+            [&a.fullname, &a.url, &a.username].iter().any(|f| matches(f))
+        })
+        .collect();
+
+    // Same order as `ls`
+    accounts.sort_by(|a, b| {
+        (&a.group, &a.name).cmp(&(&b.group, &b.name))
+    });
+
+    for a in &accounts {
+        print_account(a, long, a.last_modified);
+    }
+
+    Ok(())
+}
+
+/// Build the `Matcher` for `query`: a case-insensitive substring
+/// search or, if `regex` is true, a case-insensitive regular
+/// expression
+fn matcher(query: &str, regex: bool) -> Result<Matcher> {
+    if regex {
+        return regex_matcher(query);
+    }
+
+    let query = query.to_lowercase();
+
+    Ok(Box::new(move |s: &str| s.to_lowercase().contains(&query)))
+}
+
+#[cfg(feature = "regex")]
+fn regex_matcher(query: &str) -> Result<Matcher> {
+    use regex::RegexBuilder;
+
+    match RegexBuilder::new(query).case_insensitive(true).build() {
+        Ok(re) => Ok(Box::new(move |s: &str| re.is_match(s))),
+        Err(e) => {
+            println!("Invalid regular expression: {}", e);
+            Err(Error::BadUsage)
+        }
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_matcher(_: &str) -> Result<Matcher> {
+    let err = "lpass was built without regular expression support, \
+               rebuild it with the `regex` feature".to_owned();
+
+    Err(Error::Unsupported(err))
+}
+
+#[test]
+fn test_matcher() {
+    let m = matcher("Mail", false).unwrap();
+
+    assert!(m("Web/GMail"));
+    assert!(m("https://mail.example.com/"));
+    assert!(!m("Web/M.ail"));
+
+    let m = matcher("", false).unwrap();
+
+    assert!(m("anything"));
+
+    if cfg!(feature = "regex") {
+        let m = matcher("^web/.*mail$", true).unwrap();
+
+        assert!(m("Web/GMail"));
+        assert!(!m("Web/GMail account"));
+
+        assert!(matcher("(", true).is_err());
+    } else {
+        assert!(matcher("mail", true).is_err());
+    }
+}
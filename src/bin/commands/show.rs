@@ -0,0 +1,508 @@
+use lpass::{Result, Error, Account, SecureStorage, Vault};
+
+use CommandOption;
+
+use terminal::{color, Color};
+use clipboard;
+use commands::{open_session, find_account, get_field, with_relogin};
+use commands::relative_time;
+use json;
+
+use getopts::Matches;
+
+use std::io;
+use std::io::Write;
+use std::time::SystemTime;
+
+pub const SHOW_COMMAND: ::Command = ::Command {
+    name: "show",
+    options: &[
+        CommandOption {
+            short_name: "",
+            long_name: "username",
+            description: "only display the username",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "password",
+            description: "only display the password",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "url",
+            description: "only display the URL",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "notes",
+            description: "only display the notes",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "field",
+            description: "only display the custom field NAME",
+            argument: Some("NAME"),
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "format",
+            description: "display the account using FORMAT, for instance \
+                          '{username}:{password}'",
+            argument: Some("FORMAT"),
+        },
+        CommandOption {
+            short_name: "j",
+            long_name: "json",
+            description: "display the account as JSON",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "c",
+            long_name: "clip",
+            description: "copy the selected field (the password by \
+                          default) to the clipboard instead of \
+                          displaying it",
+            argument: None,
+        },
+        CommandOption {
+            short_name: "",
+            long_name: "ignore-missing",
+            description: "when showing several accounts, carry on if one \
+                          can't be found",
+            argument: None,
+        },
+    ],
+    free_args: "UNIQUENAME|UNIQUEID...",
+    command: show,
+};
+
+pub fn show(options: &Matches) -> Result<()> {
+    let query =
+        match options.free.get(0) {
+            Some(q) => q,
+            None => {
+                println!("Missing UNIQUENAME|UNIQUEID");
+                return Err(Error::BadUsage)
+            }
+        };
+
+    let batch = options.free.len() > 1;
+
+    if batch {
+        if options.opt_present("c") {
+            println!("--clip can only be used with a single account");
+            return Err(Error::BadUsage);
+        }
+
+        if !options.opt_present("j") && !options.opt_present("format") {
+            println!("Showing several accounts requires --json or --format");
+            return Err(Error::BadUsage);
+        }
+    }
+
+    // Parse the template before anything else so that mistakes are
+    // reported without having to unlock the vault
+    let format =
+        match options.opt_str("format") {
+            Some(f) => Some(try!(parse_format(&f))),
+            None => None,
+        };
+
+    let mut session = try!(open_session());
+
+    let vault = try!(with_relogin(&mut session, |s| s.get_vault()));
+
+    if batch {
+        return show_batch(&vault,
+                          &options.free,
+                          &format,
+                          options.opt_present("ignore-missing"));
+    }
+
+    let account = try!(find_account(&vault, query));
+
+    let output = try!(raw_output(options, &format, account));
+
+    if options.opt_present("c") {
+        let value =
+            match output {
+                Some(ref o) => o,
+                None => &account.password,
+            };
+
+        return clipboard::copy(value);
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    if let Some(output) = output {
+        // Only output the raw values so that they can be piped into
+        // other commands
+        try!(stdout.write_all(&output));
+        try!(stdout.write_all(b"\n"));
+
+        return Ok(());
+    }
+
+    let fields = display_fields(account);
+
+    try!(writeln!(stdout, "{}{}{} [id: {}]",
+                  color(Color::Bold),
+                  account.fullname,
+                  color(Color::Reset),
+                  account.id));
+
+    let note_fields = try!(account.note_fields());
+
+    for &(opt, label, value) in &fields {
+        match (opt, &note_fields) {
+            // The URL of secure notes is just a marker
+            ("url", _) if account.is_secure_note() => (),
+            ("notes", &Some(ref note_fields)) =>
+                for &(ref key, ref value) in note_fields {
+                    try!(write_field(&mut stdout, key, value));
+                },
+            _ => try!(write_field(&mut stdout, label, value)),
+        }
+    }
+
+    for f in &account.fields {
+        try!(write_field(&mut stdout, &f.name, &f.value));
+    }
+
+    // Downloaded with `lpass attach`
+    for a in &account.attachments {
+        let label = format!("att-{}", a.id);
+
+        try!(write_field(&mut stdout, &label, a.filename.as_bytes()));
+    }
+
+    let now = SystemTime::now();
+
+    let times = [
+        ("Last modified", account.last_modified),
+        ("Last accessed", account.last_touch),
+    ];
+
+    for &(label, time) in &times {
+        if let Some(t) = time {
+            let age = relative_time(t, now);
+
+            try!(write_field(&mut stdout, label, age.as_bytes()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Display the accounts matching `queries` one per line, either as
+/// JSON or using `format`. The lines are in the same order as the
+/// queries, entries that can't be found are reported in place.
+fn show_batch(vault: &Vault,
+              queries: &[String],
+              format: &Option<Vec<Piece>>,
+              ignore_missing: bool) -> Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for query in queries {
+        let found =
+            match lookup(vault, query) {
+                Ok(account) => {
+                    match format {
+                        &Some(ref format) =>
+                            try!(write_format(&mut stdout, format, account)),
+                        &None =>
+                            try!(json::write_account_line(&mut stdout,
+                                                          account)),
+                    }
+
+                    true
+                }
+                Err(msg) => {
+                    match format {
+                        &None =>
+                            try!(json::write_object(&mut stdout, &[
+                                ("query", query.as_bytes()),
+                                ("error", msg.as_bytes()),
+                            ])),
+                        // Leave an empty line on stdout so that the
+                        // output stays aligned with the queries
+                        &Some(_) =>
+                            try!(writeln!(io::stderr(), "{}", msg)),
+                    }
+
+                    false
+                }
+            };
+
+        try!(stdout.write_all(b"\n"));
+
+        if !found && !ignore_missing {
+            return Err(Error::BadUsage);
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the account matching `query` without printing anything,
+/// returns an error message if there isn't exactly one
+fn lookup<'a>(vault: &'a Vault,
+              query: &str) -> ::std::result::Result<&'a Account, String> {
+    let matches = vault.matches(query);
+
+    match matches.len() {
+        0 => Err(format!("Could not find specified account '{}'", query)),
+        1 => Ok(matches[0]),
+        n => Err(format!("Multiple matches found for '{}' ({} accounts)",
+                         query, n)),
+    }
+}
+
+/// Build the output selected by the options, if any. Returns `None`
+/// if the whole account should be displayed.
+fn raw_output(options: &Matches,
+              format: &Option<Vec<Piece>>,
+              account: &Account) -> Result<Option<SecureStorage>> {
+    let mut output = try!(SecureStorage::with_capacity(256));
+
+    if options.opt_present("j") {
+        try!(json::write_account(&mut output, account));
+
+        return Ok(Some(output));
+    }
+
+    if let &Some(ref format) = format {
+        try!(write_format(&mut output, format, account));
+
+        return Ok(Some(output));
+    }
+
+    if let Some(name) = options.opt_str("field") {
+        let note_fields = try!(account.note_fields()).unwrap_or(Vec::new());
+
+        let custom =
+            account.fields.iter().map(|f| (&*f.name, &*f.value));
+        let note =
+            note_fields.iter().map(|&(ref k, ref v)| (&**k, &**v));
+
+        let mut all = custom.chain(note);
+
+        return match all.find(|&(n, _)| n == name) {
+            Some((_, value)) => {
+                try!(output.extend_from_slice(value));
+                Ok(Some(output))
+            }
+            None => {
+                println!("Could not find field '{}'", name);
+                Err(Error::BadUsage)
+            }
+        };
+    }
+
+    let fields = display_fields(account);
+
+    let selected: Vec<_> =
+        fields.iter()
+        .filter(|&&(opt, _, _)| options.opt_present(opt))
+        .collect();
+
+    if selected.is_empty() {
+        return Ok(None);
+    }
+
+    for (i, &&(_, _, value)) in selected.iter().enumerate() {
+        if i > 0 {
+            try!(output.push(b'\n'));
+        }
+
+        try!(output.extend_from_slice(value));
+    }
+
+    Ok(Some(output))
+}
+
+/// Standard fields of `account`: option name, label and value
+fn display_fields(account: &Account)
+                  -> [(&'static str, &'static str, &[u8]); 4] {
+    [
+        ("username", "Username", account.username.as_bytes()),
+        ("password", "Password", &account.password),
+        ("url", "URL", account.url.as_bytes()),
+        ("notes", "Notes", &account.note),
+    ]
+}
+
+fn write_field<W: Write>(w: &mut W, label: &str, value: &[u8]) -> Result<()> {
+    if value.is_empty() {
+        return Ok(());
+    }
+
+    try!(write!(w, "{}{}{}: ", color(Color::Dim), label, color(Color::Reset)));
+
+    if value.contains(&b'\n') {
+        // Multi-line value, start on a new line
+        try!(w.write_all(b"\n"));
+    }
+
+    try!(w.write_all(value));
+    try!(w.write_all(b"\n"));
+
+    Ok(())
+}
+
+/// Placeholders supported by `--format`. `name` is the name of the
+/// account without the group, `fullname` includes it.
+const FORMAT_FIELDS: [&'static str; 8] = [
+    "id", "name", "fullname", "group", "username", "password", "url",
+    "notes",
+];
+
+/// Piece of a parsed `--format` template
+#[derive(Debug, PartialEq)]
+enum Piece {
+    /// Literal text
+    Text(String),
+    /// Account field, one of `FORMAT_FIELDS`
+    Field(&'static str),
+}
+
+/// Parse a `--format` template. Placeholders are written `{field}`,
+/// literal braces are escaped by doubling them: `{{` and `}}`.
+fn parse_format(format: &str) -> Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        let rest = chars.as_str();
+
+        match c {
+            '{' if rest.starts_with('{') => {
+                text.push('{');
+                chars.next();
+            }
+            '}' if rest.starts_with('}') => {
+                text.push('}');
+                chars.next();
+            }
+            '{' => {
+                let end =
+                    match rest.find('}') {
+                        Some(e) => e,
+                        None => {
+                            println!("Unterminated placeholder in format \
+                                      '{}'", format);
+                            return Err(Error::BadUsage);
+                        }
+                    };
+
+                let name = &rest[..end];
+
+                let field =
+                    match FORMAT_FIELDS.iter().find(|&&f| f == name) {
+                        Some(f) => *f,
+                        None => {
+                            println!("Unknown placeholder '{{{}}}' in \
+                                      format, expected one of: {}",
+                                     name, FORMAT_FIELDS.join(", "));
+                            return Err(Error::BadUsage);
+                        }
+                    };
+
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(text));
+                    text = String::new();
+                }
+
+                pieces.push(Piece::Field(field));
+
+                chars = rest[end + 1..].chars();
+            }
+            '}' => {
+                println!("Unmatched '}}' in format '{}', use '}}}}' for a \
+                          literal brace", format);
+                return Err(Error::BadUsage);
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+
+    Ok(pieces)
+}
+
+/// Expand the template `pieces` for `account`
+fn write_format<W: Write>(w: &mut W,
+                          pieces: &[Piece],
+                          account: &Account) -> Result<()> {
+    for piece in pieces {
+        let value =
+            match piece {
+                &Piece::Text(ref t) => t.as_bytes(),
+                &Piece::Field("id") => account.id.as_bytes(),
+                &Piece::Field("name") => account.name.as_bytes(),
+                &Piece::Field("fullname") => account.fullname.as_bytes(),
+                &Piece::Field("group") => account.group.as_bytes(),
+                &Piece::Field(f) => try!(get_field(account, f)),
+            };
+
+        try!(w.write_all(value));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_format() {
+    use lpass::SecureStorage;
+
+    let pieces = parse_format("{url} {{{username}}}:{password}}}").unwrap();
+
+    assert_eq!(pieces, vec![
+        Piece::Field("url"),
+        Piece::Text(" {".to_owned()),
+        Piece::Field("username"),
+        Piece::Text("}:".to_owned()),
+        Piece::Field("password"),
+        Piece::Text("}".to_owned()),
+    ]);
+
+    assert!(parse_format("").unwrap().is_empty());
+    assert!(parse_format("{user}").is_err());
+    assert!(parse_format("{username").is_err());
+    assert!(parse_format("username}").is_err());
+
+    let mut account = Account::new("web/mail");
+    account.username = "bob".to_owned();
+    account.password = SecureStorage::from_slice(b"hunter2").unwrap();
+
+    let mut out = Vec::new();
+
+    write_format(&mut out, &pieces, &account).unwrap();
+
+    assert_eq!(out, b" {bob}:hunter2}");
+
+    let mut out = Vec::new();
+    let pieces = parse_format("{group}/{name}").unwrap();
+
+    write_format(&mut out, &pieces, &account).unwrap();
+
+    assert_eq!(out, b"web/mail");
+
+    let mut out = Vec::new();
+    let pieces = parse_format("{fullname}").unwrap();
+
+    write_format(&mut out, &pieces, &account).unwrap();
+
+    assert_eq!(out, b"web/mail");
+}
@@ -0,0 +1,98 @@
+use lpass::Result;
+use lpass::{blob, config};
+
+use CommandOption;
+
+use terminal::{color, Color};
+use agent;
+use commands::load_session;
+
+use getopts::Matches;
+
+use std::process;
+
+pub const STATUS_COMMAND: ::Command = ::Command {
+    name: "status",
+    options: &[
+        CommandOption {
+            short_name: "q",
+            long_name: "quiet",
+            description: "don't display anything, exit with status 0 if \
+                          logged in and 1 otherwise",
+            argument: None,
+        },
+    ],
+    free_args: "",
+    command: status,
+};
+
+pub fn status(options: &Matches) -> Result<()> {
+    let quiet = options.opt_present("q");
+
+    let mut session =
+        match try!(load_session()) {
+            Some(s) => s,
+            None => {
+                if !quiet {
+                    println!("{}Not logged in.{}",
+                             color(Color::FgRed),
+                             color(Color::Reset));
+                }
+
+                process::exit(1);
+            }
+        };
+
+    if quiet {
+        return Ok(());
+    }
+
+    print!("{}Logged in{} as {}{}{} on {}",
+           color(Color::FgGreen),
+           color(Color::Reset),
+           color(Color::Bold),
+           session.username(),
+           color(Color::Reset),
+           session.server());
+
+    match session.uid() {
+        Some(uid) => println!(" [uid: {}]", uid),
+        None => println!(""),
+    }
+
+    // The key is only requested to check that the agent is alive
+    // and to read the cache version, it doesn't need the master
+    // password
+    if let Some(key) = agent::get_key() {
+        session.set_crypto_key(key);
+
+        println!("Agent: running, the decryption key is cached");
+    } else {
+        println!("Agent: {}not running{}, the master password will be \
+                  required",
+                 color(Color::FgYellow),
+                 color(Color::Reset));
+    }
+
+    match try!(config::age("blob")) {
+        Some(age) => {
+            // The cache is encrypted, the version is only available
+            // if the agent is running
+            let version =
+                match try!(session.cached_blob()) {
+                    Some(b) => try!(blob::version(&b)),
+                    None => None,
+                };
+
+            print!("Blob cache: {}s old", age.as_secs());
+
+            match version {
+                Some(v) => println!(", version {}", v),
+                None => println!(""),
+            }
+        }
+        None => println!("Blob cache: empty"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use lpass::{Result, Session};
+
+use CommandOption;
+
+use agent;
+use commands::{open_session, with_relogin};
+
+use getopts::Matches;
+
+use libc;
+
+use std::io;
+use std::process;
+
+pub const SYNC_COMMAND: ::Command = ::Command {
+    name: "sync",
+    options: &[
+        CommandOption {
+            short_name: "b",
+            long_name: "background",
+            description: "synchronize in the background",
+            argument: None,
+        },
+    ],
+    free_args: "",
+    command: sync,
+};
+
+pub fn sync(options: &Matches) -> Result<()> {
+    let mut session = try!(open_session());
+
+    if options.opt_present("b") {
+        return sync_background(session);
+    }
+
+    try!(with_relogin(&mut session, |s| s.sync()));
+
+    let accounts = try!(with_relogin(&mut session, |s| s.get_accounts()));
+
+    println!("Synchronized {} accounts.", accounts.len());
+
+    Ok(())
+}
+
+fn sync_background(mut session: Session) -> Result<()> {
+    let pid = unsafe { libc::fork() };
+
+    match pid {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            // Child process. Memory locks are not inherited through
+            // `fork` but we don't live long.
+            let status =
+                match agent::daemonize(0).and_then(|_| session.sync()) {
+                    Ok(_) => 0,
+                    Err(_) => 1,
+                };
+
+            process::exit(status);
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,195 @@
+/// Minimal CSV (RFC 4180) serialization and parsing helpers. Like
+/// the JSON helpers we write directly from the source buffers, parsed
+/// fields are stored in `SecureStorage`.
+
+use std::io::{self, Write};
+use std::mem;
+
+use lpass::{Result, Account, SecureStorage};
+
+/// Columns of the LastPass CSV export format
+pub const HEADER: &'static [&'static str] = &[
+    "url", "username", "password", "extra", "name", "grouping", "fav",
+];
+
+/// Write `s` as a CSV field. The field is quoted if it contains a
+/// comma, a quote or a line break.
+pub fn write_field<W: Write>(w: &mut W, s: &[u8]) -> io::Result<()> {
+    let needs_quotes =
+        s.iter().any(|&b| b == b',' || b == b'"' || b == b'\n' || b == b'\r');
+
+    if !needs_quotes {
+        return w.write_all(s);
+    }
+
+    try!(w.write_all(b"\""));
+
+    // Quotes are escaped by doubling them
+    for (i, chunk) in s.split(|&b| b == b'"').enumerate() {
+        if i > 0 {
+            try!(w.write_all(b"\"\""));
+        }
+
+        try!(w.write_all(chunk));
+    }
+
+    w.write_all(b"\"")
+}
+
+/// Write a CSV record made of `fields`, including the line ending
+pub fn write_record<W: Write>(w: &mut W, fields: &[&[u8]]) -> io::Result<()> {
+    for (i, &f) in fields.iter().enumerate() {
+        if i > 0 {
+            try!(w.write_all(b","));
+        }
+
+        try!(write_field(w, f));
+    }
+
+    w.write_all(b"\r\n")
+}
+
+/// Write the `HEADER` record
+pub fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    let header: Vec<&[u8]> = HEADER.iter().map(|h| h.as_bytes()).collect();
+
+    write_record(w, &header)
+}
+
+/// Write `account` as a record matching `HEADER`
+pub fn write_account<W: Write>(w: &mut W,
+                               account: &Account) -> io::Result<()> {
+    let fav: &[u8] = if account.favorite { b"1" } else { b"0" };
+
+    write_record(w, &[
+        account.url.as_bytes(),
+        account.username.as_bytes(),
+        &account.password,
+        // For secure notes this contains the structured note body
+        &account.note,
+        account.name.as_bytes(),
+        account.group.as_bytes(),
+        fav,
+    ])
+}
+
+/// Parse the CSV `data` into records. A leading UTF-8 BOM is ignored,
+/// lines can end with LF or CRLF and empty lines are skipped.
+pub fn parse(data: &[u8]) -> Result<Vec<Vec<SecureStorage>>> {
+    let data =
+        if data.starts_with(b"\xef\xbb\xbf") {
+            &data[3..]
+        } else {
+            data
+        };
+
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = SecureStorage::empty();
+    let mut in_quotes = false;
+
+    let mut i = 0;
+
+    while i < data.len() {
+        let b = data[i];
+        let next = data.get(i + 1).cloned();
+
+        if in_quotes {
+            match (b, next) {
+                // Escaped quote
+                (b'"', Some(b'"')) => {
+                    try!(field.push(b'"'));
+                    i += 1;
+                }
+                (b'"', _) => in_quotes = false,
+                _ => try!(field.push(b)),
+            }
+        } else {
+            match (b, next) {
+                (b'"', _) => in_quotes = true,
+                (b',', _) =>
+                    record.push(mem::replace(&mut field,
+                                             SecureStorage::empty())),
+                // The LF will end the record
+                (b'\r', Some(b'\n')) => (),
+                (b'\n', _) => {
+                    record.push(mem::replace(&mut field,
+                                             SecureStorage::empty()));
+
+                    push_record(&mut records, mem::replace(&mut record,
+                                                           Vec::new()));
+                }
+                _ => try!(field.push(b)),
+            }
+        }
+
+        i += 1;
+    }
+
+    if in_quotes {
+        let err = io::Error::new(io::ErrorKind::InvalidData,
+                                 "Unterminated quoted CSV field");
+        return Err(err.into());
+    }
+
+    // Last line without line ending
+    record.push(field);
+    push_record(&mut records, record);
+
+    Ok(records)
+}
+
+/// Add `record` to `records` unless it's an empty line
+fn push_record(records: &mut Vec<Vec<SecureStorage>>,
+               record: Vec<SecureStorage>) {
+    if record.len() > 1 || !record[0].is_empty() {
+        records.push(record);
+    }
+}
+
+#[test]
+fn test_parse() {
+    let data = b"\xef\xbb\xbfurl,name\r\n\
+                 \r\n\
+                 plain,\"a,b\"\n\
+                 \"say \"\"hi\"\"\",\"line 1\r\nline 2\"\r\n\
+                 ,last";
+
+    let records = parse(data).unwrap();
+
+    let expected: &[&[&[u8]]] = &[
+        &[b"url", b"name"],
+        &[b"plain", b"a,b"],
+        &[b"say \"hi\"", b"line 1\r\nline 2"],
+        &[b"", b"last"],
+    ];
+
+    assert_eq!(records.len(), expected.len());
+
+    for (r, &e) in records.iter().zip(expected) {
+        assert_eq!(r.len(), e.len());
+
+        for (f, &e) in r.iter().zip(e) {
+            assert!(f.ct_eq(e));
+        }
+    }
+
+    assert!(parse(b"\"unterminated\nfield").is_err());
+    assert!(parse(b"").unwrap().is_empty());
+}
+
+#[test]
+fn test_write_record() {
+    let mut out = Vec::new();
+
+    write_record(&mut out, &[b"plain",
+                             b"",
+                             b"a,b",
+                             b"say \"hi\"",
+                             b"line 1\nline 2"]).unwrap();
+
+    let expected: &[u8] =
+        b"plain,,\"a,b\",\"say \"\"hi\"\"\",\"line 1\nline 2\"\r\n";
+
+    assert_eq!(&out[..], expected);
+}
@@ -0,0 +1,112 @@
+/// Saved environment
+///
+/// Persistent defaults for the `LPASS_*` environment variables can be
+/// set in the `env` file of the configuration directory, one
+/// `KEY=VALUE` per line:
+///
+/// ```text
+/// LPASS_AGENT_TIMEOUT=0
+/// LPASS_SERVER=lastpass.eu
+/// ```
+///
+/// The variables already set in the environment take precedence.
+
+use lpass::Result;
+use lpass::config;
+
+use std::env;
+
+/// Load the saved environment. Must be called early, before any
+/// other thread is started.
+pub fn load() -> Result<()> {
+    let data =
+        match try!(config::read_public("env")) {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+    for (key, value) in parse(&String::from_utf8_lossy(&data)) {
+        if env::var_os(&key).is_none() {
+            debug!("Setting {} from the saved environment", key);
+
+            env::set_var(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse the contents of the `env` file. Only the `LPASS_*` variables
+/// are accepted, the other lines are ignored with a warning.
+fn parse(data: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) =
+            match line.find('=') {
+                Some(p) => (line[..p].trim(), line[p + 1..].trim()),
+                None => {
+                    warn!("Ignoring invalid line {} in the saved \
+                           environment", i + 1);
+                    continue;
+                }
+            };
+
+        let valid_key =
+            key.starts_with("LPASS_") &&
+            key.chars().all(|c| match c {
+                'A'...'Z' | '0'...'9' | '_' => true,
+                _ => false,
+            });
+
+        if !valid_key {
+            warn!("Ignoring variable '{}' in the saved environment", key);
+            continue;
+        }
+
+        // Allow quoting the value like in a shell script
+        let value =
+            if value.len() >= 2 &&
+                (value.starts_with('"') && value.ends_with('"') ||
+                 value.starts_with('\'') && value.ends_with('\'')) {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+
+        vars.push((key.to_owned(), value.to_owned()));
+    }
+
+    vars
+}
+
+#[test]
+fn test_parse() {
+    let vars = parse("# Saved environment\n\
+                      LPASS_AGENT_TIMEOUT=0\n\
+                      \n\
+                      LPASS_PINENTRY = \"/usr/bin/pinentry curses\"\n\
+                      LPASS_SERVER='lastpass.eu'\n\
+                      PATH=/tmp\n\
+                      LPASS_lower=1\n\
+                      garbage\n");
+
+    let expected = [
+        ("LPASS_AGENT_TIMEOUT", "0"),
+        ("LPASS_PINENTRY", "/usr/bin/pinentry curses"),
+        ("LPASS_SERVER", "lastpass.eu"),
+    ];
+
+    assert_eq!(vars.len(), expected.len());
+
+    for (&(ref k, ref v), &(ek, ev)) in vars.iter().zip(expected.iter()) {
+        assert_eq!(k, ek);
+        assert_eq!(v, ev);
+    }
+}
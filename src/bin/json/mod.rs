@@ -0,0 +1,306 @@
+/// Minimal JSON serialization helpers. We write directly from the
+/// source buffers so that secrets don't have to be copied into
+/// unprotected `String`s.
+
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::str::{self, Chars};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lpass::{Result, Error, Account};
+
+/// Write `s` as a JSON string literal, escaping characters as
+/// needed. `s` is expected to be UTF-8, other bytes are passed
+/// through unchanged.
+pub fn write_string<W: Write>(w: &mut W, s: &[u8]) -> io::Result<()> {
+    try!(w.write_all(b"\""));
+
+    // Write unescaped runs in one go
+    let mut start = 0;
+
+    for (i, &b) in s.iter().enumerate() {
+        let escaped: &[u8] =
+            match b {
+                b'"' => b"\\\"",
+                b'\\' => b"\\\\",
+                b'\n' => b"\\n",
+                b'\r' => b"\\r",
+                b'\t' => b"\\t",
+                // Other control characters are \u-escaped below
+                0...0x1f => b"",
+                _ => continue,
+            };
+
+        try!(w.write_all(&s[start..i]));
+
+        if escaped.is_empty() {
+            try!(write!(w, "\\u{:04x}", b));
+        } else {
+            try!(w.write_all(escaped));
+        }
+
+        start = i + 1;
+    }
+
+    try!(w.write_all(&s[start..]));
+
+    w.write_all(b"\"")
+}
+
+/// Write `account` as a JSON object
+pub fn write_account<W: Write>(w: &mut W,
+                               account: &Account) -> io::Result<()> {
+    let touch = timestamp(account.last_touch);
+    let modified = timestamp(account.last_modified);
+
+    let fields = account_fields(account, &touch, &modified);
+
+    try!(w.write_all(b"{\n"));
+
+    for (i, &(name, value)) in fields.iter().enumerate() {
+        try!(w.write_all(b"  "));
+        try!(write_string(w, name.as_bytes()));
+        try!(w.write_all(b": "));
+        try!(write_string(w, value));
+
+        if i + 1 < fields.len() {
+            try!(w.write_all(b","));
+        }
+
+        try!(w.write_all(b"\n"));
+    }
+
+    w.write_all(b"}")
+}
+
+/// Write `account` as a compact JSON object on a single line
+pub fn write_account_line<W: Write>(w: &mut W,
+                                    account: &Account) -> io::Result<()> {
+    let touch = timestamp(account.last_touch);
+    let modified = timestamp(account.last_modified);
+
+    write_object(w, &account_fields(account, &touch, &modified))
+}
+
+/// Fields of `account` as written in the JSON object. The timestamps
+/// are formatted by the caller with `timestamp`.
+fn account_fields<'a>(account: &'a Account,
+                      last_touch: &'a str,
+                      last_modified: &'a str)
+                      -> [(&'static str, &'a [u8]); 10] {
+    [
+        ("id", account.id.as_bytes()),
+        ("name", account.name.as_bytes()),
+        ("fullname", account.fullname.as_bytes()),
+        ("group", account.group.as_bytes()),
+        ("url", account.url.as_bytes()),
+        ("username", account.username.as_bytes()),
+        ("password", &account.password),
+        ("note", &account.note),
+        ("last_touch", last_touch.as_bytes()),
+        ("last_modified", last_modified.as_bytes()),
+    ]
+}
+
+/// Format `t` as seconds since the epoch like the server does, or an
+/// empty string if it's unknown
+fn timestamp(t: Option<SystemTime>) -> String {
+    t.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_default()
+}
+
+/// Write a compact JSON object made of the string `fields`
+pub fn write_object<W: Write>(w: &mut W,
+                              fields: &[(&str, &[u8])]) -> io::Result<()> {
+    try!(w.write_all(b"{"));
+
+    for (i, &(name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            try!(w.write_all(b","));
+        }
+
+        try!(write_string(w, name.as_bytes()));
+        try!(w.write_all(b":"));
+        try!(write_string(w, value));
+    }
+
+    w.write_all(b"}")
+}
+
+/// Parse a flat JSON object whose values are all strings, such as
+/// `{"type": "status"}`. Returns the (name, value) pairs in order.
+/// Nested objects, arrays and non-string values are not supported.
+pub fn parse_object(data: &[u8]) -> Result<Vec<(String, String)>> {
+    let data = try!(str::from_utf8(data).map_err(|_| bad_json()));
+
+    let mut chars = data.chars().peekable();
+    let mut fields = Vec::new();
+
+    skip_whitespace(&mut chars);
+    try!(expect(&mut chars, '{'));
+    skip_whitespace(&mut chars);
+
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let name = try!(parse_string(&mut chars));
+            skip_whitespace(&mut chars);
+            try!(expect(&mut chars, ':'));
+            skip_whitespace(&mut chars);
+            let value = try!(parse_string(&mut chars));
+            skip_whitespace(&mut chars);
+
+            fields.push((name, value));
+
+            match chars.next() {
+                Some(',') => (),
+                Some('}') => break,
+                _ => return Err(bad_json()),
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+
+    if chars.next().is_some() {
+        return Err(bad_json());
+    }
+
+    Ok(fields)
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String> {
+    try!(expect(chars, '"'));
+
+    let mut s = String::new();
+
+    loop {
+        let c =
+            match chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') =>
+                    match chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => try!(parse_unicode_escape(chars)),
+                        _ => return Err(bad_json()),
+                    },
+                Some(c) if c >= ' ' => c,
+                // Unescaped control character or end of input
+                _ => return Err(bad_json()),
+            };
+
+        s.push(c);
+    }
+}
+
+/// Parse the `XXXX` of a `\uXXXX` escape, as well as the second half
+/// of a surrogate pair if needed
+fn parse_unicode_escape(chars: &mut Peekable<Chars>) -> Result<char> {
+    let hex4 = |chars: &mut Peekable<Chars>| -> Result<u32> {
+        let mut v = 0;
+
+        for _ in 0..4 {
+            let d =
+                match chars.next().and_then(|c| c.to_digit(16)) {
+                    Some(d) => d,
+                    None => return Err(bad_json()),
+                };
+
+            v = v * 16 + d;
+        }
+
+        Ok(v)
+    };
+
+    let high = try!(hex4(chars));
+
+    let code =
+        match high {
+            0xd800...0xdbff => {
+                try!(expect(chars, '\\'));
+                try!(expect(chars, 'u'));
+
+                let low = try!(hex4(chars));
+
+                if low < 0xdc00 || low > 0xdfff {
+                    return Err(bad_json());
+                }
+
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            _ => high,
+        };
+
+    ::std::char::from_u32(code).ok_or_else(bad_json)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => chars.next(),
+            _ => break,
+        };
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<()> {
+    if chars.next() == Some(expected) {
+        Ok(())
+    } else {
+        Err(bad_json())
+    }
+}
+
+fn bad_json() -> Error {
+    Error::BadProtocol("Invalid JSON".to_owned())
+}
+
+#[test]
+fn test_parse_object() {
+    let fields =
+        parse_object(b" { \"type\" : \"get_password\",\n\
+                       \"id\":\"42\", \
+                       \"q\": \"a\\\"b\\\\c\\u00e9\\ud83d\\ude00\" } ")
+        .unwrap();
+
+    let expected = [
+        ("type", "get_password"),
+        ("id", "42"),
+        ("q", "a\"b\\c\u{e9}\u{1f600}"),
+    ];
+
+    assert_eq!(fields.len(), expected.len());
+
+    for (&(ref n, ref v), &(en, ev)) in fields.iter().zip(expected.iter()) {
+        assert_eq!(n, en);
+        assert_eq!(v, ev);
+    }
+
+    assert!(parse_object(b"{}").unwrap().is_empty());
+
+    let invalid: &[&[u8]] = &[
+        b"",
+        b"{",
+        b"{\"a\"}",
+        b"{\"a\": 1}",
+        b"{\"a\": \"b\",}",
+        b"{\"a\": \"b\"} x",
+        b"{\"a\": \"\\ud83d\"}",
+        b"{\"a\": \"\n\"}",
+        b"[\"a\"]",
+    ];
+
+    for &json in invalid {
+        assert!(parse_object(json).is_err());
+    }
+}
@@ -1,30 +1,44 @@
 use std::env;
+use std::fs;
+use std::mem;
 use std::process;
 use std::io;
 use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use libc;
 
 use lpass::{Result, Error};
 use lpass::SecureStorage;
 
-/// Prompt the user for a password
+/// Prompt the user for a password. We use pinentry if it's available
+/// and fallback to the terminal otherwise or if
+/// `LPASS_DISABLE_PINENTRY` is set.
 pub fn prompt(prompt: &str,
               desc: &str,
               error: Option<&str>) -> Result<SecureStorage> {
-    // XXX Implement fallback using the terminal and
-    // LPASS_DISABLE_PINENTRY
+    if env::var_os("LPASS_DISABLE_PINENTRY").is_some() {
+        return terminal_prompt(prompt, desc, error);
+    }
 
-    let pinentry =
-        match env::var("LPASS_PINETRY") {
-            Ok(p) => p,
-            Err(_) => "pinentry".to_owned(),
-        };
+    let pinentry = pinentry_path(|v| env::var(v).ok());
 
     debug!("Spawning {}", pinentry);
 
-    let mut pinentry = try!(process::Command::new(&pinentry)
-                            .stdin(process::Stdio::piped())
-                            .stdout(process::Stdio::piped())
-                            .spawn());
+    let spawned = process::Command::new(&pinentry)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .spawn();
+
+    let mut pinentry =
+        match spawned {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Couldn't spawn {}: {}, falling back to the terminal",
+                       pinentry, e);
+                return terminal_prompt(prompt, desc, error);
+            }
+        };
 
     let r = pinentry_proto(&mut pinentry, prompt, desc, error);
 
@@ -35,6 +49,124 @@ pub fn prompt(prompt: &str,
     r
 }
 
+/// Return the pinentry binary to use based on the environment
+/// variables returned by `getenv`
+fn pinentry_path<F>(getenv: F) -> String
+    where F: Fn(&str) -> Option<String> {
+
+    if let Some(p) = getenv("LPASS_PINENTRY") {
+        return p;
+    }
+
+    // Misspelled variable name used by previous versions, kept for
+    // compatibility
+    if let Some(p) = getenv("LPASS_PINETRY") {
+        warn!("LPASS_PINETRY is deprecated, use LPASS_PINENTRY instead");
+        return p;
+    }
+
+    "pinentry".to_owned()
+}
+
+/// Read the password directly from the controlling terminal with
+/// echo disabled
+fn terminal_prompt(prompt: &str,
+                   desc: &str,
+                   error: Option<&str>) -> Result<SecureStorage> {
+    let mut tty = try!(fs::OpenOptions::new()
+                       .read(true)
+                       .write(true)
+                       .open("/dev/tty"));
+
+    if let Some(error) = error {
+        try!(writeln!(tty, "{}", error));
+    }
+
+    try!(write!(tty, "{}\n{}: ", desc, prompt));
+    try!(tty.flush());
+
+    let password = {
+        let _noecho = try!(EchoGuard::disable(tty.as_raw_fd()));
+
+        try!(read_line_from(&mut tty))
+    };
+
+    // The newline typed by the user wasn't echoed
+    try!(tty.write_all(b"\n"));
+
+    Ok(password)
+}
+
+/// Terminal settings to restore if we're interrupted while echo is
+/// disabled
+static mut SAVED_TERMIOS: Option<(libc::c_int, libc::termios)> = None;
+
+/// Disables echo on a terminal until it's dropped
+struct EchoGuard {
+    fd: libc::c_int,
+    termios: libc::termios,
+    sigint_handler: libc::sighandler_t,
+}
+
+impl EchoGuard {
+    fn disable(fd: libc::c_int) -> Result<EchoGuard> {
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut noecho = termios;
+
+        noecho.c_lflag &= !libc::ECHO;
+
+        // Make sure the terminal settings get restored if the user
+        // hits Ctrl-C
+        let sigint_handler = unsafe {
+            SAVED_TERMIOS = Some((fd, termios));
+
+            let handler: extern "C" fn(libc::c_int) = restore_termios;
+
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t)
+        };
+
+        let guard = EchoGuard {
+            fd: fd,
+            termios: termios,
+            sigint_handler: sigint_handler,
+        };
+
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &noecho) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.termios);
+            libc::signal(libc::SIGINT, self.sigint_handler);
+            SAVED_TERMIOS = None;
+        }
+    }
+}
+
+/// SIGINT handler restoring the terminal settings before letting the
+/// signal kill us
+extern "C" fn restore_termios(signal: libc::c_int) {
+    unsafe {
+        if let Some((fd, ref termios)) = SAVED_TERMIOS {
+            libc::tcsetattr(fd, libc::TCSAFLUSH, termios);
+        }
+
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
 /// Implementation of the pinentry protocol
 fn pinentry_proto(pinentry: &mut process::Child,
                   prompt: &str,
@@ -112,9 +244,15 @@ fn read_line(pinentry: &mut process::Child) -> Result<SecureStorage> {
             }
         };
 
+    read_line_from(stdout)
+}
+
+/// Read a line from `reader` into a `SecureStorage`. The trailing
+/// newline is not included.
+fn read_line_from<R: Read>(reader: &mut R) -> Result<SecureStorage> {
     let mut line = try!(SecureStorage::with_capacity(64));
 
-    for b in stdout.bytes() {
+    for b in reader.bytes() {
         let b = try!(b);
 
         if b == b'\n' {
@@ -143,3 +281,26 @@ fn send(pinentry: &mut process::Child, data: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_pinentry_path() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |v: &str| {
+            vars.iter()
+                .find(|&&(k, _)| k == v)
+                .map(|&(_, val)| val.to_owned())
+        }
+    };
+
+    assert_eq!(pinentry_path(env(&[])), "pinentry");
+
+    assert_eq!(pinentry_path(env(&[("LPASS_PINENTRY", "/bin/pe")])),
+               "/bin/pe");
+
+    assert_eq!(pinentry_path(env(&[("LPASS_PINETRY", "/bin/old")])),
+               "/bin/old");
+
+    assert_eq!(pinentry_path(env(&[("LPASS_PINETRY", "/bin/old"),
+                                   ("LPASS_PINENTRY", "/bin/pe")])),
+               "/bin/pe");
+}
@@ -1,18 +1,54 @@
 /// Terminal-specific handling
 
 use lpass::{Result, Error};
+use lpass;
+
+pub use lpass::platform::{stdin_is_a_tty, stdout_is_a_tty};
+
+use libc;
 
 use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use std::env;
 use std::io;
 use std::io::Write;
 
+/// If true `ask_yes_no` doesn't prompt and assumes "yes"
+static ASSUME_YES: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Make `ask_yes_no` answer "yes" without prompting the user
+pub fn set_assume_yes(assume_yes: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::Relaxed);
+}
+
 /// Prompt the user for a yes-or-no question, return `Ok(())` if they
 /// reply "yes", `Err(Error::UserAbort)` if they reply "no". Can also
 /// return an I/O error if reading from the terminal fails somehow.
+///
+/// If stdin is not a terminal there's nobody to ask, the default
+/// answer is used without prompting.
+///
+/// If `set_assume_yes` was called the user is not asked at all,
+/// use `ask_yes_no_always` for truly dangerous operations.
 pub fn ask_yes_no(default_yes: bool, prompt: &str) -> Result<()> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    ask_yes_no_always(default_yes, prompt)
+}
+
+/// Same as `ask_yes_no` but `set_assume_yes` is ignored
+pub fn ask_yes_no_always(default_yes: bool, prompt: &str) -> Result<()> {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
+    let ok = || Ok(());
+    let err = || Err(Error::UserAbort);
+
+    if !stdin_is_a_tty() {
+        return if default_yes { ok() } else { err() };
+    }
+
     loop {
         print!("{}{}{}",
                color(Color::FgYellow),
@@ -29,61 +65,135 @@ pub fn ask_yes_no(default_yes: bool, prompt: &str) -> Result<()> {
 
         let mut reply = String::new();
 
-        // XXX should we check if stdin is not a tty and do something?
-        try!(stdin.read_line(&mut reply));
-
-        let ok = Ok(());
-        let err = Err(Error::UserAbort);
-
-        match reply.as_str() {
-            "\n" =>
-                if default_yes {
-                    return ok
-                } else {
-                    return err
-                },
-            "no\n" | "n\n" => return err,
-            "yes\n" | "y\n" => return ok,
-            _ => println!("{}Error{}: Response not understood.",
-                          color(Color::FgRed), color(Color::Reset)),
+        if try!(stdin.read_line(&mut reply)) == 0 {
+            // EOF (^D), don't loop forever
+            println!("");
+            return err();
+        }
+
+        match parse_yes_no(&reply, default_yes) {
+            Some(true) => return ok(),
+            Some(false) => return err(),
+            None => println!("{}Error{}: Response not understood.",
+                             color(Color::FgRed), color(Color::Reset)),
         }
     }
 }
 
+/// Parse a reply to `ask_yes_no`. Case and surrounding whitespace
+/// (including CRLF line endings) are ignored, an empty reply gives
+/// `default_yes`. Returns `None` if the reply is not understood.
+fn parse_yes_no(reply: &str, default_yes: bool) -> Option<bool> {
+    match &*reply.trim().to_lowercase() {
+        "" => Some(default_yes),
+        "yes" | "y" => Some(true),
+        "no" | "n" => Some(false),
+        _ => None,
+    }
+}
+
+/// Prompt the user for a line of text. The trailing newline is
+/// removed.
+pub fn ask(prompt: &str) -> Result<String> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    print!("{}", prompt);
+
+    try!(stdout.flush());
+
+    let mut reply = String::new();
+
+    try!(stdin.read_line(&mut reply));
+
+    Ok(reply.trim_right_matches('\n').to_owned())
+}
+
 /// If true colored output is enabled
 static COLOR_ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
 
 /// Terminal color handling
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ColorMode {
     Auto,
     Never,
     Always,
 }
 
-pub fn stdout_is_a_tty() -> bool {
-    let is_a_tty = unsafe {
-        ::libc::isatty(::libc::STDOUT_FILENO)
-    };
+/// Enable or disable colored output. Colors are only used if the
+/// terminal supports them. In `Auto` mode stdout must also be a
+/// terminal and the `NO_COLOR` environment variable must not be set
+/// (see https://no-color.org).
+pub fn set_color_mode(mode: ColorMode) {
+    let term = env::var("TERM").ok();
+    let colorterm = env::var_os("COLORTERM").is_some();
 
-    is_a_tty == 1
-}
+    let supported = term_supports_color(term.as_ref().map(|t| &**t),
+                                        colorterm);
+
+    let no_color =
+        env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false);
 
-pub fn set_color_mode(mode: ColorMode) {
     let enabled =
         match mode {
             ColorMode::Never => false,
-            ColorMode::Always => true,
-            ColorMode::Auto => stdout_is_a_tty(),
+            ColorMode::Always => supported,
+            ColorMode::Auto => supported && !no_color && stdout_is_a_tty(),
         };
 
     COLOR_ENABLED.store(enabled, Ordering::Relaxed);
 }
 
+/// Families of terminals known to support the ANSI color escapes
+const COLOR_TERMS: &'static [&'static str] = &[
+    "alacritty",
+    "ansi",
+    "cygwin",
+    "eterm",
+    "foot",
+    "gnome",
+    "konsole",
+    "kitty",
+    "linux",
+    "putty",
+    "rxvt",
+    "screen",
+    "st",
+    "tmux",
+    "vte",
+    "wezterm",
+    "xterm",
+];
+
+/// Return `true` if the terminal type `term` (the value of `$TERM`)
+/// supports the ANSI color escapes used by `color`. `colorterm` is
+/// `true` if `$COLORTERM` is set, which some terminal emulators use
+/// to advertise color support.
+fn term_supports_color(term: Option<&str>, colorterm: bool) -> bool {
+    let term =
+        match term {
+            Some(t) if !t.is_empty() && t != "dumb" => t,
+            _ => return false,
+        };
+
+    if colorterm || term.contains("color") {
+        return true;
+    }
+
+    // Match on the family: "xterm-kitty", "screen.linux"...
+    let family =
+        term.split(|c| c == '-' || c == '.').next().unwrap_or(term);
+
+    COLOR_TERMS.contains(&family)
+}
+
 pub enum Color {
     FgRed,
+    FgGreen,
     FgYellow,
+    FgBlue,
     Bold,
+    Dim,
     /// Reset to the default foreground and background color
     Reset,
 }
@@ -95,12 +205,65 @@ pub fn color(col: Color) -> &'static str {
         return ""
     }
 
-    // XXX should we query terminfo or something like that instead of
-    // hardcoding those?
+    // `set_color_mode` only enables colors for terminals supporting
+    // these escapes
     match col {
         Color::FgRed => "\x1b[31m",
+        Color::FgGreen => "\x1b[32m",
         Color::FgYellow => "\x1b[33m",
+        Color::FgBlue => "\x1b[34m",
         Color::Bold => "\x1b[1m",
+        Color::Dim => "\x1b[2m",
         Color::Reset => "\x1b[0m",
     }
 }
+
+/// Install a SIGINT handler that aborts the HTTP transfer in
+/// progress, if any, so that the command can fail cleanly. If there's
+/// no transfer in progress we're killed as usual.
+pub fn handle_interrupts() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    if lpass::transfer_in_progress() {
+        lpass::interrupt();
+    } else {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+#[test]
+fn test_term_supports_color() {
+    assert!(term_supports_color(Some("xterm-256color"), false));
+    assert!(term_supports_color(Some("screen.linux"), false));
+    assert!(term_supports_color(Some("tmux-256color"), false));
+    assert!(term_supports_color(Some("linux"), false));
+    assert!(term_supports_color(Some("unknown"), true));
+
+    assert!(!term_supports_color(None, true));
+    assert!(!term_supports_color(Some(""), false));
+    assert!(!term_supports_color(Some("dumb"), true));
+    assert!(!term_supports_color(Some("vt100"), false));
+    // Not to be confused with "st"
+    assert!(!term_supports_color(Some("sterm"), false));
+}
+
+#[test]
+fn test_parse_yes_no() {
+    assert_eq!(parse_yes_no("\n", true), Some(true));
+    assert_eq!(parse_yes_no("\r\n", false), Some(false));
+    assert_eq!(parse_yes_no("y\n", false), Some(true));
+    assert_eq!(parse_yes_no("  YES \r\n", false), Some(true));
+    assert_eq!(parse_yes_no("No\n", true), Some(false));
+    assert_eq!(parse_yes_no("N", true), Some(false));
+    assert_eq!(parse_yes_no("maybe\n", true), None);
+    assert_eq!(parse_yes_no("y es\n", true), None);
+}
@@ -0,0 +1,239 @@
+//! Account blob parsing
+//!
+//! The blob returned by the server is a sequence of chunks, each
+//! made of a 4-byte identifier (`LPAV`, `ACCT`, `ACFL`...) followed
+//! by a 4-byte big endian length and the payload itself.
+
+use Result;
+use Error;
+
+use std::str::FromStr;
+
+/// A single chunk of the blob
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Chunk<'a> {
+    /// Chunk identifier (e.g. `b"ACCT"`)
+    pub id: [u8; 4],
+    /// Chunk payload
+    pub data: &'a [u8],
+}
+
+/// Iterator over the chunks of a blob
+pub struct BlobReader<'a> {
+    /// Data left to parse
+    remaining: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    /// Create a new reader for `blob`
+    pub fn new(blob: &'a [u8]) -> BlobReader<'a> {
+        BlobReader {
+            remaining: blob,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Chunk<'a>> {
+        let buf = self.remaining;
+
+        if buf.len() < 8 {
+            return Err(truncated());
+        }
+
+        let id = [buf[0], buf[1], buf[2], buf[3]];
+
+        let (data, remaining) = try!(split_item(&buf[4..]));
+
+        self.remaining = remaining;
+
+        Ok(Chunk {
+            id: id,
+            data: data,
+        })
+    }
+}
+
+impl<'a> Iterator for BlobReader<'a> {
+    type Item = Result<Chunk<'a>>;
+
+    fn next(&mut self) -> Option<Result<Chunk<'a>>> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let chunk = self.next_chunk();
+
+        if chunk.is_err() {
+            // Don't attempt to parse anything past an error
+            self.remaining = &[];
+        }
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunk<'a> {
+    /// Return an iterator over the items contained in this chunk
+    pub fn items(&self) -> Items<'a> {
+        Items::new(self.data)
+    }
+}
+
+/// Iterator over the items of a chunk. Each item is made of a 4-byte
+/// big endian length followed by the payload.
+pub struct Items<'a> {
+    /// Data left to parse
+    remaining: &'a [u8],
+}
+
+impl<'a> Items<'a> {
+    /// Create an iterator over the items contained in `data`
+    pub fn new(data: &'a [u8]) -> Items<'a> {
+        Items {
+            remaining: data,
+        }
+    }
+
+    /// Return the next item or an empty slice if we've reached the
+    /// end of the chunk. Useful for optional trailing fields.
+    pub fn next_or_empty(&mut self) -> Result<&'a [u8]> {
+        match self.next() {
+            Some(i) => i,
+            None => Ok(&[]),
+        }
+    }
+
+    /// Skip over `n` items. Missing items are ignored.
+    pub fn skip_items(&mut self, n: usize) -> Result<()> {
+        for _ in 0..n {
+            try!(self.next_or_empty());
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Result<&'a [u8]>> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        match split_item(self.remaining) {
+            Ok((item, remaining)) => {
+                self.remaining = remaining;
+                Some(Ok(item))
+            }
+            Err(e) => {
+                self.remaining = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Return the version of `blob` found in its `LPAV` chunk, if any
+pub fn version(blob: &[u8]) -> Result<Option<u64>> {
+    for chunk in BlobReader::new(blob) {
+        let chunk = try!(chunk);
+
+        if &chunk.id == b"LPAV" {
+            let version = try!(String::from_utf8(chunk.data.to_vec()));
+
+            return Ok(Some(try!(u64::from_str(&version))));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Split the length-prefixed item at the start of `buf`, return the
+/// item payload and the remaining data.
+fn split_item(buf: &[u8]) -> Result<(&[u8], &[u8])> {
+    if buf.len() < 4 {
+        return Err(truncated());
+    }
+
+    let len = read_be_u32(&buf[0..4]) as usize;
+
+    let buf = &buf[4..];
+
+    if len > buf.len() {
+        return Err(truncated());
+    }
+
+    Ok((&buf[..len], &buf[len..]))
+}
+
+/// Decode a big endian 32bit integer. `b` must be at least 4 bytes
+/// long.
+fn read_be_u32(b: &[u8]) -> u32 {
+    ((b[0] as u32) << 24) |
+    ((b[1] as u32) << 16) |
+    ((b[2] as u32) << 8) |
+    (b[3] as u32)
+}
+
+fn truncated() -> Error {
+    Error::BadProtocol("Truncated blob chunk".to_owned())
+}
+
+#[test]
+fn test_blob_reader() {
+    let empty: &[u8] = &[];
+
+    assert!(BlobReader::new(empty).next().is_none());
+
+    let blob = b"LPAV\x00\x00\x00\x0242\
+                 ACCT\x00\x00\x00\x00\
+                 ACFL\x00\x00\x00\x03abc";
+
+    let chunks: Vec<_> =
+        BlobReader::new(blob).map(|c| c.unwrap()).collect();
+
+    assert_eq!(chunks, vec![
+        Chunk { id: *b"LPAV", data: b"42" },
+        Chunk { id: *b"ACCT", data: b"" },
+        Chunk { id: *b"ACFL", data: b"abc" },
+    ]);
+}
+
+#[test]
+fn test_version() {
+    let blob = b"ACCT\x00\x00\x00\x00\
+                 LPAV\x00\x00\x00\x0242";
+
+    assert_eq!(version(blob).unwrap(), Some(42));
+    assert_eq!(version(b"ACCT\x00\x00\x00\x00").unwrap(), None);
+    assert!(version(b"LPAV\x00\x00\x00\x02xx").is_err());
+}
+
+#[test]
+fn test_blob_reader_truncated() {
+    let truncated: &[&[u8]] = &[
+        // Truncated header
+        b"LPA",
+        b"LPAV\x00\x00",
+        // Length extends past the end of the buffer
+        b"LPAV\x00\x00\x00\x0542",
+        b"LPAV\x00\x00\x00\x0242ACCT\x00\x00\x01\x00abc",
+        b"LPAV\xff\xff\xff\xff",
+    ];
+
+    for &blob in truncated {
+        let mut reader = BlobReader::new(blob);
+
+        loop {
+            match reader.next() {
+                Some(Ok(_)) => (),
+                Some(Err(Error::BadProtocol(_))) => break,
+                Some(Err(e)) => panic!("Unexpected error {:?}", e),
+                None => panic!("Truncated blob parsed successfully"),
+            }
+        }
+
+        // The reader should stop after an error
+        assert!(reader.next().is_none());
+    }
+}
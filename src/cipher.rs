@@ -0,0 +1,635 @@
+//! AES-256 field encryption and decryption
+
+use Result;
+use Error;
+use SecureStorage;
+use crypto;
+use crypto::AES_BLOCK_LEN;
+
+use openssl::symm::{Cipher, Crypter, Mode};
+use openssl::rand::rand_bytes;
+use openssl::pkey::PKey;
+use openssl::rsa;
+use base64;
+
+use std::io::Write;
+
+/// Decrypt a base64-encoded field using `key`. LastPass uses two
+/// formats for the encrypted fields:
+///
+/// * `!<base64 IV>|<base64 ciphertext>` for AES-256-CBC
+/// * `<base64 ciphertext>` for AES-256-ECB
+///
+/// The decrypted data is returned in a `SecureStorage`.
+pub fn decrypt_field(key: &[u8], data: &[u8]) -> Result<SecureStorage> {
+    if data.is_empty() {
+        return Ok(SecureStorage::empty());
+    }
+
+    let (iv, ciphertext) = try!(split_field(data));
+
+    decrypt(key, iv.as_ref().map(|iv| &**iv), &ciphertext)
+}
+
+/// Split a field in the `decrypt_field` format into the IV (if it's
+/// CBC) and the ciphertext
+fn split_field(data: &[u8]) -> Result<(Option<Vec<u8>>, Vec<u8>)> {
+    let pipe = data.iter().position(|&b| b == b'|');
+
+    match (data.first(), pipe) {
+        (Some(&b'!'), Some(pipe)) => {
+            let iv = try!(base64_decode(&data[1..pipe]));
+            let ciphertext = try!(base64_decode(&data[pipe + 1..]));
+
+            if iv.len() != 16 {
+                let err = format!("Invalid IV length ({})", iv.len());
+                return Err(Error::BadProtocol(err));
+            }
+
+            Ok((Some(iv), ciphertext))
+        }
+        _ => Ok((None, try!(base64_decode(data)))),
+    }
+}
+
+/// Size of the blocks decrypted at once by `decrypt_attachment`
+const ATTACHMENT_CHUNK_LEN: usize = 64 * 1024;
+
+/// Decrypt an attachment downloaded by `Session::get_attachment` and
+/// write the file to `out`. The file is base64-encoded then encrypted
+/// like a field (see `decrypt_field`). It's decrypted and decoded
+/// `ATTACHMENT_CHUNK_LEN` bytes at a time to never hold the whole
+/// plaintext in memory. Returns the size of the file.
+pub fn decrypt_attachment<W>(key: &[u8],
+                             data: &[u8],
+                             out: &mut W) -> Result<u64>
+    where W: Write {
+    let (iv, ciphertext) = try!(split_field(data));
+
+    try!(check_ciphertext_len(&ciphertext));
+
+    let mut decrypter =
+        try!(crypto::Decrypter::new(key, iv.as_ref().map(|iv| &**iv)));
+
+    let mut plaintext =
+        try!(SecureStorage::from_vec(vec![0; ATTACHMENT_CHUNK_LEN +
+                                             AES_BLOCK_LEN]));
+
+    // Decrypted base64 not decoded yet
+    let mut pending = SecureStorage::empty();
+
+    let mut written = 0;
+
+    for chunk in ciphertext.chunks(ATTACHMENT_CHUNK_LEN) {
+        let len = try!(decrypter.update(chunk, &mut plaintext));
+
+        try!(pending.extend_from_slice(&plaintext[..len]));
+
+        written += try!(write_base64(&mut pending, out, false));
+    }
+
+    let len = try!(decrypter.finalize(&mut plaintext));
+
+    try!(pending.extend_from_slice(&plaintext[..len]));
+
+    written += try!(write_base64(&mut pending, out, true));
+
+    Ok(written)
+}
+
+/// Decode the base64 data in `pending` and write it to `out`. Unless
+/// `last` is true the trailing incomplete base64 group is left in
+/// `pending`. Returns the number of bytes written.
+fn write_base64<W>(pending: &mut SecureStorage,
+                   out: &mut W,
+                   last: bool) -> Result<u64>
+    where W: Write {
+    let end = if last { pending.len() } else { pending.len() / 4 * 4 };
+
+    let decoded =
+        try!(SecureStorage::from_vec(try!(base64_decode(&pending[..end]))));
+
+    try!(out.write_all(&decoded));
+
+    let rest = try!(SecureStorage::from_slice(&pending[end..]));
+
+    *pending = rest;
+
+    Ok(decoded.len() as u64)
+}
+
+/// Decrypt a raw binary field as found in the account blob. The
+/// formats are the same as for `decrypt_field` without the base64
+/// encoding:
+///
+/// * `!<16 bytes IV><ciphertext>` for AES-256-CBC
+/// * `<ciphertext>` for AES-256-ECB
+pub fn decrypt_raw(key: &[u8], data: &[u8]) -> Result<SecureStorage> {
+    if data.is_empty() {
+        return Ok(SecureStorage::empty());
+    }
+
+    // Same heuristic as the C client
+    if data.len() >= 33 && data.len() % 16 == 1 && data[0] == b'!' {
+        let iv = &data[1..17];
+        let ciphertext = &data[17..];
+
+        decrypt(key, Some(iv), ciphertext)
+    } else {
+        decrypt(key, None, data)
+    }
+}
+
+/// Tags around the hex-encoded private key, see `decrypt_private_key`
+const PRIVATE_KEY_START: &'static [u8] = b"LastPassPrivateKey<";
+const PRIVATE_KEY_END: &'static [u8] = b">LastPassPrivateKey";
+
+/// Decrypt the user's RSA private key. `data` is the `privatekeyenc`
+/// returned by the server on login, either hex-encoded AES-256-CBC
+/// using the first 16 bytes of `key` as IV or a regular CBC field
+/// like the ones accepted by `decrypt_field`.
+///
+/// The plaintext is the hex-encoded DER (PKCS#8) key wrapped between
+/// `LastPassPrivateKey<` and `>LastPassPrivateKey`, the decoded DER
+/// key is returned.
+pub fn decrypt_private_key(key: &[u8], data: &[u8]) -> Result<SecureStorage> {
+    let bad_key = || Error::BadProtocol("Invalid private key".to_owned());
+
+    if data.is_empty() {
+        return Err(bad_key());
+    }
+
+    let decrypted =
+        if data[0] == b'!' {
+            try!(decrypt_field(key, data))
+        } else {
+            let ciphertext = try!(hex_decode(data));
+
+            try!(decrypt(key, Some(&key[..16]), &ciphertext))
+        };
+
+    let start =
+        match find(&decrypted, PRIVATE_KEY_START) {
+            Some(p) => p + PRIVATE_KEY_START.len(),
+            None => return Err(bad_key()),
+        };
+
+    let end =
+        match find(&decrypted[start..], PRIVATE_KEY_END) {
+            Some(p) => start + p,
+            None => return Err(bad_key()),
+        };
+
+    hex_decode(&decrypted[start..end])
+}
+
+/// Encrypt the DER-encoded RSA `private_key` with `key` in the
+/// hex-encoded format accepted by `decrypt_private_key`
+pub fn encrypt_private_key(key: &[u8], private_key: &[u8]) -> Result<Vec<u8>> {
+    const HEX: &'static [u8] = b"0123456789abcdef";
+
+    let len =
+        PRIVATE_KEY_START.len() + private_key.len() * 2 +
+        PRIVATE_KEY_END.len();
+
+    let mut wrapped = try!(SecureStorage::with_capacity(len));
+
+    try!(wrapped.extend_from_slice(PRIVATE_KEY_START));
+
+    for &b in private_key {
+        try!(wrapped.push(HEX[(b >> 4) as usize]));
+        try!(wrapped.push(HEX[(b & 0xf) as usize]));
+    }
+
+    try!(wrapped.extend_from_slice(PRIVATE_KEY_END));
+
+    let ciphertext =
+        try!(crypto::encrypt(key, Some(&key[..16]), &wrapped));
+
+    let mut encoded = Vec::with_capacity(ciphertext.len() * 2);
+
+    for b in ciphertext {
+        encoded.push(HEX[(b >> 4) as usize]);
+        encoded.push(HEX[(b & 0xf) as usize]);
+    }
+
+    Ok(encoded)
+}
+
+/// Decrypt `ciphertext` with the DER-encoded RSA `private_key` as
+/// returned by `decrypt_private_key`. LastPass uses OAEP padding.
+pub fn rsa_decrypt(private_key: &[u8],
+                   ciphertext: &[u8]) -> Result<SecureStorage> {
+    let pkey = try!(PKey::private_key_from_der(private_key));
+    let rsa = try!(pkey.rsa());
+
+    let mut plaintext = try!(SecureStorage::from_vec(vec![0; rsa.size()]));
+
+    let len = try!(rsa.private_decrypt(ciphertext,
+                                       &mut plaintext,
+                                       rsa::PKCS1_OAEP_PADDING));
+
+    plaintext.truncate(len);
+
+    Ok(plaintext)
+}
+
+/// Encrypt `plaintext` with the DER-encoded (SubjectPublicKeyInfo)
+/// RSA `public_key` using OAEP padding, like the sharing keys of
+/// LastPass
+pub fn rsa_encrypt(public_key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let rsa = try!(rsa::Rsa::public_key_from_der(public_key));
+
+    let mut ciphertext = vec![0; rsa.size()];
+
+    let len = try!(rsa.public_encrypt(plaintext,
+                                      &mut ciphertext,
+                                      rsa::PKCS1_OAEP_PADDING));
+
+    ciphertext.truncate(len);
+
+    Ok(ciphertext)
+}
+
+/// Encrypt `plaintext` using `key` with AES-256-CBC and a random
+/// IV. The result is formatted like the fields accepted by
+/// `decrypt_field`: `!<base64 IV>|<base64 ciphertext>`. An empty
+/// `plaintext` gives an empty field.
+pub fn encrypt_field(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    if plaintext.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut iv = [0; 16];
+
+    try!(rand_bytes(&mut iv));
+
+    let ciphertext =
+        try!(crypto::encrypt(key, Some(&iv), plaintext));
+
+    let mut field = Vec::new();
+
+    field.push(b'!');
+    field.extend_from_slice(base64::encode(&iv).as_bytes());
+    field.push(b'|');
+    field.extend_from_slice(base64::encode(&ciphertext).as_bytes());
+
+    Ok(field)
+}
+
+/// Encrypt `plaintext` using `key` with AES-256-ECB. The result is
+/// the base64-encoded ciphertext. This is only meant for legacy
+/// fields that LastPass still stores in ECB mode, use
+/// `encrypt_field` for everything else.
+pub fn encrypt_field_ecb(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    if plaintext.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ciphertext =
+        try!(crypto::encrypt(key, None, plaintext));
+
+    Ok(base64::encode(&ciphertext).into_bytes())
+}
+
+/// Length of the nonce used by `seal`
+const GCM_NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag appended by `seal`
+const GCM_TAG_LEN: usize = 16;
+
+/// Encrypt and authenticate `plaintext` using `key` with AES-256-GCM
+/// and a random nonce. `aad` is authenticated but not encrypted. The
+/// result is the nonce followed by the ciphertext and the tag.
+pub fn seal(key: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0; GCM_NONCE_LEN];
+
+    try!(rand_bytes(&mut nonce));
+
+    let cipher = Cipher::aes_256_gcm();
+    let block_size = cipher.block_size();
+
+    let mut crypter = try!(Crypter::new(cipher,
+                                        Mode::Encrypt,
+                                        key,
+                                        Some(&nonce)));
+
+    try!(crypter.aad_update(aad));
+
+    let mut sealed = nonce.to_vec();
+
+    let mut ciphertext = vec![0; plaintext.len() + block_size];
+
+    let mut len = try!(crypter.update(plaintext, &mut ciphertext));
+    len += try!(crypter.finalize(&mut ciphertext[len..]));
+
+    sealed.extend_from_slice(&ciphertext[..len]);
+
+    let mut tag = [0; GCM_TAG_LEN];
+
+    try!(crypter.get_tag(&mut tag));
+
+    sealed.extend_from_slice(&tag);
+
+    Ok(sealed)
+}
+
+/// Decrypt data encrypted by `seal`. Fails if `sealed` or `aad` have
+/// been tampered with or if `key` is wrong.
+pub fn open(key: &[u8], aad: &[u8], sealed: &[u8]) -> Result<SecureStorage> {
+    if sealed.len() < GCM_NONCE_LEN + GCM_TAG_LEN {
+        let err = format!("Sealed data too short ({})", sealed.len());
+        return Err(Error::BadProtocol(err));
+    }
+
+    let (nonce, rest) = sealed.split_at(GCM_NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - GCM_TAG_LEN);
+
+    let cipher = Cipher::aes_256_gcm();
+    let block_size = cipher.block_size();
+
+    let mut crypter = try!(Crypter::new(cipher,
+                                        Mode::Decrypt,
+                                        key,
+                                        Some(nonce)));
+
+    try!(crypter.aad_update(aad));
+
+    let mut plaintext =
+        try!(SecureStorage::from_vec(vec![0; ciphertext.len() + block_size]));
+
+    let mut len = try!(crypter.update(ciphertext, &mut plaintext));
+
+    try!(crypter.set_tag(tag));
+
+    // This is where the tag is checked
+    len += try!(crypter.finalize(&mut plaintext[len..]));
+
+    SecureStorage::from_slice(&plaintext[..len])
+}
+
+/// Decrypt `ciphertext` with AES-256-CBC if `iv` is given,
+/// AES-256-ECB otherwise
+fn decrypt(key: &[u8],
+           iv: Option<&[u8]>,
+           ciphertext: &[u8]) -> Result<SecureStorage> {
+    try!(check_ciphertext_len(ciphertext));
+
+    crypto::decrypt(key, iv, ciphertext)
+}
+
+/// Make sure that `ciphertext` is made of whole AES blocks
+fn check_ciphertext_len(ciphertext: &[u8]) -> Result<()> {
+    if ciphertext.len() % AES_BLOCK_LEN != 0 {
+        let err = format!("Invalid ciphertext length ({})", ciphertext.len());
+        return Err(Error::BadProtocol(err));
+    }
+
+    Ok(())
+}
+
+/// Return the position of the first occurrence of `needle` in
+/// `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Decode the hex string `data`
+pub fn hex_decode(data: &[u8]) -> Result<SecureStorage> {
+    let bad_hex = || Error::BadProtocol("Invalid hex string".to_owned());
+
+    if data.len() % 2 != 0 {
+        return Err(bad_hex());
+    }
+
+    let mut decoded = try!(SecureStorage::with_capacity(data.len() / 2));
+
+    for pair in data.chunks(2) {
+        let s = try!(::std::str::from_utf8(pair).map_err(|_| bad_hex()));
+        let b = try!(u8::from_str_radix(s, 16).map_err(|_| bad_hex()));
+
+        try!(decoded.push(b));
+    }
+
+    Ok(decoded)
+}
+
+fn base64_decode(data: &[u8]) -> Result<Vec<u8>> {
+    match base64::decode(data) {
+        Ok(d) => Ok(d),
+        Err(_) => Err(Error::BadProtocol("Invalid base64 data".to_owned())),
+    }
+}
+
+#[test]
+fn test_decrypt_field() {
+    let key: Vec<u8> = (0..32).collect();
+
+    let tests: &[(&[u8], &[u8])] = &[
+        (b"", b""),
+        // ECB
+        (b"kLxO/sQ8w5Jq6Zs5pBq8Ow==",
+         b"lpass"),
+        (b"7bpahrsEVb5iRdkRBlwi4Z6xA6E2fnXcAlxl5IdPyy4=",
+         b"correct horse battery staple"),
+        // CBC
+        (b"!oKGio6SlpqeoqaqrrK2urw==|\
+           uRPVwQfmuZqcUnjkZPQXK7EovoZy/QADhPwCKwYQpGU=",
+         b"correct horse battery staple"),
+    ];
+
+    for &(data, expected) in tests {
+        let plaintext = decrypt_field(&key, data).unwrap();
+        let expected = SecureStorage::from_slice(expected).unwrap();
+
+        assert!(plaintext == expected);
+    }
+
+    let invalid: &[&[u8]] = &[
+        // Not base64
+        b"#!?",
+        b"!oKGio6SlpqeoqaqrrK2urw==|#!?",
+        // Bad IV length
+        b"!oKGio6SlpqeoqaqrrK2u|kLxO/sQ8w5Jq6Zs5pBq8Ow==",
+        // Ciphertext length is not a multiple of the block size
+        b"kLxO/sQ8w5Jq6Zs5pBq8",
+    ];
+
+    for &data in invalid {
+        match decrypt_field(&key, data) {
+            Err(Error::BadProtocol(_)) => (),
+            _ => panic!("Invalid field was decrypted"),
+        }
+    }
+}
+
+#[test]
+fn test_encrypt_field() {
+    let key: Vec<u8> = (0..32).collect();
+
+    let plaintexts: &[&[u8]] = &[
+        b"",
+        b"lpass",
+        b"correct horse battery staple",
+        b"0123456789abcdef",
+    ];
+
+    for &plaintext in plaintexts {
+        let cbc = encrypt_field(&key, plaintext).unwrap();
+        let ecb = encrypt_field_ecb(&key, plaintext).unwrap();
+
+        if !plaintext.is_empty() {
+            assert!(cbc[0] == b'!');
+            assert!(ecb[0] != b'!');
+        }
+
+        for field in &[cbc, ecb] {
+            let decrypted = decrypt_field(&key, field).unwrap();
+
+            assert!(decrypted.ct_eq(plaintext));
+        }
+    }
+
+    // Same vector as in test_decrypt_field
+    assert_eq!(encrypt_field_ecb(&key, b"lpass").unwrap(),
+               b"kLxO/sQ8w5Jq6Zs5pBq8Ow==".to_vec());
+
+    // The IV must be random
+    assert!(encrypt_field(&key, b"lpass").unwrap() !=
+            encrypt_field(&key, b"lpass").unwrap());
+}
+
+#[test]
+fn test_seal() {
+    let key: Vec<u8> = (0..32).collect();
+    let other_key: Vec<u8> = (1..33).collect();
+
+    let sealed = seal(&key, b"header", b"lpass").unwrap();
+
+    assert_eq!(sealed.len(), GCM_NONCE_LEN + 5 + GCM_TAG_LEN);
+    assert!(open(&key, b"header", &sealed).unwrap().ct_eq(b"lpass"));
+
+    // The nonce is random
+    assert!(seal(&key, b"header", b"lpass").unwrap() != sealed);
+
+    assert!(open(&other_key, b"header", &sealed).is_err());
+    assert!(open(&key, b"other header", &sealed).is_err());
+    assert!(open(&key, b"header", &sealed[..GCM_TAG_LEN]).is_err());
+
+    for i in 0..sealed.len() {
+        let mut tampered = sealed.clone();
+
+        tampered[i] ^= 0x80;
+
+        assert!(open(&key, b"header", &tampered).is_err());
+    }
+
+    let empty = seal(&key, b"", b"").unwrap();
+
+    assert!(open(&key, b"", &empty).unwrap().is_empty());
+}
+
+#[test]
+fn test_decrypt_private_key() {
+    let key: Vec<u8> = (0..32).collect();
+
+    let der = [0x30, 0x82, 0x01, 0x02, 0xde, 0xad, 0xbe, 0xef];
+
+    // Hex-encoded CBC with the IV taken from the key
+    let wrapped =
+        b"f2bea062545b1db528ee1ef6cd99f2c6b28d15c359c98b8ddbfc534bbdfd09ec\
+          b4775d6334fdba33b5cac0c518fde8dda9f2c9b04f4f8745aff7ff87cb4c0eb9";
+
+    let decrypted = decrypt_private_key(&key, wrapped).unwrap();
+
+    assert!(decrypted.ct_eq(&der));
+
+    // Regular CBC field
+    let plaintext = b"LastPassPrivateKey<30820102deadbeef>LastPassPrivateKey";
+    let field = encrypt_field(&key, plaintext).unwrap();
+
+    let decrypted = decrypt_private_key(&key, &field).unwrap();
+
+    assert!(decrypted.ct_eq(&der));
+
+    // Missing wrapper
+    let field = encrypt_field(&key, b"30820102deadbeef").unwrap();
+
+    assert!(decrypt_private_key(&key, &field).is_err());
+    assert!(decrypt_private_key(&key, b"").is_err());
+}
+
+#[test]
+fn test_rsa_decrypt() {
+    // 512-bit key, way too small to be used for anything else
+    let private_key =
+        hex_decode(b"30820153020100300d06092a864886f70d01010105000482013d3082\
+                      0139020100024100bd305b1340cee7ce505bd6761639a6ccb2d2463a\
+                      94b9235644025215c8994ac0af93c3a9e3361ecffcb8a15f2529bf09\
+                      94342680f811f5edae3bfb66647c85410203010001024046599ee44f\
+                      eb2083b59a10eba46417c47292828ef212ce331be3090714aafc027b\
+                      11e209e155809259f311a25b3920890f5a55a241f369899d023a9809\
+                      2da1c9022100e0e4527d884bd8e73d6117b798808d77c28d5c0a28aa\
+                      f626d3ce27b99552631f022100d75bbff60d6594163a3612f50b550d\
+                      a99a51abd8dd9651587c23eb79d7bd6b9f0220012c6fd9c3da517973\
+                      728c43230298ba6543839571fce267f576fff211fb6a150220010676\
+                      cb7d14d6b7a45f4d87cd1e028aa4296f6000ee0e4515923634c64a28\
+                      910220088539d73afcaba445448beaecd80848aeba603a34d0959c50\
+                      cc09b6a0ba38f5").unwrap();
+
+    let ciphertext =
+        hex_decode(b"5755ed8e04e4262688fe3fa3f3e7ac33c090a0fb14ec72b5914fac76\
+                      0472fe1e57d17db3b11d1b76e12f831120cbe4015c7b90efcf5f6c27\
+                      1711c625ca358ada").unwrap();
+
+    let plaintext = rsa_decrypt(&private_key, &ciphertext).unwrap();
+
+    assert!(plaintext.ct_eq(b"lpass"));
+
+    assert!(rsa_decrypt(&private_key, b"lpass").is_err());
+    assert!(rsa_decrypt(b"", &ciphertext).is_err());
+
+    let public_key =
+        hex_decode(b"305c300d06092a864886f70d0101010500034b003048024100bd30\
+                      5b1340cee7ce505bd6761639a6ccb2d2463a94b9235644025215c8\
+                      994ac0af93c3a9e3361ecffcb8a15f2529bf0994342680f811f5ed\
+                      ae3bfb66647c85410203010001").unwrap();
+
+    // OAEP is randomized, we can only check the round trip
+    let ciphertext = rsa_encrypt(&public_key, b"lpass").unwrap();
+
+    assert!(rsa_decrypt(&private_key, &ciphertext).unwrap().ct_eq(b"lpass"));
+
+    assert!(rsa_encrypt(&private_key, b"lpass").is_err());
+
+    let key = [0x42; 32];
+    let encrypted = encrypt_private_key(&key, &private_key).unwrap();
+
+    assert!(encrypted.iter().all(|b| b"0123456789abcdef".contains(b)));
+    assert!(decrypt_private_key(&key, &encrypted).unwrap()
+            .ct_eq(&private_key));
+}
+
+#[test]
+fn test_decrypt_attachment() {
+    let key: Vec<u8> = (0..32).collect();
+
+    // Large enough to be decrypted in several chunks
+    let file: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+    let encoded = base64::encode(&file);
+
+    for field in &[encrypt_field(&key, encoded.as_bytes()).unwrap(),
+                   encrypt_field_ecb(&key, encoded.as_bytes()).unwrap()] {
+        let mut out = Vec::new();
+
+        let len = decrypt_attachment(&key, field, &mut out).unwrap();
+
+        assert_eq!(len, file.len() as u64);
+        assert!(out == file);
+    }
+
+    let mut out = Vec::new();
+
+    assert!(decrypt_attachment(&key, b"!AAAA|AAAA", &mut out).is_err());
+}
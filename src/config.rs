@@ -0,0 +1,188 @@
+//! Configuration directory handling
+//!
+//! All the persistent state is stored in `$LPASS_HOME` which defaults
+//! to `~/.lpass`. Files are created readable only by the user.
+
+use Result;
+use Error;
+use SecureStorage;
+use platform;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Return the path to the configuration directory, creating it if
+/// necessary.
+pub fn home() -> Result<PathBuf> {
+    let home =
+        match env::var_os("LPASS_HOME") {
+            Some(h) => PathBuf::from(h),
+            None => {
+                match platform::home_dir() {
+                    Some(h) => h.join(".lpass"),
+                    None => {
+                        let err = "Can't locate the home directory";
+                        return Err(Error::Unsupported(err.to_owned()));
+                    }
+                }
+            }
+        };
+
+    if !home.is_dir() {
+        debug!("Creating {}", home.display());
+
+        try!(platform::create_private_dir(&home));
+    }
+
+    Ok(home)
+}
+
+/// Return the path of the configuration file `name`
+pub fn path(name: &str) -> Result<PathBuf> {
+    let home = try!(home());
+
+    Ok(home.join(name))
+}
+
+/// Return `true` if the configuration file `name` exists
+pub fn exists(name: &str) -> Result<bool> {
+    let path = try!(path(name));
+
+    Ok(path.exists())
+}
+
+/// Return the time elapsed since the configuration file `name` was
+/// last modified, or `None` if it doesn't exist.
+pub fn age(name: &str) -> Result<Option<Duration>> {
+    let path = try!(path(name));
+
+    let metadata =
+        match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+    let modified = try!(metadata.modified());
+
+    // If the file is in the future consider that it's brand new
+    let age =
+        SystemTime::now().duration_since(modified)
+        .unwrap_or(Duration::from_secs(0));
+
+    Ok(Some(age))
+}
+
+/// Write `data` to the configuration file `name`, replacing any
+/// existing content. The file is only readable by the user.
+pub fn write(name: &str, data: &[u8]) -> Result<()> {
+    let path = try!(path(name));
+
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    let mut file =
+        try!(platform::open_private_file(&mut options).open(&path));
+
+    // The permissions only apply if the file is created
+    try!(platform::make_private(&file));
+
+    try!(file.write_all(data));
+
+    Ok(())
+}
+
+/// Read the configuration file `name`, returns `None` if it doesn't
+/// exist. Fails if the file is accessible by other users.
+pub fn read(name: &str) -> Result<Option<SecureStorage>> {
+    let path = try!(path(name));
+
+    let mut file =
+        match fs::File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+    let metadata = try!(file.metadata());
+
+    if !platform::is_private(&metadata) {
+        return Err(Error::InsecurePermissions(path));
+    }
+
+    let mut data =
+        try!(SecureStorage::from_vec(vec![0; metadata.len() as usize]));
+
+    try!(file.read_exact(&mut data));
+
+    Ok(Some(data))
+}
+
+/// Read the non-secret configuration file `name`, returns `None` if
+/// it doesn't exist. Unlike `read` the file can be readable by other
+/// users but it must not be writable by them.
+pub fn read_public(name: &str) -> Result<Option<Vec<u8>>> {
+    let path = try!(path(name));
+
+    let mut file =
+        match fs::File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+    let metadata = try!(file.metadata());
+
+    if platform::is_writable_by_others(&metadata) {
+        return Err(Error::InsecurePermissions(path));
+    }
+
+    let mut data = Vec::new();
+
+    try!(file.read_to_end(&mut data));
+
+    Ok(Some(data))
+}
+
+/// Remove the configuration file `name`. Doesn't fail if the file
+/// doesn't exist.
+pub fn unlink(name: &str) -> Result<()> {
+    let path = try!(path(name));
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Overwrite the configuration file `name` with zeroes before
+/// removing it. Doesn't fail if the file doesn't exist.
+pub fn shred(name: &str) -> Result<()> {
+    let path = try!(path(name));
+
+    let len =
+        match fs::metadata(&path) {
+            Ok(m) => m.len() as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+    {
+        let mut file = try!(fs::OpenOptions::new().write(true).open(&path));
+
+        try!(file.write_all(&vec![0; len]));
+        try!(file.sync_all());
+    }
+
+    unlink(name)
+}
@@ -0,0 +1,392 @@
+//! Cryptographic primitives backend
+//!
+//! The key derivation, hashing and AES-256 CBC/ECB primitives used
+//! by `kdf`, `cipher` and the certificate pinning are implemented
+//! with OpenSSL by default or with the pure Rust RustCrypto crates
+//! when the `rustcrypto` feature is enabled. Both backends must
+//! produce exactly the same results.
+//!
+//! OpenSSL is still needed for the rest (TLS, RSA, AES-GCM and random
+//! numbers) whatever the backend.
+
+use Result;
+use SecureStorage;
+
+pub use self::backend::{pbkdf2_sha256, sha256, Decrypter, encrypt};
+
+/// Size of an AES block in bytes
+pub const AES_BLOCK_LEN: usize = 16;
+
+/// Decrypt `ciphertext` with AES-256 in CBC mode if `iv` is given,
+/// ECB otherwise. The PKCS#7 padding is removed.
+pub fn decrypt(key: &[u8],
+               iv: Option<&[u8]>,
+               ciphertext: &[u8]) -> Result<SecureStorage> {
+    let mut decrypter = try!(Decrypter::new(key, iv));
+
+    // Room for the additional block returned by `finalize`
+    let mut plaintext =
+        try!(SecureStorage::from_vec(vec![0; ciphertext.len() +
+                                             AES_BLOCK_LEN]));
+
+    let mut len = try!(decrypter.update(ciphertext, &mut plaintext));
+    len += try!(decrypter.finalize(&mut plaintext[len..]));
+
+    SecureStorage::from_slice(&plaintext[..len])
+}
+
+#[cfg(not(feature = "rustcrypto"))]
+mod backend {
+    use Result;
+
+    use openssl::pkcs5;
+    use openssl::hash::{self, MessageDigest};
+    use openssl::symm::{Cipher, Crypter, Mode};
+
+    /// PBKDF2 with HMAC-SHA256, the key is written to `out`
+    pub fn pbkdf2_sha256(password: &[u8],
+                         salt: &[u8],
+                         iterations: u32,
+                         out: &mut [u8]) -> Result<()> {
+        try!(pkcs5::pbkdf2_hmac(password,
+                                salt,
+                                iterations as usize,
+                                MessageDigest::sha256(),
+                                out));
+
+        Ok(())
+    }
+
+    /// Return the SHA-256 hash of `data`
+    pub fn sha256(data: &[u8]) -> Result<[u8; 32]> {
+        let h = try!(hash::hash(MessageDigest::sha256(), data));
+
+        let mut digest = [0; 32];
+
+        digest.copy_from_slice(&h);
+
+        Ok(digest)
+    }
+
+    fn cipher(iv: Option<&[u8]>) -> Cipher {
+        match iv {
+            Some(_) => Cipher::aes_256_cbc(),
+            None => Cipher::aes_256_ecb(),
+        }
+    }
+
+    /// Encrypt `plaintext` with AES-256 in CBC mode if `iv` is given,
+    /// ECB otherwise, using PKCS#7 padding
+    pub fn encrypt(key: &[u8],
+                   iv: Option<&[u8]>,
+                   plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut crypter =
+            try!(Crypter::new(cipher(iv), Mode::Encrypt, key, iv));
+
+        let mut ciphertext = vec![0; plaintext.len() + super::AES_BLOCK_LEN];
+
+        let mut len = try!(crypter.update(plaintext, &mut ciphertext));
+        len += try!(crypter.finalize(&mut ciphertext[len..]));
+
+        ciphertext.truncate(len);
+
+        Ok(ciphertext)
+    }
+
+    /// Incremental AES-256 decryption in CBC mode if an IV is given,
+    /// ECB otherwise. The output buffers must have room for one more
+    /// block than the input.
+    pub struct Decrypter(Crypter);
+
+    impl Decrypter {
+        pub fn new(key: &[u8], iv: Option<&[u8]>) -> Result<Decrypter> {
+            let crypter =
+                try!(Crypter::new(cipher(iv), Mode::Decrypt, key, iv));
+
+            Ok(Decrypter(crypter))
+        }
+
+        /// Decrypt `input` into `output`, returns the number of bytes
+        /// written. The last block is held back until `finalize`.
+        pub fn update(&mut self,
+                      input: &[u8],
+                      output: &mut [u8]) -> Result<usize> {
+            Ok(try!(self.0.update(input, output)))
+        }
+
+        /// Write the last block without its padding to `output`
+        pub fn finalize(&mut self, output: &mut [u8]) -> Result<usize> {
+            Ok(try!(self.0.finalize(output)))
+        }
+    }
+}
+
+#[cfg(feature = "rustcrypto")]
+mod backend {
+    use Result;
+    use Error;
+
+    use super::AES_BLOCK_LEN;
+
+    use aes::Aes256;
+    use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+    use aes::cipher::generic_array::GenericArray;
+    use sha2::{Digest, Sha256};
+
+    /// PBKDF2 with HMAC-SHA256, the key is written to `out`
+    pub fn pbkdf2_sha256(password: &[u8],
+                         salt: &[u8],
+                         iterations: u32,
+                         out: &mut [u8]) -> Result<()> {
+        ::pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, out);
+
+        Ok(())
+    }
+
+    /// Return the SHA-256 hash of `data`
+    pub fn sha256(data: &[u8]) -> Result<[u8; 32]> {
+        let mut digest = [0; 32];
+
+        digest.copy_from_slice(&Sha256::digest(data));
+
+        Ok(digest)
+    }
+
+    fn new_cipher(key: &[u8]) -> Result<Aes256> {
+        match Aes256::new_from_slice(key) {
+            Ok(c) => Ok(c),
+            Err(_) => {
+                let err = format!("Invalid AES key length ({})", key.len());
+                Err(Error::BadProtocol(err))
+            }
+        }
+    }
+
+    fn new_iv(iv: Option<&[u8]>) -> Result<Option<[u8; AES_BLOCK_LEN]>> {
+        match iv {
+            Some(iv) if iv.len() == AES_BLOCK_LEN => {
+                let mut block = [0; AES_BLOCK_LEN];
+
+                block.copy_from_slice(iv);
+
+                Ok(Some(block))
+            }
+            Some(iv) => {
+                let err = format!("Invalid IV length ({})", iv.len());
+                Err(Error::BadProtocol(err))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn xor(block: &mut [u8], other: &[u8]) {
+        for (b, o) in block.iter_mut().zip(other) {
+            *b ^= *o;
+        }
+    }
+
+    /// Encrypt `plaintext` with AES-256 in CBC mode if `iv` is given,
+    /// ECB otherwise, using PKCS#7 padding
+    pub fn encrypt(key: &[u8],
+                   iv: Option<&[u8]>,
+                   plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = try!(new_cipher(key));
+        let mut prev = try!(new_iv(iv));
+
+        // There's always some padding, a full block if the plaintext
+        // is aligned
+        let pad = AES_BLOCK_LEN - plaintext.len() % AES_BLOCK_LEN;
+        let len = plaintext.len() + pad;
+
+        let mut data = Vec::with_capacity(len);
+
+        // The plaintext is only ever copied to `block`, where it's
+        // overwritten by the ciphertext
+        let mut block = [0; AES_BLOCK_LEN];
+
+        for start in (0..len).step_by(AES_BLOCK_LEN) {
+            for (i, b) in block.iter_mut().enumerate() {
+                *b = match plaintext.get(start + i) {
+                    Some(&p) => p,
+                    None => pad as u8,
+                };
+            }
+
+            if let Some(ref p) = prev {
+                xor(&mut block, p);
+            }
+
+            cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+
+            if let Some(ref mut p) = prev {
+                p.copy_from_slice(&block);
+            }
+
+            data.extend_from_slice(&block);
+        }
+
+        Ok(data)
+    }
+
+    /// Incremental AES-256 decryption in CBC mode if an IV is given,
+    /// ECB otherwise. The output buffers must have room for one more
+    /// block than the input.
+    pub struct Decrypter {
+        cipher: Aes256,
+        /// Previous ciphertext block in CBC mode
+        prev: Option<[u8; AES_BLOCK_LEN]>,
+        /// Input bytes not making a full block yet
+        partial: Vec<u8>,
+        /// Last decrypted block, held back since it contains the
+        /// padding if it's the final one
+        last: Option<[u8; AES_BLOCK_LEN]>,
+    }
+
+    impl Decrypter {
+        pub fn new(key: &[u8], iv: Option<&[u8]>) -> Result<Decrypter> {
+            Ok(Decrypter {
+                cipher: try!(new_cipher(key)),
+                prev: try!(new_iv(iv)),
+                partial: Vec::with_capacity(AES_BLOCK_LEN),
+                last: None,
+            })
+        }
+
+        /// Decrypt `input` into `output`, returns the number of bytes
+        /// written. The last block is held back until `finalize`.
+        pub fn update(&mut self,
+                      input: &[u8],
+                      output: &mut [u8]) -> Result<usize> {
+            let mut written = 0;
+
+            for &b in input {
+                self.partial.push(b);
+
+                if self.partial.len() < AES_BLOCK_LEN {
+                    continue;
+                }
+
+                let mut block = [0; AES_BLOCK_LEN];
+
+                block.copy_from_slice(&self.partial);
+
+                for b in self.partial.iter_mut() {
+                    *b = 0;
+                }
+
+                self.partial.clear();
+
+                let ciphertext = block;
+
+                self.cipher
+                    .decrypt_block(GenericArray::from_mut_slice(&mut block));
+
+                if let Some(ref mut p) = self.prev {
+                    xor(&mut block, p);
+                    *p = ciphertext;
+                }
+
+                if let Some(last) = self.last.take() {
+                    output[written..written + AES_BLOCK_LEN]
+                        .copy_from_slice(&last);
+                    written += AES_BLOCK_LEN;
+                }
+
+                self.last = Some(block);
+            }
+
+            Ok(written)
+        }
+
+        /// Write the last block without its padding to `output`
+        pub fn finalize(&mut self, output: &mut [u8]) -> Result<usize> {
+            let bad_padding =
+                || Error::BadProtocol("Invalid AES padding".to_owned());
+
+            if !self.partial.is_empty() {
+                return Err(bad_padding());
+            }
+
+            let last =
+                match self.last.take() {
+                    Some(l) => l,
+                    None => return Err(bad_padding()),
+                };
+
+            let pad = last[AES_BLOCK_LEN - 1] as usize;
+
+            if pad == 0 || pad > AES_BLOCK_LEN ||
+                last[AES_BLOCK_LEN - pad..].iter().any(|&b| b as usize != pad) {
+                return Err(bad_padding());
+            }
+
+            let len = AES_BLOCK_LEN - pad;
+
+            output[..len].copy_from_slice(&last[..len]);
+
+            Ok(len)
+        }
+    }
+
+    /// Don't leave plaintext behind
+    impl Drop for Decrypter {
+        fn drop(&mut self) {
+            for b in self.partial.iter_mut() {
+                *b = 0;
+            }
+
+            if let Some(ref mut l) = self.last {
+                *l = [0; AES_BLOCK_LEN];
+            }
+        }
+    }
+}
+
+#[test]
+fn test_aes() {
+    let key: Vec<u8> = (0..32).collect();
+    let iv: Vec<u8> = (32..48).collect();
+
+    // AES-256-ECB("lpass"), same vector as in the cipher tests
+    let ecb = b"\x90\xbc\x4e\xfe\xc4\x3c\xc3\x92\
+                \x6a\xe9\x9b\x39\xa4\x1a\xbc\x3b";
+
+    assert_eq!(encrypt(&key, None, b"lpass").unwrap(), ecb.to_vec());
+    assert!(decrypt(&key, None, ecb).unwrap().ct_eq(b"lpass"));
+
+    for len in 0..50 {
+        let plaintext: Vec<u8> = (0..len).map(|b| b as u8).collect();
+
+        for iv in &[None, Some(&iv[..])] {
+            let ciphertext = encrypt(&key, *iv, &plaintext).unwrap();
+
+            assert_eq!(ciphertext.len(), (len / 16 + 1) * 16);
+
+            // Feed the ciphertext one byte at a time to exercise the
+            // buffering
+            let mut decrypter = Decrypter::new(&key, *iv).unwrap();
+            let mut decrypted = vec![0; ciphertext.len() + AES_BLOCK_LEN];
+            let mut pos = 0;
+
+            for b in ciphertext.chunks(1) {
+                pos += decrypter.update(b, &mut decrypted[pos..]).unwrap();
+            }
+
+            pos += decrypter.finalize(&mut decrypted[pos..]).unwrap();
+
+            assert_eq!(&decrypted[..pos], &plaintext[..]);
+        }
+    }
+
+    // Bad padding or truncated ciphertext
+    assert!(decrypt(&key, Some(&iv), &[0; 16]).is_err());
+    assert!(decrypt(&key, None, &ecb[..15]).is_err());
+}
+
+#[test]
+fn test_sha256() {
+    let h = sha256(b"hunter2").unwrap();
+
+    // Same as in `test_secret`
+    assert_eq!(&h[..4], b"\xf5\x2f\xbd\x32");
+}
@@ -1,9 +1,11 @@
 use std::convert::From;
+use std::error;
 use std::io;
 use std::fmt;
 use std::num;
 use std::string;
 use std::result;
+use std::path::PathBuf;
 
 use curl;
 use openssl;
@@ -27,12 +29,22 @@ pub enum Error {
     InvalidUser,
     /// Action failed because OTP auth is required
     OtpRequired(OtpMethod),
+    /// The OTP was rejected by the server. Contains the server's
+    /// message.
+    OtpFailed(OtpMethod, String),
+    /// Action requires an authenticated session
+    NotAuthenticated,
+    /// The server doesn't recognize our session anymore, it expired
+    /// or was revoked. A new login is needed.
+    SessionExpired,
     /// Input/output error
     IoError(io::Error),
     /// CURL library error
     CurlError(curl::Error),
     /// OpenSSL library error
     OpensslError(openssl::error::ErrorStack),
+    /// HTTP request timed out
+    Timeout,
     /// HTTP request didn't receive a 200 response
     HttpError(u32),
     /// A server reply didn't make sense
@@ -41,6 +53,16 @@ pub enum Error {
     Unsupported(String),
     /// Server returned an invalid XML
     XmlError(xml_reader::Error),
+    /// A configuration file is accessible by other users
+    InsecurePermissions(PathBuf),
+    /// Login refused because of an account, device or IP restriction.
+    /// Contains the server's description of the restriction.
+    AccountRestricted(String),
+    /// The login must be approved from the verification email sent
+    /// by LastPass before retrying
+    DeviceVerificationRequired,
+    /// The out-of-band login wasn't approved in time
+    ApprovalTimeout,
 }
 
 impl From<io::Error> for Error {
@@ -82,15 +104,61 @@ impl From<xml_reader::Error> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            &Error::BadUsage =>
+                write!(f, "Invalid command usage"),
+            &Error::UserAbort =>
+                write!(f, "Aborted by the user"),
+            &Error::InvalidPassword =>
+                write!(f, "Invalid password"),
+            &Error::InvalidUser =>
+                write!(f, "Unknown user"),
+            &Error::OtpRequired(m) =>
+                write!(f, "{} authentication required", m),
+            &Error::OtpFailed(m, ref msg) =>
+                write!(f, "{} authentication failed: {}", m, msg),
+            &Error::NotAuthenticated =>
+                write!(f, "Not logged in"),
+            &Error::SessionExpired =>
+                write!(f, "Session expired"),
+            &Error::IoError(ref e) =>
+                write!(f, "I/O error: {}", e),
             &Error::CurlError(ref e) =>
                 write!(f, "CURL library error: {}", e),
+            &Error::OpensslError(ref e) =>
+                write!(f, "OpenSSL library error: {}", e),
+            &Error::Timeout =>
+                write!(f, "HTTP request timed out"),
+            &Error::HttpError(code) =>
+                write!(f, "HTTP request failed with status {}", code),
             &Error::BadProtocol(ref e) =>
                 write!(f, "Protocol error: {}", e),
             &Error::Unsupported(ref e) =>
                 write!(f, "Unsupported: {}", e),
             &Error::XmlError(ref e) =>
                 write!(f, "Received invalid XML: {}", e),
-            e => write!(f, "{:?}", e)
+            &Error::InsecurePermissions(ref p) =>
+                write!(f, "{} is accessible by other users, \
+                           refusing to use it", p.display()),
+            &Error::AccountRestricted(ref c) =>
+                write!(f, "Login restricted: {}", c),
+            &Error::DeviceVerificationRequired =>
+                write!(f, "Device verification required, check your email \
+                           for a message from LastPass to approve this \
+                           login and try again"),
+            &Error::ApprovalTimeout =>
+                write!(f, "The login wasn't approved in time"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            &Error::IoError(ref e) => Some(e),
+            &Error::CurlError(ref e) => Some(e),
+            &Error::OpensslError(ref e) => Some(e),
+            &Error::XmlError(ref e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -4,15 +4,38 @@ use Result;
 use Error;
 use SecureStorage;
 
-use openssl::pkcs5;
-use openssl::hash::MessageDigest;
+use crypto::pbkdf2_sha256;
 
-/// Key derivation function used to generate the login key (the one
-/// sent to the server)
-pub fn login_key(username: &str,
-                 password: &[u8],
-                 iterations: u32) -> Result<SecureStorage> {
+#[cfg(feature = "argon2")]
+use argon2rs::{Argon2, Variant};
+
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+/// Default maximum number of iterations accepted by the key
+/// derivation functions
+pub const DEFAULT_MAX_ITERATIONS: u32 = 600_000;
+
+/// Maximum number of iterations, 0 means `DEFAULT_MAX_ITERATIONS`
+static MAX_ITERATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set the maximum number of iterations accepted by the key
+/// derivation functions. A corrupt or malicious iteration count
+/// could otherwise make us spin for a very long time.
+pub fn set_max_iterations(max: u32) {
+    MAX_ITERATIONS.store(max as usize, Ordering::Relaxed);
+}
+
+/// Return the maximum number of iterations accepted by the key
+/// derivation functions
+pub fn max_iterations() -> u32 {
+    match MAX_ITERATIONS.load(Ordering::Relaxed) {
+        0 => DEFAULT_MAX_ITERATIONS,
+        n => n as u32,
+    }
+}
 
+/// Make sure that `iterations` is within the supported range
+pub fn check_iterations(iterations: u32) -> Result<()> {
     // The C client doesn't do that but it's probably not a good idea
     // to work with a very low number of iterations. The C client has
     // a special KDF implementation when iterations == 1, so look
@@ -23,16 +46,37 @@ pub fn login_key(username: &str,
         return Err(Error::Unsupported(err));
     }
 
+    if iterations > max_iterations() {
+        let err = format!("Iteration count too high ({})", iterations);
+
+        return Err(Error::BadProtocol(err));
+    }
+
+    Ok(())
+}
+
+/// Key derivation function used to generate the login key (the one
+/// sent to the server)
+pub fn login_key(username: &str,
+                 password: &[u8],
+                 iterations: u32) -> Result<SecureStorage> {
+
+    try!(check_iterations(iterations));
+
     let decrypt_key =
         try!(crypto_key(username, password, iterations));
 
+    login_key_from_crypto_key(&decrypt_key, password)
+}
+
+/// Derive the login key from an already computed `crypto_key`. This
+/// is cheap, use it instead of `login_key` when both keys are needed
+/// to only run the slow KDF once.
+pub fn login_key_from_crypto_key(crypto_key: &[u8],
+                                 password: &[u8]) -> Result<SecureStorage> {
     let mut login_key = try!(SecureStorage::from_vec(vec![0; 32]));
 
-    try!(pkcs5::pbkdf2_hmac(&decrypt_key,
-                            password,
-                            1,
-                            MessageDigest::sha256(),
-                            &mut login_key));
+    try!(pbkdf2_sha256(crypto_key, password, 1, &mut login_key));
 
     Ok(login_key)
 }
@@ -43,27 +87,106 @@ pub fn crypto_key(username: &str,
                   password: &[u8],
                   iterations: u32) -> Result<SecureStorage> {
 
-    // The C client doesn't do that but it's probably not a good idea
-    // to work with a very low number of iterations. The C client has
-    // a special KDF implementation when iterations == 1, so look
-    // there if we ever need to implement that.
-    if iterations < 1000 {
-        let err = format!("Iteration count too low ({})", iterations);
+    try!(check_iterations(iterations));
+
+    let mut key = try!(SecureStorage::from_vec(vec![0; 32]));
+
+    try!(pbkdf2_sha256(password, username.as_bytes(), iterations, &mut key));
+
+    Ok(key)
+}
+
+/// Key used to encrypt the blob cache, derived from the `crypto_key`
+/// and a random `salt` stored alongside the cache. Since the crypto
+/// key is already the output of a slow KDF a single iteration is
+/// enough.
+pub fn cache_key(crypto_key: &[u8], salt: &[u8]) -> Result<SecureStorage> {
+    let mut key = try!(SecureStorage::from_vec(vec![0; 32]));
+
+    try!(pbkdf2_sha256(crypto_key, salt, 1, &mut key));
+
+    Ok(key)
+}
+
+/// Secret shared with the browser extension `extension` to
+/// authenticate its native messaging requests, derived from the
+/// `crypto_key` like `cache_key`. It stays valid until the master
+/// password changes and can't be computed without the crypto key.
+pub fn pairing_token(crypto_key: &[u8],
+                     extension: &str) -> Result<SecureStorage> {
+    let mut token = try!(SecureStorage::from_vec(vec![0; 32]));
+
+    let salt = format!("lpass-nativemsg:{}", extension);
+
+    try!(pbkdf2_sha256(crypto_key, salt.as_bytes(), 1, &mut token));
+
+    Ok(token)
+}
+
+/// Number of passes over the memory used by `local_key`
+#[cfg(feature = "argon2")]
+pub const LOCAL_KEY_PASSES: u32 = 3;
+
+/// Amount of memory used by `local_key`, in KiB
+#[cfg(feature = "argon2")]
+pub const LOCAL_KEY_MEMORY: u32 = 64 * 1024;
+
+/// Minimum salt length accepted by `local_key`
+#[cfg(feature = "argon2")]
+pub const LOCAL_KEY_MIN_SALT_LEN: usize = 16;
+
+/// Key used to encrypt the data stored locally (cached blob...),
+/// derived from `password` and `salt` with Argon2i using
+/// `LOCAL_KEY_PASSES` passes over `LOCAL_KEY_MEMORY` KiB of memory
+/// and a single lane.
+///
+/// This is independent of the LastPass KDF: the key is never sent to
+/// the server and can't decrypt anything coming from it. `salt`
+/// should be random and stored alongside the encrypted data.
+#[cfg(feature = "argon2")]
+pub fn local_key(password: &[u8], salt: &[u8]) -> Result<SecureStorage> {
+    if salt.len() < LOCAL_KEY_MIN_SALT_LEN {
+        let err = format!("Salt too short ({} bytes)", salt.len());
 
         return Err(Error::Unsupported(err));
     }
 
+    let argon2 =
+        match Argon2::new(LOCAL_KEY_PASSES, 1, LOCAL_KEY_MEMORY,
+                          Variant::Argon2i) {
+            Ok(a) => a,
+            Err(e) => {
+                let err = format!("Invalid Argon2 parameters: {:?}", e);
+
+                return Err(Error::Unsupported(err));
+            }
+        };
+
     let mut key = try!(SecureStorage::from_vec(vec![0; 32]));
 
-    try!(pkcs5::pbkdf2_hmac(password,
-                            username.as_bytes(),
-                            iterations as usize,
-                            MessageDigest::sha256(),
-                            &mut key));
+    argon2.hash(&mut key, password, salt, &[], &[]);
 
     Ok(key)
 }
 
+/// Return `true` if `candidate` is the master password that produced
+/// the `expected_key` crypto key (see `crypto_key`). The keys are
+/// compared in constant time.
+///
+/// This works entirely offline, the server is not contacted. It can
+/// be used to confirm the master password before unlocking a locally
+/// cached vault for instance. Returns `false` if the key can't be
+/// derived (invalid iteration count...).
+pub fn verify_password(username: &str,
+                       candidate: &[u8],
+                       iterations: u32,
+                       expected_key: &[u8]) -> bool {
+    match crypto_key(username, candidate, iterations) {
+        Ok(key) => key.ct_eq(expected_key),
+        Err(_) => false,
+    }
+}
+
 #[test]
 fn test_login_key() {
     assert!(login_key("", b"", 1).is_err());
@@ -101,5 +224,79 @@ fn test_login_key() {
         let expected = SecureStorage::from_slice(expected).unwrap();
 
         assert!(key == expected);
+
+        let crypto = crypto_key(user, pw, iter).unwrap();
+        let key = login_key_from_crypto_key(&crypto, pw).unwrap();
+
+        assert!(key == expected);
     }
 }
+
+#[test]
+fn test_max_iterations() {
+    // This would take ages if the iteration count wasn't validated
+    match crypto_key("bob", b"password", 2_000_000_000) {
+        Err(Error::BadProtocol(_)) => (),
+        _ => panic!("Absurd iteration count was accepted"),
+    }
+
+    match login_key("bob", b"password", u32::max_value()) {
+        Err(Error::BadProtocol(_)) => (),
+        _ => panic!("Absurd iteration count was accepted"),
+    }
+
+    assert!(check_iterations(DEFAULT_MAX_ITERATIONS).is_ok());
+}
+
+#[test]
+fn test_verify_password() {
+    let key = crypto_key("bob", b"password", 1000).unwrap();
+
+    assert!(verify_password("bob", b"password", 1000, &key));
+    assert!(!verify_password("bob", b"Password", 1000, &key));
+    assert!(!verify_password("alice", b"password", 1000, &key));
+    assert!(!verify_password("bob", b"password", 1001, &key));
+    assert!(!verify_password("bob", b"password", 1000, &key[..16]));
+    assert!(!verify_password("bob", b"password", 1, &key));
+}
+
+#[cfg(feature = "argon2")]
+#[test]
+fn test_local_key() {
+    assert!(local_key(b"password", b"too short").is_err());
+
+    let tests: &[(&[u8], &[u8], [u8; 32])] = &[
+        (b"password", b"somesaltsomesalt",
+         [0x7d, 0x1b, 0x11, 0x63, 0xd3, 0xc0, 0xb7, 0x91,
+          0xfe, 0xa8, 0x02, 0xae, 0x5d, 0x1c, 0xcb, 0xd3,
+          0xfe, 0x89, 0x6c, 0x54, 0xa1, 0xb0, 0x27, 0x7a,
+          0xd9, 0x6e, 0x5a, 0x1f, 0x31, 0x12, 0x93, 0xf7]),
+        (b"correct horse battery staple", b"lpass-rs-cache-salt",
+         [0x10, 0xce, 0xda, 0x44, 0x60, 0xf6, 0x58, 0x51,
+          0x19, 0x3a, 0xe6, 0xd5, 0x54, 0xfd, 0x64, 0x2f,
+          0xd6, 0xee, 0xad, 0x60, 0xba, 0x3d, 0x0f, 0x06,
+          0xc1, 0xa7, 0xcc, 0x36, 0xf7, 0xe0, 0xf3, 0x9b]),
+    ];
+
+    for &(password, salt, ref expected) in tests {
+        let key = local_key(password, salt).unwrap();
+
+        assert!(key.ct_eq(expected));
+    }
+}
+
+#[test]
+fn test_pairing_token() {
+    let key = [0x42; 32];
+
+    let a = pairing_token(&key, "chrome-extension://abcdef").unwrap();
+    let b = pairing_token(&key, "lpass@example.com").unwrap();
+
+    assert_eq!(a.len(), 32);
+    assert!(a.ct_eq(&pairing_token(&key, "chrome-extension://abcdef")
+                    .unwrap()));
+    assert!(!a.ct_eq(&b));
+    assert!(!a.ct_eq(&pairing_token(&[0x43; 32],
+                                    "chrome-extension://abcdef")
+                     .unwrap()));
+}
@@ -9,24 +9,84 @@ extern crate openssl;
 extern crate base64;
 extern crate libc;
 extern crate xml as xml_sax;
+#[cfg(feature = "argon2")]
+extern crate argon2rs;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "rustcrypto")]
+extern crate aes;
+#[cfg(feature = "rustcrypto")]
+extern crate sha2;
+#[cfg(feature = "rustcrypto")]
+extern crate pbkdf2;
 
 mod http;
 mod error;
 mod secure;
 mod xml;
+mod account;
+mod vault;
+mod crypto;
 
 pub mod kdf;
+pub mod blob;
+pub mod cipher;
+pub mod config;
+pub mod pwgen;
+pub mod platform;
+
+use openssl::rand::rand_bytes;
 
 use std::u32;
 use std::str::FromStr;
+use std::collections::HashMap;
 use std::fmt;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub use error::{Result, Error};
 pub use secure::Storage as SecureStorage;
+pub use secure::set_allow_unlocked_memory;
+pub use account::{Account, Attachment, Field, Share};
+pub use vault::Vault;
+pub use http::{HttpConfig, HttpClient, Transport};
+pub use http::{interrupt, transfer_in_progress};
 
 /// Version of lpass-rs set in Cargo.toml
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+/// Maximum time in seconds we wait for the user to approve an
+/// out-of-band login
+const OUT_OF_BAND_TIMEOUT: u64 = 120;
+
+/// Maximum age in seconds of the cached blob before `get_accounts`
+/// downloads a fresh copy
+const BLOB_CACHE_MAX_AGE: u64 = 300;
+
+/// Identifier and version of the blob cache file format. The file
+/// starts with this header followed by a random salt of
+/// `BLOB_CACHE_SALT_LEN` bytes and the blob sealed with
+/// `cipher::seal`, the header and salt are authenticated.
+const BLOB_CACHE_MAGIC: &'static [u8] = b"LPBC\x01";
+
+/// Length of the salt used to derive the blob cache key
+const BLOB_CACHE_SALT_LEN: usize = 16;
+
+/// Error causes returned by the server when the session ID or token
+/// we send is not valid anymore (expired or revoked)
+const SESSION_ERROR_CAUSES: &'static [&'static str] = &[
+    "notloggedin",
+    "sessionexpired",
+    "invalidsession",
+];
+
+/// Length of the ID generated to identify a trusted device
+const TRUSTED_ID_LEN: usize = 32;
+
+/// Default LastPass server
+pub const DEFAULT_SERVER: &'static str = "lastpass.com";
+
 /// Session state
 pub struct Session {
     /// Login of the user, used to log into the server and to derive
@@ -46,27 +106,228 @@ pub struct Session {
     /// decrypt the data. This is not the same as the key used to log
     /// into the server.
     crypto_key: Option<SecureStorage>,
+    /// RSA private key encrypted with the crypto key, used to decrypt
+    /// the keys of shared folders. Not all accounts have one.
+    private_key_enc: Option<Vec<u8>>,
+    /// Transport used for all the requests of the session, an
+    /// `HttpClient` unless set by `with_transport`
+    http: Box<Transport>,
+    /// Policy for the use of the cached blob
+    sync_mode: SyncMode,
+    /// Version of the last blob loaded (its `LPAV` chunk)
+    blob_version: Option<u64>,
 }
 
 impl Session {
     /// Create a new session for `username`. Usernames are always
     /// lowercase so `username` will be converted if necessary.
     pub fn new(username: &str) -> Session {
+        Session::with_server(username, DEFAULT_SERVER)
+    }
+
+    /// Create a new session for `username` on `server` (e.g.
+    /// "lastpass.eu"). `server` should be a bare hostname without
+    /// scheme or path.
+    pub fn with_server(username: &str, server: &str) -> Session {
         curl::init();
 
+        let http = HttpClient::new(HttpConfig::default());
+
+        Session::with_transport(username, server, Box::new(http))
+    }
+
+    /// Same as `with_server` but all the requests go through
+    /// `transport`. This is mainly useful to test the protocol
+    /// handling against canned responses.
+    pub fn with_transport(username: &str,
+                          server: &str,
+                          transport: Box<Transport>) -> Session {
         Session {
             // The username is always converted to lowercase in the
             // API.
             username: username.to_lowercase(),
-            server: "lastpass.com".to_owned(),
+            server: server.to_owned(),
             iterations: None,
             uid: None,
             session_id: None,
             session_token: None,
             crypto_key: None,
+            private_key_enc: None,
+            http: transport,
+            sync_mode: SyncMode::Auto,
+            blob_version: None,
+        }
+    }
+
+    /// Load the session saved by `save`. Returns `None` if no
+    /// session has been saved. The crypto key is not saved, it has
+    /// to be set by calling `unlock`.
+    pub fn load() -> Result<Option<Session>> {
+        let data =
+            match try!(config::read("session")) {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+
+        let mut username = None;
+        let mut server = None;
+        let mut iterations = None;
+        let mut uid = None;
+        let mut session_id = None;
+        let mut session_token = None;
+        let mut private_key_enc = None;
+
+        for line in data.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+
+            let sep =
+                match line.iter().position(|&b| b == b' ') {
+                    Some(p) => p,
+                    None => return Err(bad_session_file()),
+                };
+
+            let (key, value) = (&line[..sep], &line[sep + 1..]);
+
+            match key {
+                b"username" =>
+                    username = Some(try!(String::from_utf8(value.to_vec()))),
+                b"server" =>
+                    server = Some(try!(String::from_utf8(value.to_vec()))),
+                b"iterations" =>
+                    iterations = Some(try!(parse_u32(value))),
+                b"uid" =>
+                    uid = Some(try!(parse_u32(value))),
+                b"session_id" =>
+                    session_id = Some(try!(SecureStorage::from_slice(value))),
+                b"session_token" => {
+                    let token = try!(SecureStorage::from_slice(value));
+                    session_token = Some(token);
+                }
+                b"private_key_enc" =>
+                    private_key_enc = Some(value.to_vec()),
+                _ => warn!("Unknown session parameter {}",
+                           String::from_utf8_lossy(key)),
+            }
+        }
+
+        let (username, server) =
+            match (username, server) {
+                (Some(u), Some(s)) => (u, s),
+                _ => return Err(bad_session_file()),
+            };
+
+        let mut session = Session::new(&username);
+
+        session.server = server;
+        session.iterations = iterations;
+        session.uid = uid;
+        session.session_id = session_id;
+        session.session_token = session_token;
+        session.private_key_enc = private_key_enc;
+
+        Ok(Some(session))
+    }
+
+    /// Save the session to the configuration directory so that it
+    /// can be reused with `load`. The crypto key is *not* saved.
+    pub fn save(&self) -> Result<()> {
+        let (session_id, session_token) =
+            match (&self.session_id, &self.session_token) {
+                (&Some(ref i), &Some(ref t)) => (i, t),
+                _ => return Err(Error::NotAuthenticated),
+            };
+
+        let mut data = try!(SecureStorage::with_capacity(256));
+
+        {
+            let mut add = |key: &str, value: &[u8]| -> Result<()> {
+                for &b in key.as_bytes().iter().chain(b" ") {
+                    try!(data.push(b));
+                }
+
+                for &b in value.iter().chain(b"\n") {
+                    try!(data.push(b));
+                }
+
+                Ok(())
+            };
+
+            try!(add("username", self.username.as_bytes()));
+            try!(add("server", self.server.as_bytes()));
+
+            if let Some(i) = self.iterations {
+                try!(add("iterations", format!("{}", i).as_bytes()));
+            }
+
+            if let Some(uid) = self.uid {
+                try!(add("uid", format!("{}", uid).as_bytes()));
+            }
+
+            try!(add("session_id", session_id));
+            try!(add("session_token", session_token));
+
+            if let Some(ref k) = self.private_key_enc {
+                try!(add("private_key_enc", k));
+            }
+        }
+
+        config::write("session", &data)
+    }
+
+    /// Return the key used to decrypt the account data if it's
+    /// available.
+    pub fn crypto_key(&self) -> Option<&SecureStorage> {
+        self.crypto_key.as_ref()
+    }
+
+    /// Set the key used to decrypt the account data, for instance if
+    /// it was cached by an agent.
+    pub fn set_crypto_key(&mut self, key: SecureStorage) {
+        self.crypto_key = Some(key);
+    }
+
+    /// Decrypt the user's RSA private key with the crypto key. The
+    /// key is returned in DER (PKCS#8) format.
+    pub fn decrypt_private_key(&self) -> Result<SecureStorage> {
+        let key =
+            match self.crypto_key {
+                Some(ref k) => k,
+                None => return Err(Error::NotAuthenticated),
+            };
+
+        match self.private_key_enc {
+            Some(ref k) => cipher::decrypt_private_key(key, k),
+            None => {
+                let err = "No private key available".to_owned();
+                Err(Error::Unsupported(err))
+            }
         }
     }
 
+    /// Remove the session saved by `save`, if any, as well as the
+    /// cached blob
+    pub fn remove_saved() -> Result<()> {
+        try!(Session::invalidate_cache());
+
+        config::unlink("session")
+    }
+
+    /// Derive the crypto key from the master `password` without
+    /// logging into the server. Used to decrypt the blob with a
+    /// session restored by `load`.
+    pub fn unlock(&mut self, password: SecureStorage) -> Result<()> {
+        let iterations = try!(self.iterations());
+
+        let crypto_key =
+            try!(kdf::crypto_key(&self.username, &password, iterations));
+
+        self.crypto_key = Some(crypto_key);
+
+        Ok(())
+    }
+
     /// Return `true` if the session is authenticated on the server.
     pub fn is_authenticated(&self) -> bool {
         self.session_id.is_some() && self.session_token.is_some()
@@ -77,6 +338,32 @@ impl Session {
         &self.server
     }
 
+    /// Return the user ID assigned by the server on login
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// Return an opaque handle to the session ID
+    pub fn session_id(&self) -> Option<Secret> {
+        self.session_id.as_ref().map(|s| Secret::new(s))
+    }
+
+    /// Return an opaque handle to the session token
+    pub fn session_token(&self) -> Option<Secret> {
+        self.session_token.as_ref().map(|s| Secret::new(s))
+    }
+
+    /// Set the configuration used by the HTTP client. This replaces
+    /// the transport set by `with_transport`, if any.
+    pub fn set_http_config(&mut self, config: HttpConfig) {
+        self.http = Box::new(HttpClient::new(config));
+    }
+
+    /// Set the policy for the use of the cached blob
+    pub fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.sync_mode = mode;
+    }
+
     /// Return the username used by this session. Usernames are always
     /// lowercase.
     pub fn username(&self) -> &str {
@@ -102,13 +389,16 @@ impl Session {
     /// this session's `username`
     fn server_iterations(&self) -> Result<u32> {
         let response =
-            try!(self.post("iterations.php",
-                           &[(b"email", self.username().as_bytes())]));
+            try!(self.post_idempotent("iterations.php",
+                                      &[(b"email",
+                                         self.username().as_bytes())]));
 
         let s = try!(String::from_utf8(response));
 
         let iter = try!(u32::from_str(&s));
 
+        try!(kdf::check_iterations(iter));
+
         debug!("Iterations for {}: {}", self.username(), iter);
 
         Ok(iter)
@@ -118,117 +408,402 @@ impl Session {
     /// is true then we tell the server that two factor authentication
     /// won't be necessary for subsequents logins.
     ///
+    /// The ID identifying this device is stored in the `trusted_id`
+    /// configuration file. It's not removed on logout so that the
+    /// device remains trusted across sessions.
+    ///
     /// If two-factor auth is requested by the server `otp_prompt` is
     /// called to get the OTP. If this closure returns `None` then the
-    /// login is aborted and this function returns an error.
+    /// login is aborted and this function returns an error. If the
+    /// server rejects the OTP the closure is called again with the
+    /// server's message as second parameter to let the user retry.
+    ///
+    /// For `OtpMethod::OutOfBand` the closure is only called to let
+    /// the user know that they have to approve the login on their
+    /// device. It should return an empty OTP to start waiting for the
+    /// approval or `None` to abort the login, in which case
+    /// `Error::UserAbort` is returned. If the login isn't approved
+    /// within `OUT_OF_BAND_TIMEOUT` seconds `Error::ApprovalTimeout`
+    /// is returned.
     pub fn login<F>(&mut self,
                     password: SecureStorage,
                     trust: bool,
                     mut otp_prompt: F) -> Result<()>
-        where F: FnMut(OtpMethod) -> Option<SecureStorage> {
+        where F: FnMut(OtpMethod, Option<&str>) -> Option<SecureStorage> {
 
+        // Each secret is dropped (and therefore zeroed) as soon as
+        // it's no longer needed instead of living until the end of
+        // the function: the password once both keys are derived, the
+        // login key once hex-encoded and the hex key once the login
+        // requests are done.
         let iterations = try!(self.iterations());
 
-        let login_key =
-            try!(kdf::login_key(&self.username(), &password, iterations));
+        // Derived before talking to the server so that the password
+        // doesn't stay in memory during the (potentially very long)
+        // second factor authentication. The login key is derived from
+        // it to only run the slow KDF once.
+        let crypto_key =
+            try!(kdf::crypto_key(&self.username(), &password, iterations));
+
+        let hex_key = {
+            let login_key =
+                try!(kdf::login_key_from_crypto_key(&crypto_key, &password));
+
+            try!(hex_encode_secure(&login_key))
+        };
+
+        drop(password);
+
+        let iter_str = format!("{}", iterations);
+
+        let username = self.username().to_owned();
+
+        // The trusted ID identifies this device. It's only generated
+        // when the user asks us to trust the device but once it
+        // exists it's sent with every login so that the server can
+        // skip the second factor.
+        let (trusted_id, new_trusted_id) =
+            match try!(config::read("trusted_id")) {
+                Some(id) => (Some(id), false),
+                None if trust => {
+                    (Some(try!(pwgen::generate(TRUSTED_ID_LEN, true, false))),
+                     true)
+                }
+                None => (None, false),
+            };
+
+        let trust_label = trust_label();
+
+        let res = {
+            // Lifted from the C command line client, not sure if any
+            // of those should be made configurable.
+            let mut params: Vec<(&[u8], &[u8])> = vec![
+                (b"xml", b"2"),
+                (b"username", username.as_bytes()),
+                (b"hash", &hex_key),
+                (b"iterations", iter_str.as_bytes()),
+                (b"includeprivatekeyenc", b"1"),
+                (b"method", b"cli"),
+                (b"outofbandsupported", b"1"),
+            ];
+
+            if let Some(ref id) = trusted_id {
+                params.push((b"uuid", id));
 
-        let iter_str = format!("{}", try!(self.iterations()));
+                if trust {
+                    params.push((b"trustlabel", trust_label.as_bytes()));
+                }
+            }
+
+            let params = &params[..];
+
+            let mut res =
+                self.try_login(params);
+
+            loop {
+                let (m, rejected) =
+                    match res {
+                        Err(Error::OtpRequired(m)) => (m, None),
+                        Err(Error::OtpFailed(m, ref msg)) =>
+                            (m, Some(msg.clone())),
+                        _ => break,
+                    };
+
+                // The OTP is dropped at the end of each iteration
+                let otp =
+                    match (otp_prompt(m, rejected.as_ref().map(|r| &**r)),
+                           m) {
+                        (Some(o), _) => o,
+                        (None, OtpMethod::OutOfBand) =>
+                            return Err(Error::UserAbort),
+                        (None, _) => return res,
+                    };
+
+                res =
+                    if m == OtpMethod::OutOfBand {
+                        let timeout = Duration::from_secs(OUT_OF_BAND_TIMEOUT);
+
+                        self.out_of_band_login(params, timeout)
+                    } else {
+                        let mut params = params.to_owned();
 
-        // hex-encode the key
-        let mut hex_key =
-            try!(SecureStorage::from_vec(vec![0; login_key.len() * 2]));
+                        params.push((m.post_var(), &otp));
 
-        for (i, b) in login_key.iter().enumerate() {
-            let to_hex = b"0123456789abcdef";
+                        self.try_login(&params)
+                    };
+            }
+
+            res
+        };
+
+        drop(hex_key);
 
-            hex_key[i * 2] = to_hex[(b >> 4) as usize];
-            hex_key[i * 2 + 1] = to_hex[(b & 0xf) as usize];
+        try!(res);
+
+        if let (Some(id), true) = (trusted_id.as_ref(), new_trusted_id) {
+            try!(config::write("trusted_id", id));
         }
 
-        let _ = trust;
+        self.crypto_key = Some(crypto_key);
+
+        // The cached blob could belong to a different user
+        Session::invalidate_cache()
+    }
+
+    /// Log into the server again with the master `password` after
+    /// the server invalidated the session (`Error::SessionExpired`
+    /// returned by a data request), and save the new session. The
+    /// password is always needed since the login hash is derived from
+    /// it, but the trusted device ID saved by `login`, if any, is sent
+    /// again so the second factor is skipped when the server allows
+    /// it. See `login` for `otp_prompt`.
+    pub fn relogin<F>(&mut self,
+                      password: SecureStorage,
+                      otp_prompt: F) -> Result<()>
+        where F: FnMut(OtpMethod, Option<&str>) -> Option<SecureStorage> {
+
+        self.session_id = None;
+        self.session_token = None;
+
+        try!(self.login(password, false, otp_prompt));
+
+        self.save()
+    }
+
+    /// Change the master password from `password` to
+    /// `new_password`. Every encrypted field the server asks for
+    /// (account fields, form fields...) is decrypted with the current
+    /// key and encrypted again with the new one, `progress` is called
+    /// with the number of fields processed so far and the total. The
+    /// private key is re-encrypted and the new key is shared with the
+    /// enterprise administrators allowed to recover the account, if
+    /// any. The number of iterations is kept.
+    ///
+    /// On success the server revokes the session, the caller has to
+    /// log in again with the new password.
+    pub fn change_password<F>(&mut self,
+                              password: SecureStorage,
+                              new_password: SecureStorage,
+                              mut progress: F) -> Result<()>
+        where F: FnMut(usize, usize) {
+
+        let iterations = try!(self.iterations());
 
         let username = self.username().to_owned();
 
-        // Lifted from the C command line client, not sure if any of those
-        // should be made configurable.
+        let derive = |password: SecureStorage| -> Result<_> {
+            let crypto_key =
+                try!(kdf::crypto_key(&username, &password, iterations));
+
+            let hex_key = {
+                let login_key =
+                    try!(kdf::login_key_from_crypto_key(&crypto_key,
+                                                        &password));
+
+                try!(hex_encode_secure(&login_key))
+            };
+
+            Ok((hex_key, crypto_key))
+        };
+
+        let (hex_key, key) = try!(derive(password));
+        let (new_hex_key, new_key) = try!(derive(new_password));
+
+        // Lifted from the C command line client
         let params: &[(&[u8], &[u8])] = &[
-            (b"xml", b"2"),
+            (b"cmd", b"getacctschangepw"),
             (b"username", username.as_bytes()),
             (b"hash", &hex_key),
-            (b"iterations", iter_str.as_bytes()),
-            // XXX not implemented
-            (b"includeprivatekeyenc", b"1"),
-            (b"method", b"cli"),
-            // XXX not implemented
-            (b"outofbandsupported", b"0"),
+            (b"changepw", b"1"),
+            (b"changepw2", b"1"),
+            (b"includersaprivatekeyenc", b"1"),
+            (b"changeun", b""),
+            (b"resetrsakeys", b"0"),
+            (b"includeendmenuenc", b"1"),
         ];
 
-        let mut res =
-            self.try_login(params);
+        let response = try!(self.post("lastpass/api.php", params));
 
-        while let Err(Error::OtpRequired(m)) = res {
-            let otp =
-                match otp_prompt(m) {
-                    Some(o) => o,
-                    None => return Err(Error::OtpRequired(m)),
-                };
+        let xml = try!(parse_xml_response(&response));
 
-            let mut params = params.to_owned();
+        try!(check_session_response(&xml));
 
-            params.push((m.post_var(), &otp));
+        let change = try!(parse_password_change(&xml));
 
-            res = self.try_login(&params);
+        // One "old:new" line per field
+        let mut reencrypt = Vec::new();
+
+        for (i, &(ref old, optional)) in change.fields.iter().enumerate() {
+            match cipher::decrypt_field(&key, old.as_bytes()) {
+                Ok(plaintext) => {
+                    let new = try!(cipher::encrypt_field(&new_key,
+                                                         &plaintext));
+
+                    reencrypt.extend_from_slice(old.as_bytes());
+                    reencrypt.push(b':');
+                    reencrypt.extend_from_slice(&new);
+                    reencrypt.push(b'\n');
+                }
+                // Some of the optional fields are not encrypted
+                Err(e) if optional =>
+                    debug!("Skipping field that can't be decrypted: {}", e),
+                Err(e) => return Err(e),
+            }
+
+            progress(i + 1, change.fields.len());
         }
 
-        let crypto_key =
-            try!(kdf::crypto_key(&self.username(), &password, iterations));
+        let (private_key_enc, private_key_hash) =
+            if change.private_key_enc.is_empty() {
+                (Vec::new(), String::new())
+            } else {
+                let private_key =
+                    try!(cipher::decrypt_private_key(
+                        &key, change.private_key_enc.as_bytes()));
 
-        self.crypto_key = Some(crypto_key);
+                let enc =
+                    try!(cipher::encrypt_private_key(&new_key, &private_key));
 
-        Ok(())
+                let h = try!(crypto::sha256(&enc));
+
+                (enc, hex_encode(&h))
+            };
+
+        let key_hash =
+            hex_encode(&try!(crypto::sha256(&new_key)));
+
+        let mut su_keys = Vec::new();
+
+        for (i, su_key) in change.su_keys.iter().enumerate() {
+            let &(ref uid, ref public_key) = su_key;
+
+            let public_key = try!(cipher::hex_decode(public_key.as_bytes()));
+
+            let enc = try!(cipher::rsa_encrypt(&public_key, &new_key));
+
+            su_keys.push((format!("sukey{}", i), uid.clone()));
+            su_keys.push((format!("sukeyval{}", i), hex_encode(&enc)));
+        }
+
+        let su_key_count = change.su_keys.len().to_string();
+        let iter_str = iterations.to_string();
+        let enc_username =
+            try!(cipher::encrypt_field(&new_key, username.as_bytes()));
+
+        let res = {
+            let mut params: Vec<(&[u8], &[u8])> = vec![
+                (b"cmd", b"updatepassword"),
+                (b"pwupdate", b"1"),
+                (b"email", username.as_bytes()),
+                (b"token", change.token.as_bytes()),
+                (b"reencrypt", &reencrypt),
+                (b"newprivatekeyenc", &private_key_enc),
+                (b"newuserkeyhexhash", key_hash.as_bytes()),
+                (b"newprivatekeyenchexhash", private_key_hash.as_bytes()),
+                (b"newpasswordhash", &new_hex_key),
+                (b"key_iterations", iter_str.as_bytes()),
+                (b"encrypted_username", &enc_username),
+                (b"origusername", username.as_bytes()),
+                (b"sukeycnt", su_key_count.as_bytes()),
+            ];
+
+            for &(ref name, ref value) in &su_keys {
+                params.push((name.as_bytes(), value.as_bytes()));
+            }
+
+            self.post("lastpass/api.php", &params)
+        };
+
+        let response = try!(res);
+
+        try!(check_authed_response(&response));
+
+        if !response.windows(10).any(|w| w == b"pwchangeok") {
+            let response = String::from_utf8_lossy(&response);
+            let first_line = response.trim().lines().next().unwrap_or("");
+
+            let err = format!("Password change failed: {}", first_line);
+            return Err(Error::BadProtocol(err));
+        }
+
+        // The old session and everything encrypted with the old key
+        // are now useless
+        self.session_id = None;
+        self.session_token = None;
+        self.crypto_key = None;
+        self.private_key_enc = None;
+
+        Session::invalidate_cache()
+    }
+
+    /// Poll the server until the user approves the login on their
+    /// out-of-band device or `timeout` elapses. The expiration is
+    /// reported as `Error::ApprovalTimeout`, not as another
+    /// `OtpRequired` which would make `login` ask again forever.
+    fn out_of_band_login(&mut self,
+                         params: &[(&[u8], &[u8])],
+                         timeout: Duration) -> Result<()> {
+        let mut params = params.to_owned();
+
+        params.push((b"outofbandrequest", b"1"));
+
+        let start = Instant::now();
+
+        loop {
+            match self.try_login(&params) {
+                Err(Error::OtpRequired(OtpMethod::OutOfBand)) => {
+                    if start.elapsed() >= timeout {
+                        return Err(Error::ApprovalTimeout);
+                    }
+
+                    thread::sleep(Duration::from_secs(1));
+                }
+                res => return res,
+            }
+        }
     }
 
+    /// Send a login request. If the server tells us that the account
+    /// lives on a different server (another datacenter) we switch to
+    /// it and try again, only once in order not to loop forever.
     fn try_login(&mut self, params: &[(&[u8], &[u8])]) -> Result<()> {
+        let server =
+            match try!(self.login_request(params)) {
+                Some(s) => s,
+                None => return Ok(()),
+            };
+
+        info!("Redirected from {} to {}", self.server, server);
+
+        self.server = server;
+
+        match try!(self.login_request(params)) {
+            Some(s) => {
+                let err = format!("Login redirected again to {}", s);
+                Err(Error::BadProtocol(err))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Perform a single login request. Returns the server we're
+    /// redirected to, if any.
+    fn login_request(&mut self,
+                     params: &[(&[u8], &[u8])]) -> Result<Option<String>> {
         let response =
             try!(self.post("login.php", params));
 
         let xml =
-            try!(xml::Dom::parse(&response as &[u8]));
+            try!(parse_xml_response(&response));
 
         let bad_xml = Error::BadProtocol("Invalid XML received".to_owned());
 
-        if let Some(ok) = xml.element(&["response", "ok"]) {
-            self.finalize_login(ok)
+        if let Some(server) = try!(login_redirect(&xml, &self.server)) {
+            Ok(Some(server))
+        } else if let Some(ok) = xml.element(&["response", "ok"]) {
+            self.finalize_login(ok).map(|_| None)
         } else if let Some(e) = xml.element(&["response", "error"]) {
-            let cause: &str =
-                match e.attribute("cause") {
-                    Some(e) => &e.value,
-                    None => return Err(bad_xml),
-                };
-
-            let err =
-                match cause {
-                    "unknownpassword" =>
-                        Error::InvalidPassword,
-                    "unkownemail" =>
-                        Error::InvalidUser,
-                    "otprequired" | "otpfailed" =>
-                        Error::OtpRequired(OtpMethod::YubiKey),
-                    "googleauthrequired" | "googleauthfailed" =>
-                        Error::OtpRequired(OtpMethod::GoogleAuthenticator),
-                    "sesameotprequired" | "sesameotpfailed" =>
-                        Error::OtpRequired(OtpMethod::Sesame),
-                    "outofbandrequired" | "multifactorresponsefailed" =>
-                        Error::Unsupported(
-                            format!("Out-of-band auth requested: {}", cause)),
-                    "gridrestricted" =>
-                        Error::Unsupported(
-                            format!("Grid-based auth requested: {}", cause)),
-                    _ =>
-                        Error::BadProtocol(format!("Unknown error: {}", cause)),
-                };
-
-            Err(err)
+            Err(login_error(e))
         } else {
             Err(bad_xml)
         }
@@ -248,52 +823,1955 @@ impl Session {
         let uid = try!(get_attrib("uid"));
         let session_id = try!(get_attrib("sessionid")).into_bytes();
         let token = try!(get_attrib("token")).into_bytes();
-        // XXX We don't need that for the moment, it's the RSA private
-        // key used to handle shares.
-        let _private_key_enc = try!(get_attrib("privatekeyenc")).into_bytes();
+        let private_key_enc = try!(get_attrib("privatekeyenc")).into_bytes();
+
+        // An empty session would be considered authenticated while
+        // every request would fail
+        if token.is_empty() {
+            return Err(Error::BadProtocol("Empty session token".to_owned()));
+        }
+
+        if !is_valid_session_id(&session_id) {
+            return Err(Error::BadProtocol("Invalid session ID".to_owned()));
+        }
 
         self.uid = Some(try!(u32::from_str(&uid)));
         self.session_id = Some(try!(SecureStorage::from_vec(session_id)));
         self.session_token = Some(try!(SecureStorage::from_vec(token)));
+        self.private_key_enc =
+            if private_key_enc.is_empty() {
+                None
+            } else {
+                Some(private_key_enc)
+            };
 
         Ok(())
     }
 
-    fn post(&self,
-            page: &str,
-            params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
-        http::post(self.server(), page, params)
+    /// Revoke the session on the server. The session's credentials
+    /// and crypto key are cleared even if the request fails.
+    pub fn logout(&mut self) -> Result<()> {
+        let params: &[(&[u8], &[u8])] = &[
+            (b"noexit", b"1"),
+        ];
+
+        let res = self.authed_post("logout.php", params).map(|_| ());
+
+        self.uid = None;
+        self.session_id = None;
+        self.session_token = None;
+        self.crypto_key = None;
+        self.private_key_enc = None;
+
+        res
     }
-}
 
-/// Supported OTP methods
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub enum OtpMethod {
-    /// Yubico hardware tokens
-    YubiKey,
-    /// Google Authenticator
-    GoogleAuthenticator,
-    /// LastPass USB-key based OTP
-    Sesame,
-}
+    /// Download the account blob from the server. The blob is
+    /// returned base64-decoded but its fields are still encrypted
+    /// with the crypto key.
+    pub fn get_blob(&mut self) -> Result<SecureStorage> {
+        if !self.is_authenticated() {
+            return Err(Error::NotAuthenticated);
+        }
 
-impl OtpMethod {
-    /// Return the name of the POST variable used to send the OTP code
-    /// to the server.
-    fn post_var(self) -> &'static [u8] {
-        match self {
-            OtpMethod::Sesame => b"sesameotp",
-            _ => b"otp",
+        let token =
+            match self.session_token {
+                Some(ref t) => t,
+                None => return Err(Error::NotAuthenticated),
+            };
+
+        // Lifted from the C command line client
+        let params: &[(&[u8], &[u8])] = &[
+            (b"mobile", b"1"),
+            (b"b64", b"1"),
+            (b"hash", b"0.0"),
+            (b"requestsrc", b"cli"),
+            (b"token", token),
+        ];
+
+        let session_id = self.session_id.as_ref().map(|s| &**s);
+
+        // The blob can be big, stream it directly into locked memory
+        let response =
+            try!(self.http.post_idempotent_secure(self.server(),
+                                                  "getaccts.php",
+                                                  session_id,
+                                                  params));
+
+        if response.is_empty() {
+            return Err(Error::BadProtocol("Empty blob received".to_owned()));
         }
+
+        // The blob is base64-encoded, XML means that the request
+        // failed
+        if response.starts_with(b"<") {
+            let xml = try!(parse_xml_response(&response));
+
+            try!(check_session_response(&xml));
+
+            let cause =
+                xml.element(&["response", "error"])
+                .or(xml.element(&["xmlresponse", "error"]))
+                .and_then(|e| e.attribute("message").or(e.attribute("cause")))
+                .map_or("unknown error", |a| &*a.value);
+
+            let err = format!("Blob download failed: {}", cause);
+
+            return Err(Error::BadProtocol(err));
+        }
+
+        base64_decode_secure(&response)
     }
-}
 
-impl fmt::Display for OtpMethod {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &OtpMethod::GoogleAuthenticator =>
-                write!(f, "Google Authenticator"),
-            _ => write!(f, "{:?}", self),
+    /// Download the blob and store it in the cache. Returns the new
+    /// blob.
+    pub fn sync(&mut self) -> Result<SecureStorage> {
+        let blob = try!(self.get_blob());
+
+        try!(self.write_cached_blob(&blob));
+
+        self.set_blob_version(&blob);
+
+        Ok(blob)
+    }
+
+    /// Return the version of the last blob loaded by `load_blob` or
+    /// `sync`, if known
+    pub fn blob_version(&self) -> Option<u64> {
+        self.blob_version
+    }
+
+    fn set_blob_version(&mut self, blob: &[u8]) {
+        self.blob_version =
+            match blob::version(blob) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Couldn't parse the blob version: {}", e);
+                    None
+                }
+            };
+    }
+
+    /// Encrypt `blob` with a key derived from the crypto key and
+    /// store it in the cache. If the crypto key is not available the
+    /// cache is removed instead.
+    fn write_cached_blob(&self, blob: &[u8]) -> Result<()> {
+        let crypto_key =
+            match self.crypto_key {
+                Some(ref k) => k,
+                None => {
+                    debug!("Decryption key not available, not caching \
+                            the blob");
+                    return Session::invalidate_cache();
+                }
+            };
+
+        let cache = try!(seal_blob_cache(crypto_key, blob));
+
+        config::write("blob", &cache)
+    }
+
+    /// Return the cached blob, if any. The cache is ignored (and
+    /// `None` returned) if it can't be decrypted: crypto key not
+    /// available, file corrupted or tampered with, old format...
+    pub fn cached_blob(&self) -> Result<Option<SecureStorage>> {
+        let cache =
+            match try!(config::read("blob")) {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+
+        let crypto_key =
+            match self.crypto_key {
+                Some(ref k) => k,
+                None => return Ok(None),
+            };
+
+        match open_blob_cache(crypto_key, &cache) {
+            Ok(blob) => Ok(Some(blob)),
+            Err(e) => {
+                warn!("Ignoring the blob cache: {}", e);
+                Ok(None)
+            }
         }
     }
+
+    /// Return the cached blob or download a fresh one with `sync`
+    /// depending on the session's `SyncMode`.
+    pub fn load_blob(&mut self) -> Result<SecureStorage> {
+        let max_age = Duration::from_secs(BLOB_CACHE_MAX_AGE);
+
+        let use_cache =
+            match self.sync_mode {
+                SyncMode::Now => false,
+                SyncMode::No => true,
+                SyncMode::Auto =>
+                    match try!(config::age("blob")) {
+                        Some(age) => age < max_age,
+                        None => false,
+                    },
+            };
+
+        if use_cache {
+            match try!(self.cached_blob()) {
+                Some(blob) => {
+                    debug!("Using cached blob");
+                    self.set_blob_version(&blob);
+                    return Ok(blob);
+                }
+                None if self.sync_mode == SyncMode::No => {
+                    let err = "No cached blob available".to_owned();
+                    return Err(Error::Unsupported(err));
+                }
+                None => (),
+            }
+        }
+
+        self.sync()
+    }
+
+    /// Remove the cached blob. This must be done after any change to
+    /// the vault.
+    pub fn invalidate_cache() -> Result<()> {
+        config::unlink("blob")
+    }
+
+    /// Decrypt all the accounts in the blob returned by `load_blob`
+    pub fn get_accounts(&mut self) -> Result<Vec<Account>> {
+        let accounts = try!(self.decode_accounts());
+
+        debug!("Decoded {} accounts", accounts.len());
+
+        Ok(accounts)
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    fn decode_accounts(&mut self) -> Result<Vec<Account>> {
+        try!(self.accounts_iter()).collect()
+    }
+
+    /// Same as above but the accounts are decrypted in parallel. The
+    /// shares are decoded first since the accounts following them
+    /// need their key.
+    #[cfg(feature = "rayon")]
+    fn decode_accounts(&mut self) -> Result<Vec<Account>> {
+        use rayon::prelude::*;
+
+        let blob = try!(self.load_blob());
+
+        let key =
+            match self.crypto_key {
+                Some(ref k) => k,
+                None => {
+                    let err = "Decryption key is not available".to_owned();
+                    return Err(Error::Unsupported(err));
+                }
+            };
+
+        let attachments = attachment_chunks(&blob);
+
+        let mut shares: Vec<Share> = Vec::new();
+        // ACCT chunk, index of its share and ACFL chunks
+        let mut chunks: Vec<(&[u8], Option<usize>, Vec<&[u8]>)> = Vec::new();
+        // The ACFL chunks belong to the account right before them
+        let mut in_account = false;
+
+        for chunk in blob::BlobReader::new(&blob) {
+            let chunk = try!(chunk);
+
+            match &chunk.id {
+                b"SHAR" => {
+                    let s = try!(Share::from_chunk(chunk.data, key, || {
+                        self.decrypt_private_key()
+                    }));
+
+                    debug!("Decoding share {} [id: {}]", s.name, s.id);
+
+                    shares.push(s);
+                }
+                b"ACCT" => {
+                    let share = shares.len().checked_sub(1);
+
+                    chunks.push((chunk.data, share, Vec::new()));
+                }
+                b"ACFL" => {
+                    let last =
+                        if in_account { chunks.last_mut() } else { None };
+
+                    match last {
+                        Some(c) => c.2.push(chunk.data),
+                        None => warn!("Ignoring orphan ACFL chunk"),
+                    }
+                }
+                _ => (),
+            }
+
+            in_account = &chunk.id == b"ACCT" || &chunk.id == b"ACFL";
+        }
+
+        // `collect` preserves the order of the chunks
+        let accounts: Vec<Result<Account>> =
+            chunks.par_iter()
+            .map(|&(data, share, ref fields)| {
+                decode_account(data,
+                               fields,
+                               share.map(|i| &shares[i]),
+                               key,
+                               &attachments)
+            })
+            .collect();
+
+        accounts.into_iter().collect()
+    }
+
+    /// Return an iterator over the accounts of the vault. The blob is
+    /// loaded like in `get_accounts` but each account is only
+    /// decrypted when the iterator reaches it, a search can stop
+    /// early without decrypting the whole vault.
+    pub fn accounts_iter(&mut self) -> Result<Accounts> {
+        let blob = try!(self.load_blob());
+
+        Accounts::new(self, blob)
+    }
+
+    /// Create or update `account` in the vault. If the account's ID
+    /// is "0" a new account is created. Returns the ID assigned by
+    /// the server if it was present in the response.
+    pub fn update_account(&mut self,
+                          account: &Account) -> Result<Option<String>> {
+        // Shared accounts are encrypted with the share's key and use
+        // a different endpoint
+        if account.share_id.is_some() {
+            let err = "Modifying shared accounts is not supported".to_owned();
+            return Err(Error::Unsupported(err));
+        }
+
+        let key =
+            match self.crypto_key {
+                Some(ref k) => k,
+                None => {
+                    let err = "Encryption key is not available".to_owned();
+                    return Err(Error::Unsupported(err));
+                }
+            };
+
+        let name = try!(cipher::encrypt_field(key, account.name.as_bytes()));
+        let group =
+            try!(cipher::encrypt_field(key, account.group.as_bytes()));
+        let username =
+            try!(cipher::encrypt_field(key, account.username.as_bytes()));
+        let password = try!(cipher::encrypt_field(key, &account.password));
+        let note = try!(cipher::encrypt_field(key, &account.note));
+        let url = hex_encode(account.url.as_bytes());
+        let fields = try!(field_params(&account.fields, key));
+        let field_count = account.fields.len().to_string();
+
+        let res = {
+            // Lifted from the C command line client, no conflict detection
+            let mut params: Vec<(&[u8], &[u8])> = vec![
+                (b"extjs", b"1"),
+                (b"name", &name),
+                (b"grouping", &group),
+                (b"pwprotect", b"off"),
+                (b"aid", account.id.as_bytes()),
+                (b"url", url.as_bytes()),
+                (b"username", &username),
+                (b"password", &password),
+                (b"extra", &note),
+            ];
+
+            if !fields.is_empty() {
+                params.push((b"fieldcnt", field_count.as_bytes()));
+
+                for &(ref name, ref value) in &fields {
+                    params.push((name.as_bytes(), value));
+                }
+            }
+
+            self.post_website(&params)
+        };
+
+        try!(Session::invalidate_cache());
+
+        res
+    }
+
+    /// Delete the account with ID `id` from the vault
+    pub fn delete_account(&mut self, id: &str) -> Result<()> {
+        let params: &[(&[u8], &[u8])] = &[
+            (b"extjs", b"1"),
+            (b"delete", b"1"),
+            (b"aid", id.as_bytes()),
+        ];
+
+        let res = self.post_website(params).map(|_| ());
+
+        try!(Session::invalidate_cache());
+
+        res
+    }
+
+    /// POST a request to `show_website.php` which is used for all
+    /// account modifications. Returns the account ID from the
+    /// response if there's one.
+    fn post_website(&self,
+                    params: &[(&[u8], &[u8])]) -> Result<Option<String>> {
+        let response = try!(self.authed_post("show_website.php", params));
+
+        let xml = try!(parse_xml_response(&response));
+
+        website_result(&xml)
+    }
+
+    /// Download `attachment` of `account`, decrypt it and write the
+    /// file to `out`. Returns the size of the file.
+    pub fn get_attachment<W>(&self,
+                             account: &Account,
+                             attachment: &Attachment,
+                             out: &mut W) -> Result<u64>
+        where W: Write {
+        let key =
+            match account.attach_key {
+                Some(ref k) => k,
+                None => {
+                    let err = format!("No attachment key for {}",
+                                      account.fullname);
+                    return Err(Error::BadProtocol(err));
+                }
+            };
+
+        let data = {
+            let mut params: Vec<(&[u8], &[u8])> = vec![
+                (b"getattach", attachment.storage_key.as_bytes()),
+            ];
+
+            if let Some(ref id) = account.share_id {
+                params.push((b"sharedfolderid", id.as_bytes()));
+            }
+
+            try!(self.authed_post_idempotent("getattach.php", &params))
+        };
+
+        cipher::decrypt_attachment(key, &data, out)
+    }
+
+    /// Add the new `account` to the vault and return the ID assigned
+    /// by the server. If the server doesn't tell us the ID we
+    /// resynchronize the vault to find it.
+    pub fn add_account(&mut self, account: &Account) -> Result<String> {
+        if account.id != "0" {
+            let err = format!("Account {} already exists", account.id);
+            return Err(Error::Unsupported(err));
+        }
+
+        if let Some(id) = try!(self.update_account(account)) {
+            return Ok(id);
+        }
+
+        // Assume that the newest account with this name is the one we
+        // just added
+        try!(self.get_accounts())
+            .into_iter()
+            .filter(|a| a.fullname == account.fullname)
+            .filter_map(|a| u64::from_str(&a.id).ok())
+            .max()
+            .map(|id| id.to_string())
+            .ok_or(Error::BadProtocol("Added account not found".to_owned()))
+    }
+
+    /// Return the decrypted accounts organized in a `Vault`
+    pub fn get_vault(&mut self) -> Result<Vault> {
+        let accounts = try!(self.get_accounts());
+
+        Ok(Vault::new(accounts))
+    }
+
+    /// Same as `post` but the request is retried on transient
+    /// errors. Only use for requests without side effects.
+    fn post_idempotent(&self,
+                       page: &str,
+                       params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+        let session_id = self.session_id.as_ref().map(|s| &**s);
+
+        self.http.post_idempotent(self.server(), page, session_id, params)
+    }
+
+    /// POST an authenticated request to `page`. This is the choke
+    /// point for the requests made on behalf of a logged in user. The
+    /// only exceptions are the blob download, which streams the
+    /// response into locked memory, and `change_password`, which uses
+    /// the token returned by the server for the change instead of the
+    /// session's. The session ID cookie, the CSRF `token` and
+    /// `method=cli` are added to `params`. If the response is an XML
+    /// error saying that the session isn't valid anymore
+    /// `Error::SessionExpired` is returned, other errors are left to
+    /// the caller.
+    fn authed_post(&self,
+                   page: &str,
+                   params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+        self.authed_request(page, params, false)
+    }
+
+    /// Same as `authed_post` but the request is retried on transient
+    /// errors. Only use for requests without side effects.
+    fn authed_post_idempotent(&self,
+                              page: &str,
+                              params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+        self.authed_request(page, params, true)
+    }
+
+    fn authed_request(&self,
+                      page: &str,
+                      params: &[(&[u8], &[u8])],
+                      idempotent: bool) -> Result<Vec<u8>> {
+        let token =
+            match (&self.session_id, &self.session_token) {
+                (&Some(_), &Some(ref t)) => t,
+                _ => return Err(Error::NotAuthenticated),
+            };
+
+        let mut params = params.to_vec();
+
+        params.push((b"token", token));
+        params.push((b"method", b"cli"));
+
+        let response =
+            if idempotent {
+                try!(self.post_idempotent(page, &params))
+            } else {
+                try!(self.post(page, &params))
+            };
+
+        try!(check_authed_response(&response));
+
+        Ok(response)
+    }
+
+    fn post(&self,
+            page: &str,
+            params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+        let session_id = self.session_id.as_ref().map(|s| &**s);
+
+        self.http.post(self.server(), page, session_id, params)
+    }
+}
+
+/// The secrets are never displayed, only their length
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("username", &self.username)
+            .field("server", &self.server)
+            .field("iterations", &self.iterations)
+            .field("uid", &self.uid)
+            .field("session_id", &self.session_id)
+            .field("session_token", &self.session_token)
+            .field("crypto_key", &self.crypto_key)
+            .field("private_key_enc",
+                   &self.private_key_enc.as_ref().map(|_| ".."))
+            .field("http", &self.http)
+            .field("sync_mode", &self.sync_mode)
+            .finish()
+    }
+}
+
+/// Encrypt `blob` for the cache, see `BLOB_CACHE_MAGIC` for the
+/// format
+fn seal_blob_cache(crypto_key: &[u8], blob: &[u8]) -> Result<Vec<u8>> {
+    let mut salt = [0; BLOB_CACHE_SALT_LEN];
+
+    try!(rand_bytes(&mut salt));
+
+    let key = try!(kdf::cache_key(crypto_key, &salt));
+
+    let mut cache = BLOB_CACHE_MAGIC.to_vec();
+
+    cache.extend_from_slice(&salt);
+
+    let sealed = try!(cipher::seal(&key, &cache, blob));
+
+    cache.extend_from_slice(&sealed);
+
+    Ok(cache)
+}
+
+/// Decrypt the blob cache written by `seal_blob_cache`
+fn open_blob_cache(crypto_key: &[u8], cache: &[u8]) -> Result<SecureStorage> {
+    let header_len = BLOB_CACHE_MAGIC.len() + BLOB_CACHE_SALT_LEN;
+
+    if cache.len() < header_len || !cache.starts_with(BLOB_CACHE_MAGIC) {
+        return Err(Error::BadProtocol("Unknown blob cache format".to_owned()));
+    }
+
+    let (header, sealed) = cache.split_at(header_len);
+
+    let salt = &header[BLOB_CACHE_MAGIC.len()..];
+
+    let key = try!(kdf::cache_key(crypto_key, salt));
+
+    cipher::open(&key, header, sealed)
+}
+
+/// Decode the base64 `data` into a `SecureStorage`. The data is
+/// decoded in small chunks so that we don't end up with a copy of
+/// the whole plaintext in unlocked memory.
+fn base64_decode_secure(data: &[u8]) -> Result<SecureStorage> {
+    // Must be a multiple of 4 so that the chunks can be decoded
+    // independently
+    const CHUNK_LEN: usize = 4096;
+
+    let mut decoded = try!(SecureStorage::with_capacity(data.len() / 4 * 3));
+
+    for chunk in data.chunks(CHUNK_LEN) {
+        let mut plain =
+            match base64::decode(chunk) {
+                Ok(p) => p,
+                Err(_) => {
+                    let err = "Blob isn't valid base64".to_owned();
+                    return Err(Error::BadProtocol(err));
+                }
+            };
+
+        let res = decoded.extend_from_slice(&plain);
+
+        for b in plain.iter_mut() {
+            *b = 0;
+        }
+
+        try!(res);
+    }
+
+    Ok(decoded)
+}
+
+/// Hex-encode `data` into a `SecureStorage`
+fn hex_encode_secure(data: &[u8]) -> Result<SecureStorage> {
+    let to_hex = b"0123456789abcdef";
+
+    let mut hex = try!(SecureStorage::from_vec(vec![0; data.len() * 2]));
+
+    for (i, &b) in data.iter().enumerate() {
+        hex[i * 2] = to_hex[(b >> 4) as usize];
+        hex[i * 2 + 1] = to_hex[(b & 0xf) as usize];
+    }
+
+    Ok(hex)
+}
+
+/// Return the label identifying this device in the list of trusted
+/// devices
+fn trust_label() -> String {
+    match platform::hostname() {
+        Some(h) => format!("{} (lpass-rs)", h),
+        None => "lpass-rs".to_owned(),
+    }
+}
+
+/// Parse a server response expected to be XML. The server sometimes
+/// returns an HTML error or maintenance page with a 200 status, in
+/// which case we return an error containing the first line of the
+/// page instead of an obscure XML parsing error.
+fn parse_xml_response(response: &[u8]) -> Result<xml::Dom> {
+    let text = String::from_utf8_lossy(response);
+    let text = text.trim_left_matches('\u{feff}').trim_left();
+
+    let start: String = text.chars().take(14).collect();
+    let start = start.to_lowercase();
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        let first_line = text.lines().next().unwrap_or("");
+        let first_line: String = first_line.trim().chars().take(80).collect();
+
+        return Err(Error::BadProtocol(
+            format!("server returned an HTML page: {}", first_line)));
+    }
+
+    xml::Dom::parse(response)
+}
+
+/// Check the XML response to a data request for an error meaning
+/// that our session is not valid anymore, in which case
+/// `Error::SessionExpired` is returned. The other errors are left to
+/// the caller.
+fn check_session_response(xml: &xml::Dom) -> Result<()> {
+    let error =
+        xml.element(&["response", "error"])
+        .or(xml.element(&["xmlresponse", "error"]));
+
+    let cause =
+        match error.and_then(|e| e.attribute("cause")) {
+            Some(c) => &*c.value,
+            None => return Ok(()),
+        };
+
+    if SESSION_ERROR_CAUSES.contains(&cause) {
+        Err(Error::SessionExpired)
+    } else {
+        Ok(())
+    }
+}
+
+/// Return `Error::SessionExpired` if `response`, returned by an
+/// authenticated request, is an XML error saying that the session
+/// isn't valid anymore. Not all the pages return XML
+/// (attachments...), the other responses are accepted.
+fn check_authed_response(response: &[u8]) -> Result<()> {
+    if response.starts_with(b"<") {
+        if let Ok(xml) = xml::Dom::parse(response) {
+            try!(check_session_response(&xml));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse the response to a `show_website.php` request, see
+/// `Session::post_website`
+fn website_result(xml: &xml::Dom) -> Result<Option<String>> {
+    if let Some(result) = xml.element(&["xmlresponse", "result"]) {
+        return Ok(result.attribute("aid").map(|a| a.value.clone()));
+    }
+
+    let e =
+        match xml.element(&["xmlresponse", "error"]) {
+            Some(e) => e,
+            None => {
+                return Err(Error::BadProtocol("Invalid XML received"
+                                              .to_owned()))
+            }
+        };
+
+    let msg =
+        match e.attribute("message").or(e.attribute("cause")) {
+            Some(a) => &*a.value,
+            // Some errors only have a text description
+            None if !e.text().is_empty() => e.text(),
+            None => "unknown error",
+        };
+
+    Err(Error::BadProtocol(format!("Update failed: {}", msg)))
+}
+
+/// Data returned by the server to start a password change
+struct PasswordChange {
+    /// Token identifying the change, sent back with the re-encrypted
+    /// data
+    token: String,
+    /// Current encrypted private key, can be empty
+    private_key_enc: String,
+    /// Encrypted fields to re-encrypt and whether they can be skipped
+    /// if they can't be decrypted
+    fields: Vec<(String, bool)>,
+    /// User ID and hex-encoded public key of the administrators
+    /// allowed to recover the account
+    su_keys: Vec<(String, String)>,
+}
+
+/// Parse the response to a `getacctschangepw` request
+fn parse_password_change(xml: &xml::Dom) -> Result<PasswordChange> {
+    let bad_xml = || Error::BadProtocol("Invalid XML received".to_owned());
+
+    let root =
+        match xml.element(&["lastpass"]) {
+            Some(r) => r,
+            None => return Err(bad_xml()),
+        };
+
+    // The server says so when the current password is wrong
+    if let Some(rc) = root.attribute("rc") {
+        if rc.value != "OK" {
+            return Err(Error::InvalidPassword);
+        }
+    }
+
+    let data =
+        match root.child("data") {
+            Some(d) => d,
+            None => return Err(bad_xml()),
+        };
+
+    let attribute = |e: &xml::Element, name| {
+        e.attribute(name).map(|a| a.value.clone())
+    };
+
+    let token =
+        match attribute(data, "token") {
+            Some(t) => t,
+            None => return Err(bad_xml()),
+        };
+
+    let mut fields = Vec::new();
+
+    for accounts in data.children_named("accounts") {
+        for account in accounts.children_named("account") {
+            for name in &["u", "p"] {
+                if let Some(v) = attribute(account, name) {
+                    fields.push((v, false));
+                }
+            }
+        }
+    }
+
+    for f in data.children_named("fields") {
+        for field in f.children_named("field") {
+            if let Some(v) = attribute(field, "value") {
+                fields.push((v, true));
+            }
+        }
+    }
+
+    let mut su_keys = Vec::new();
+
+    for keys in data.children_named("su_keys") {
+        for key in keys.children_named("su_key") {
+            match (attribute(key, "uid"), attribute(key, "sharing_key")) {
+                (Some(uid), Some(k)) => su_keys.push((uid, k)),
+                _ => return Err(bad_xml()),
+            }
+        }
+    }
+
+    // Empty fields don't need to be re-encrypted
+    fields.retain(|&(ref f, _)| !f.is_empty());
+
+    Ok(PasswordChange {
+        token: token,
+        private_key_enc: attribute(data, "privatekeyenc").unwrap_or_default(),
+        fields: fields,
+        su_keys: su_keys,
+    })
+}
+
+/// Return true if `id` looks like a session ID: a non-empty string of
+/// the characters PHP uses for them. It ends up in the session
+/// cookie so anything else could corrupt the request headers.
+fn is_valid_session_id(id: &[u8]) -> bool {
+    !id.is_empty() &&
+        id.iter().all(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b',')
+}
+
+/// Look for a redirection in a login response: either a
+/// `<redirect url="..."/>` node or an error with a `server` attribute
+/// different from `current`. Returns the hostname of the new server.
+fn login_redirect(xml: &xml::Dom, current: &str) -> Result<Option<String>> {
+    let redirect = xml.element(&["response", "redirect"]);
+
+    let target =
+        if let Some(r) = redirect {
+            match r.attribute("url").or(r.attribute("server")) {
+                Some(t) => &*t.value,
+                None => {
+                    let err = "Missing redirect URL".to_owned();
+                    return Err(Error::BadProtocol(err));
+                }
+            }
+        } else {
+            let server =
+                xml.element(&["response", "error"])
+                .and_then(|e| e.attribute("server"));
+
+            match server {
+                Some(s) => &*s.value,
+                None => return Ok(None),
+            }
+        };
+
+    // Keep the hostname (and port) of "https://host:port/path"
+    let host =
+        match target.find("://") {
+            Some(p) => &target[p + 3..],
+            None => target,
+        };
+
+    let host =
+        match host.find('/') {
+            Some(p) => &host[..p],
+            None => host,
+        };
+
+    let valid =
+        !host.is_empty() &&
+        host.chars().all(|c| c.is_ascii_alphanumeric() || ".-:".contains(c));
+
+    if !valid {
+        let err = format!("Invalid redirect target '{}'", target);
+        return Err(Error::BadProtocol(err));
+    }
+
+    let host = host.to_lowercase();
+
+    if redirect.is_none() && host == current {
+        // Error from the server we're already talking to
+        return Ok(None);
+    }
+
+    Ok(Some(host))
+}
+
+/// Convert the `<error>` node of a login response into an `Error`.
+/// The server's `message`, if any, is included in the errors that
+/// carry a description.
+fn login_error(e: &xml::Element) -> Error {
+    let cause: &str =
+        match e.attribute("cause") {
+            Some(c) => &c.value,
+            None => {
+                let err = "Missing login error cause".to_owned();
+                return Error::BadProtocol(err);
+            }
+        };
+
+    let message = e.attribute("message").map(|m| &*m.value);
+
+    let describe = |what: &str| {
+        match message {
+            Some(m) => format!("{}: {} ({})", what, cause, m),
+            None => format!("{}: {}", what, cause),
+        }
+    };
+
+    let otp_required = |method| {
+        if let Some(m) = message {
+            info!("{} required: {}", method, m);
+        }
+
+        Error::OtpRequired(method)
+    };
+
+    let otp_failed = |method| {
+        Error::OtpFailed(method, message.unwrap_or(cause).to_owned())
+    };
+
+    match cause {
+        "unknownpassword" =>
+            Error::InvalidPassword,
+        "unkownemail" =>
+            Error::InvalidUser,
+        "otprequired" =>
+            otp_required(OtpMethod::YubiKey),
+        "otpfailed" =>
+            otp_failed(OtpMethod::YubiKey),
+        "googleauthrequired" =>
+            otp_required(OtpMethod::GoogleAuthenticator),
+        "googleauthfailed" =>
+            otp_failed(OtpMethod::GoogleAuthenticator),
+        "sesameotprequired" =>
+            otp_required(OtpMethod::Sesame),
+        "sesameotpfailed" =>
+            otp_failed(OtpMethod::Sesame),
+        "outofbandrequired" =>
+            otp_required(OtpMethod::OutOfBand),
+        // The user rejected the login on their device
+        "multifactorresponsefailed" =>
+            Error::UserAbort,
+        "gridrestricted" =>
+            Error::Unsupported(describe("Grid-based auth requested")),
+        "restrictedmobile" | "accountrestricted" | "restrictedip" =>
+            Error::AccountRestricted(message.unwrap_or(cause).to_owned()),
+        "verifydevice" =>
+            Error::DeviceVerificationRequired,
+        _ =>
+            Error::BadProtocol(describe("Unknown error")),
+    }
+}
+
+/// Build the `update_account` parameters for the custom form
+/// `fields`. The values of the text-like fields are encrypted with
+/// `key`.
+fn field_params(fields: &[Field],
+                key: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut params = Vec::new();
+
+    for (i, f) in fields.iter().enumerate() {
+        let value =
+            if f.is_encrypted() {
+                try!(cipher::encrypt_field(key, &f.value))
+            } else {
+                f.value.to_vec()
+            };
+
+        let checked = if f.checked { b"1" } else { b"0" };
+
+        params.push((format!("fieldname{}", i), f.name.as_bytes().to_vec()));
+        params.push((format!("fieldtype{}", i),
+                     f.field_type.as_bytes().to_vec()));
+        params.push((format!("fieldvalue{}", i), value));
+        params.push((format!("fieldchecked{}", i), checked.to_vec()));
+    }
+
+    Ok(params)
+}
+
+fn parse_u32(s: &[u8]) -> Result<u32> {
+    let s = try!(String::from_utf8(s.to_vec()));
+
+    Ok(try!(u32::from_str(&s)))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    let to_hex = b"0123456789abcdef";
+
+    let mut hex = String::with_capacity(data.len() * 2);
+
+    for &b in data {
+        hex.push(to_hex[(b >> 4) as usize] as char);
+        hex.push(to_hex[(b & 0xf) as usize] as char);
+    }
+
+    hex
+}
+
+fn bad_session_file() -> Error {
+    Error::BadProtocol("Invalid session file".to_owned())
+}
+
+/// Opaque handle to a secret (key, token, password...). The secret
+/// itself can't be accessed, it's redacted when formatted. Two
+/// handles to random secrets (session IDs, tokens) can be told apart
+/// using their `fingerprint`.
+///
+/// `SecureStorage` can't be displayed and its `Debug` output only
+/// contains the length. Any other secret that has to be mentioned in
+/// a log line or in a `Debug` implementation must be wrapped in a
+/// `Secret` rather than formatted directly.
+pub struct Secret<'a> {
+    secret: &'a [u8],
+    /// `false` for the secrets chosen by a human, see
+    /// `Secret::password`
+    fingerprinted: bool,
+}
+
+impl<'a> Secret<'a> {
+    /// Wrap the random, high entropy `secret` (session ID, token...)
+    pub fn new(secret: &'a [u8]) -> Secret<'a> {
+        Secret {
+            secret: secret,
+            fingerprinted: true,
+        }
+    }
+
+    /// Wrap the password `secret`. Its fingerprint is always `***`:
+    /// an unsalted hash of a low entropy secret could be looked up
+    /// in a dictionary.
+    pub fn password(secret: &'a [u8]) -> Secret<'a> {
+        Secret {
+            secret: secret,
+            fingerprinted: false,
+        }
+    }
+
+    /// Return a short hex fingerprint of the secret: the first 4
+    /// bytes of its SHA-256, or `***` if it's empty or a password.
+    /// It can be displayed safely.
+    pub fn fingerprint(&self) -> String {
+        if self.secret.is_empty() || !self.fingerprinted {
+            return "***".to_owned();
+        }
+
+        match crypto::sha256(self.secret) {
+            Ok(h) => hex_encode(&h[..4]),
+            Err(_) => "????????".to_owned(),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Secret<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", self.fingerprint())
+    }
+}
+
+impl<'a> fmt::Display for Secret<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted {}]", self.fingerprint())
+    }
+}
+
+/// Lazy iterator over the accounts of a blob, returned by
+/// `Session::accounts_iter`
+pub struct Accounts<'a> {
+    session: &'a Session,
+    key: &'a SecureStorage,
+    blob: SecureStorage,
+    /// Offset of the next chunk in `blob`
+    pos: usize,
+    /// The accounts following a SHAR chunk belong to the share
+    share: Option<Share>,
+    /// ATTA chunks indexed by account ID. They're at the end of the
+    /// blob, they have to be found before decoding the accounts.
+    attachments: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl<'a> Accounts<'a> {
+    fn new(session: &'a Session, blob: SecureStorage) -> Result<Accounts<'a>> {
+        let key =
+            match session.crypto_key {
+                Some(ref k) => k,
+                None => {
+                    let err = "Decryption key is not available".to_owned();
+                    return Err(Error::Unsupported(err));
+                }
+            };
+
+        let attachments =
+            attachment_chunks(&blob)
+            .into_iter()
+            .map(|(id, chunks)| {
+                (id, chunks.into_iter().map(|c| c.to_vec()).collect())
+            })
+            .collect();
+
+        Ok(Accounts {
+            session: session,
+            key: key,
+            blob: blob,
+            pos: 0,
+            share: None,
+            attachments: attachments,
+        })
+    }
+
+    /// Decode the `ACCT` chunk whose payload is at `start` in the blob
+    /// as well as the `ACFL` chunks following it
+    fn account(&mut self, start: usize, len: usize) -> Result<Account> {
+        let mut fields = Vec::new();
+
+        loop {
+            // Peek at the next chunk, errors are reported by the next
+            // call to `next`
+            let mut pos = self.pos;
+
+            match read_chunk(&self.blob, &mut pos) {
+                Some(Ok(c)) if &c.id == b"ACFL" => {
+                    self.pos = pos;
+                    fields.push(c.data);
+                }
+                _ => break,
+            }
+        }
+
+        decode_account(&self.blob[start..start + len],
+                       &fields,
+                       self.share.as_ref(),
+                       self.key,
+                       &self.attachments)
+    }
+}
+
+impl<'a> Iterator for Accounts<'a> {
+    type Item = Result<Account>;
+
+    fn next(&mut self) -> Option<Result<Account>> {
+        loop {
+            // The chunk borrows the blob, keep the offset of its
+            // payload (right before the new position) instead of
+            // copying it out of the locked memory
+            let (id, start, len) =
+                match read_chunk(&self.blob, &mut self.pos) {
+                    Some(Ok(c)) => {
+                        let len = c.data.len();
+
+                        (c.id, self.pos - len, len)
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => return None,
+                };
+
+            match &id {
+                b"SHAR" => {
+                    let session = self.session;
+                    let data = &self.blob[start..start + len];
+
+                    let share =
+                        Share::from_chunk(data, self.key, || {
+                            session.decrypt_private_key()
+                        });
+
+                    match share {
+                        Ok(s) => {
+                            debug!("Decoding share {} [id: {}]", s.name, s.id);
+                            self.share = Some(s);
+                        }
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                b"ACCT" => return Some(self.account(start, len)),
+                b"ACFL" => warn!("Ignoring orphan ACFL chunk"),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Decode the `ACCT` chunk `data` and its `ACFL` chunks `fields`.
+/// The account is decrypted with the `share`'s key if it belongs to
+/// one, otherwise with the user's crypto `key`. Its ATTA chunks are
+/// looked up in `attachments`, see `attachment_chunks`.
+fn decode_account<T>(data: &[u8],
+                     fields: &[&[u8]],
+                     share: Option<&Share>,
+                     key: &[u8],
+                     attachments: &HashMap<String, Vec<T>>) -> Result<Account>
+    where T: AsRef<[u8]> {
+    let account_key = share.map_or(key, |s| &s.sharing_key);
+
+    let mut account = try!(Account::from_chunk(data, account_key));
+
+    if let Some(s) = share {
+        account.set_share(s);
+    }
+
+    for f in fields {
+        account.fields.push(try!(Field::from_chunk(f, account_key)));
+    }
+
+    if let Some(chunks) = attachments.get(&account.id) {
+        try!(account.add_attachments(chunks));
+    }
+
+    Ok(account)
+}
+
+/// Return the ATTA chunks of `blob` indexed by the ID of the account
+/// they belong to. Stops at the first invalid chunk, the error is
+/// reported when decoding the accounts. The attachments whose parent
+/// can't be decoded are skipped.
+fn attachment_chunks(blob: &[u8]) -> HashMap<String, Vec<&[u8]>> {
+    let mut attachments = HashMap::new();
+
+    for chunk in blob::BlobReader::new(blob) {
+        let chunk =
+            match chunk {
+                Ok(c) => c,
+                Err(_) => break,
+            };
+
+        if &chunk.id == b"ATTA" {
+            match Attachment::parent_from_chunk(chunk.data) {
+                Ok(parent) => {
+                    attachments.entry(parent)
+                        .or_insert(Vec::new())
+                        .push(chunk.data);
+                }
+                Err(e) => warn!("Ignoring an invalid attachment: {}", e),
+            }
+        }
+    }
+
+    attachments
+}
+
+/// Read the chunk at offset `pos` in `blob` and move `pos` past it.
+/// On error `pos` is moved to the end of the blob.
+fn read_chunk<'a>(blob: &'a [u8],
+                  pos: &mut usize) -> Option<Result<blob::Chunk<'a>>> {
+    let chunk = blob::BlobReader::new(&blob[*pos..]).next();
+
+    match chunk {
+        // Identifier and length come before the payload
+        Some(Ok(ref c)) => *pos += 8 + c.data.len(),
+        Some(Err(_)) => *pos = blob.len(),
+        None => (),
+    }
+
+    chunk
+}
+
+/// Policy for the use of the cached blob
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SyncMode {
+    /// Use the cache if it's more recent than `BLOB_CACHE_MAX_AGE`,
+    /// otherwise download the blob. This is the default.
+    Auto,
+    /// Always download the blob
+    Now,
+    /// Only use the cache, fail if there's none
+    No,
+}
+
+/// Supported OTP methods
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OtpMethod {
+    /// Yubico hardware tokens
+    YubiKey,
+    /// Google Authenticator
+    GoogleAuthenticator,
+    /// LastPass USB-key based OTP
+    Sesame,
+    /// Login approval on a separate device (e.g. LastPass
+    /// Authenticator push notification)
+    OutOfBand,
+}
+
+impl OtpMethod {
+    /// Return the name of the POST variable used to send the OTP code
+    /// to the server.
+    fn post_var(self) -> &'static [u8] {
+        match self {
+            OtpMethod::Sesame => b"sesameotp",
+            _ => b"otp",
+        }
+    }
+}
+
+impl fmt::Display for OtpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &OtpMethod::GoogleAuthenticator =>
+                write!(f, "Google Authenticator"),
+            &OtpMethod::OutOfBand =>
+                write!(f, "Out-of-band"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[test]
+fn test_login_error() {
+    let parse = |xml: &[u8]| {
+        let dom = xml::Dom::parse(xml).unwrap();
+
+        login_error(dom.element(&["response", "error"]).unwrap())
+    };
+
+    match parse(b"<response><error cause=\"accountlocked\" \
+                  message=\"Too many attempts\"/></response>") {
+        Error::BadProtocol(m) =>
+            assert_eq!(m, "Unknown error: accountlocked (Too many attempts)"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match parse(b"<response><error cause=\"gridrestricted\"/></response>") {
+        Error::Unsupported(m) =>
+            assert_eq!(m, "Grid-based auth requested: gridrestricted"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match parse(b"<response><error cause=\"verifydevice\"/></response>") {
+        Error::DeviceVerificationRequired => (),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match parse(b"<response><error cause=\"restrictedmobile\"/></response>") {
+        Error::AccountRestricted(m) => assert_eq!(m, "restrictedmobile"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match parse(b"<response><error cause=\"googleauthrequired\" \
+                  message=\"Enter your code\"/></response>") {
+        Error::OtpRequired(OtpMethod::GoogleAuthenticator) => (),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match parse(b"<response><error cause=\"googleauthfailed\" \
+                  message=\"Invalid code\"/></response>") {
+        Error::OtpFailed(OtpMethod::GoogleAuthenticator, m) =>
+            assert_eq!(m, "Invalid code"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn test_finalize_login() {
+    let finalize = |ok: &[u8]| {
+        let mut session = Session::new("user@example.com");
+
+        let xml = xml::Dom::parse(ok).unwrap();
+
+        let res = session.finalize_login(xml.element(&["ok"]).unwrap());
+
+        (res, session.is_authenticated())
+    };
+
+    let (res, authenticated) =
+        finalize(b"<ok uid=\"42\" sessionid=\"0a1B2c-3,d\" token=\"t0k3n=\" \
+                   privatekeyenc=\"\"/>");
+
+    assert!(res.is_ok() && authenticated);
+
+    let invalid: &[&[u8]] = &[
+        b"<ok sessionid=\"0a1b\" token=\"t0k3n\" privatekeyenc=\"\"/>",
+        b"<ok uid=\"42\" token=\"t0k3n\" privatekeyenc=\"\"/>",
+        b"<ok uid=\"42\" sessionid=\"0a1b\" privatekeyenc=\"\"/>",
+        b"<ok uid=\"42\" sessionid=\"\" token=\"t0k3n\" \
+           privatekeyenc=\"\"/>",
+        b"<ok uid=\"42\" sessionid=\"0a1b\" token=\"\" \
+           privatekeyenc=\"\"/>",
+        b"<ok uid=\"42\" sessionid=\"0a1b; x=y\" token=\"t0k3n\" \
+           privatekeyenc=\"\"/>",
+        b"<ok uid=\"-1\" sessionid=\"0a1b\" token=\"t0k3n\" \
+           privatekeyenc=\"\"/>",
+    ];
+
+    for &ok in invalid {
+        match finalize(ok) {
+            (Err(Error::BadProtocol(_)), false) => (),
+            (r, _) => panic!("Invalid login accepted: {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn test_check_session_response() {
+    let check = |xml: &[u8]| {
+        check_session_response(&xml::Dom::parse(xml).unwrap())
+    };
+
+    let expired = |r| match r {
+        Err(Error::SessionExpired) => true,
+        _ => false,
+    };
+
+    assert!(expired(check(b"<xmlresponse><error cause=\"notloggedin\"/>\
+                            </xmlresponse>")));
+    assert!(expired(check(b"<response><error cause=\"sessionexpired\" \
+                            message=\"Session expired\"/></response>")));
+
+    assert!(check(b"<xmlresponse><error cause=\"invalidaid\"/>\
+                    </xmlresponse>").is_ok());
+    assert!(check(b"<xmlresponse><error>notloggedin</error>\
+                    </xmlresponse>").is_ok());
+    assert!(check(b"<xmlresponse><result aid=\"42\"/></xmlresponse>")
+            .is_ok());
+}
+
+#[test]
+fn test_parse_password_change() {
+    let parse = |xml: &[u8]| {
+        parse_password_change(&xml::Dom::parse(xml).unwrap())
+    };
+
+    let change =
+        parse(b"<lastpass rc=\"OK\">\
+                <data xml=\"2\" token=\"t0k3n\" privatekeyenc=\"abcd\">\
+                <accounts>\
+                <account aid=\"1\" u=\"!uiv|user\" p=\"!piv|pass\"/>\
+                <account aid=\"2\" u=\"\" p=\"ecbpass\"/>\
+                </accounts>\
+                <fields><field value=\"!fiv|field\"/></fields>\
+                <su_keys><su_key uid=\"42\" sharing_key=\"3082\"/></su_keys>\
+                </data></lastpass>").unwrap();
+
+    assert_eq!(change.token, "t0k3n");
+    assert_eq!(change.private_key_enc, "abcd");
+    assert_eq!(change.fields, vec![
+        ("!uiv|user".to_owned(), false),
+        ("!piv|pass".to_owned(), false),
+        ("ecbpass".to_owned(), false),
+        ("!fiv|field".to_owned(), true),
+    ]);
+    assert_eq!(change.su_keys, vec![("42".to_owned(), "3082".to_owned())]);
+
+    match parse(b"<lastpass rc=\"BAD\"/>") {
+        Err(Error::InvalidPassword) => (),
+        _ => panic!("Invalid password not detected"),
+    }
+
+    assert!(parse(b"<lastpass rc=\"OK\"><data/></lastpass>").is_err());
+    assert!(parse(b"<response><ok/></response>").is_err());
+}
+
+#[test]
+fn test_login_redirect() {
+    let redirect = |xml: &[u8]| {
+        login_redirect(&xml::Dom::parse(xml).unwrap(), "lastpass.com")
+    };
+
+    assert_eq!(redirect(b"<response><redirect url=\"https://lastpass.eu/\"/>\
+                          </response>").unwrap(),
+               Some("lastpass.eu".to_owned()));
+    assert_eq!(redirect(b"<response><error cause=\"unknownemail\" \
+                          server=\"LastPass.eu\"/></response>").unwrap(),
+               Some("lastpass.eu".to_owned()));
+
+    assert_eq!(redirect(b"<response><error cause=\"unknownpassword\" \
+                          server=\"lastpass.com\"/></response>").unwrap(),
+               None);
+    assert_eq!(redirect(b"<response><error cause=\"unknownpassword\"/>\
+                          </response>").unwrap(),
+               None);
+    assert_eq!(redirect(b"<response><ok uid=\"1\"/></response>").unwrap(),
+               None);
+
+    assert!(redirect(b"<response><redirect/></response>").is_err());
+    assert!(redirect(b"<response><redirect url=\"https://evil host/\"/>\
+                       </response>").is_err());
+}
+
+#[test]
+fn test_parse_xml_response() {
+    let html: &[&[u8]] = &[
+        b"<!DOCTYPE html>\n<html><body>Maintenance</body></html>",
+        b"\r\n  <HTML>\n<head><title>502 Bad Gateway</title></head>",
+    ];
+
+    for page in html {
+        match parse_xml_response(page) {
+            Err(Error::BadProtocol(m)) => {
+                assert!(m.starts_with("server returned an HTML page: "));
+                assert!(!m.contains('\n'));
+            }
+            _ => panic!("HTML page not detected"),
+        }
+    }
+
+    let xml = b"<?xml version=\"1.0\"?><response><ok/></response>";
+
+    assert!(parse_xml_response(xml).unwrap()
+            .element(&["response", "ok"]).is_some());
+}
+
+#[test]
+fn test_blob_cache() {
+    let key: Vec<u8> = (0..32).collect();
+    let other_key: Vec<u8> = (1..33).collect();
+
+    let blob = b"LPAV\x00\x00\x00\x0242";
+
+    let cache = seal_blob_cache(&key, blob).unwrap();
+
+    assert!(cache.starts_with(BLOB_CACHE_MAGIC));
+    // The blob is not stored in the clear
+    assert!(!cache.windows(4).any(|w| w == b"LPAV"));
+
+    assert!(open_blob_cache(&key, &cache).unwrap().ct_eq(blob));
+    assert!(open_blob_cache(&other_key, &cache).is_err());
+
+    // The salt is authenticated
+    let mut tampered = cache.clone();
+
+    tampered[BLOB_CACHE_MAGIC.len()] ^= 1;
+
+    assert!(open_blob_cache(&key, &tampered).is_err());
+
+    // Caches from older versions were stored in the clear
+    assert!(open_blob_cache(&key, blob).is_err());
+}
+
+#[test]
+fn test_base64_decode_secure() {
+    let data: Vec<u8> = (0..10000).map(|i| (i * 7) as u8).collect();
+
+    let encoded = base64::encode(&data);
+
+    let decoded = base64_decode_secure(encoded.as_bytes()).unwrap();
+
+    assert!(decoded.ct_eq(&data));
+
+    assert!(base64_decode_secure(b"not base64!").is_err());
+}
+
+#[test]
+fn test_hex_encode_secure() {
+    let key = SecureStorage::from_slice(&[0x00, 0x7f, 0xa5, 0xff]).unwrap();
+
+    let hex = hex_encode_secure(&key).unwrap();
+
+    assert!(hex.ct_eq(b"007fa5ff"));
+    assert_eq!(&*hex_encode_secure(&[]).unwrap(), b"");
+}
+
+#[test]
+fn test_secret() {
+    let token = SecureStorage::from_slice(b"hunter2").unwrap();
+    let other = SecureStorage::from_slice(b"hunter3").unwrap();
+
+    let secret = Secret::new(&token);
+
+    // First bytes of sha256("hunter2")
+    assert_eq!(secret.fingerprint(), "f52fbd32");
+    assert!(secret.fingerprint() != Secret::new(&other).fingerprint());
+
+    assert_eq!(format!("{}", secret), "[redacted f52fbd32]");
+    assert_eq!(format!("{:?}", secret), "Secret(f52fbd32)");
+    assert_eq!(format!("{}", Secret::new(b"")), "[redacted ***]");
+
+    // Passwords are never fingerprinted
+    assert_eq!(Secret::password(&token).fingerprint(), "***");
+    assert_eq!(format!("{:?}", Secret::password(b"hunter2")), "Secret(***)");
+}
+
+#[test]
+fn test_accounts_iter() {
+    let mut session = Session::new("user@example.com");
+
+    let key = SecureStorage::from_slice(&[0; 32]).unwrap();
+
+    assert!(Accounts::new(&session, key).is_err());
+
+    session.set_crypto_key(SecureStorage::from_slice(&[0; 32]).unwrap());
+
+    // Only the ID is needed, the other items are empty
+    let blob = b"LPAV\x00\x00\x00\x0227\
+                 ACCT\x00\x00\x00\x05\x00\x00\x00\x011\
+                 ACFL\x00\x00\x00\x17\
+                 \x00\x00\x00\x01a\
+                 \x00\x00\x00\x08checkbox\
+                 \x00\x00\x00\x02on\
+                 ACCT\x00\x00\x00\x05\x00\x00\x00\x012\
+                 ENDM\x00\x00\x00\x02OK\
+                 ACCT\x00\x00\x00\x05";
+
+    let blob = SecureStorage::from_slice(blob).unwrap();
+
+    let mut accounts = Accounts::new(&session, blob).unwrap();
+
+    let first = accounts.next().unwrap().unwrap();
+
+    assert_eq!(first.id, "1");
+    assert_eq!(first.fields.len(), 1);
+    assert_eq!(first.fields[0].name, "a");
+
+    let second = accounts.next().unwrap().unwrap();
+
+    assert_eq!(second.id, "2");
+    assert!(second.fields.is_empty());
+
+    // Truncated chunk
+    assert!(accounts.next().unwrap().is_err());
+    assert!(accounts.next().is_none());
+}
+
+#[test]
+fn test_field_params() {
+    let key: Vec<u8> = (0..32).collect();
+
+    let mut checkbox =
+        Field::new("remember", SecureStorage::from_slice(b"on").unwrap());
+
+    checkbox.field_type = "checkbox".to_owned();
+    checkbox.checked = true;
+
+    let fields = vec![
+        Field::new("apikey", SecureStorage::from_slice(b"s3cr3t").unwrap()),
+        checkbox,
+    ];
+
+    let params = field_params(&fields, &key).unwrap();
+
+    let names: Vec<&str> = params.iter().map(|p| &*p.0).collect();
+
+    assert_eq!(names, ["fieldname0", "fieldtype0", "fieldvalue0",
+                       "fieldchecked0", "fieldname1", "fieldtype1",
+                       "fieldvalue1", "fieldchecked1"]);
+
+    assert_eq!(params[0].1, b"apikey");
+    assert_eq!(params[1].1, b"text");
+    assert_eq!(params[3].1, b"0");
+
+    // Text values are encrypted, checkboxes are not
+    let value = cipher::decrypt_field(&key, &params[2].1).unwrap();
+
+    assert!(value.ct_eq(b"s3cr3t"));
+    assert_eq!(params[6].1, b"on");
+    assert_eq!(params[7].1, b"1");
+}
+
+#[test]
+fn test_update_fields() {
+    // `update_account` invalidates the cached blob
+    let _home = TestHome::new("fields");
+
+    let key = SecureStorage::from_slice(&[0x42; 32]).unwrap();
+
+    let login = b"<response><ok uid=\"42\" sessionid=\"s3ss10n\" \
+                  token=\"t0k3n\" privatekeyenc=\"\"/></response>";
+
+    let transport = MockTransport::new(&[
+        ("lastpass.com", "login.php", login),
+        ("lastpass.com", "show_website.php",
+         b"<xmlresponse><result aid=\"1234\"/></xmlresponse>"),
+    ]);
+
+    let sent = transport.sent.clone();
+
+    let mut session =
+        Session::with_transport("user@example.com",
+                                DEFAULT_SERVER,
+                                Box::new(transport));
+
+    session.try_login(&[]).unwrap();
+    session.set_crypto_key(SecureStorage::from_slice(&key).unwrap());
+
+    let mut checkbox =
+        Field::new("remember", SecureStorage::from_slice(b"on").unwrap());
+
+    checkbox.field_type = "checkbox".to_owned();
+    checkbox.checked = true;
+
+    let mut account = Account::new("web/site");
+
+    account.id = "0".to_owned();
+    account.fields = vec![
+        Field::new("apikey", SecureStorage::from_slice(b"s3cr3t").unwrap()),
+        checkbox,
+    ];
+
+    assert_eq!(session.update_account(&account).unwrap(),
+               Some("1234".to_owned()));
+
+    let param = |name| MockTransport::sent_param(&sent, 1, name).unwrap();
+
+    assert_eq!(param("fieldcnt"), b"2");
+    assert_eq!(param("fieldname0"), b"apikey");
+    assert_eq!(param("fieldtype0"), b"text");
+    assert_eq!(param("fieldchecked0"), b"0");
+    assert_eq!(param("fieldname1"), b"remember");
+    assert_eq!(param("fieldtype1"), b"checkbox");
+    assert_eq!(param("fieldvalue1"), b"on");
+    assert_eq!(param("fieldchecked1"), b"1");
+
+    let value = cipher::decrypt_field(&key, &param("fieldvalue0")).unwrap();
+
+    assert!(value.ct_eq(b"s3cr3t"));
+}
+
+#[test]
+fn test_authed_post() {
+    let session = Session::new("user@example.com");
+
+    // Nothing is sent without a session
+    match session.authed_post("show_website.php", &[(b"extjs", b"1")]) {
+        Err(Error::NotAuthenticated) => (),
+        _ => panic!("Unauthenticated request was sent"),
+    }
+}
+
+#[test]
+fn test_website_result() {
+    let result = |xml: &[u8]| website_result(&xml::Dom::parse(xml).unwrap());
+
+    assert_eq!(result(b"<xmlresponse><result aid=\"42\"/></xmlresponse>")
+               .unwrap(),
+               Some("42".to_owned()));
+
+    match result(b"<xmlresponse><error message=\"nope\"/></xmlresponse>") {
+        Err(Error::BadProtocol(ref m)) => assert_eq!(m, "Update failed: nope"),
+        _ => panic!("Invalid error"),
+    }
+
+    assert!(result(b"<xmlresponse/>").is_err());
+}
+
+/// Parameters of the requests received by a `MockTransport`
+#[cfg(test)]
+type SentParams = ::std::cell::RefCell<Vec<Vec<(Vec<u8>, Vec<u8>)>>>;
+
+/// Transport returning canned responses in order. Each response is
+/// expected to be requested from the given server and page. The
+/// parameters of every request are recorded in `sent`.
+#[cfg(test)]
+#[derive(Debug)]
+struct MockTransport {
+    responses: ::std::cell::RefCell<Vec<(&'static str,
+                                         &'static str,
+                                         Vec<u8>)>>,
+    sent: ::std::rc::Rc<SentParams>,
+}
+
+#[cfg(test)]
+impl MockTransport {
+    fn new(responses: &[(&'static str, &'static str, &[u8])])
+           -> MockTransport {
+        let responses =
+            responses.iter()
+            .map(|&(server, page, r)| (server, page, r.to_vec()))
+            .collect();
+
+        MockTransport {
+            responses: ::std::cell::RefCell::new(responses),
+            sent: Default::default(),
+        }
+    }
+
+    /// Return the value of the parameter `name` of the `n`th request
+    fn sent_param(sent: &SentParams,
+                  n: usize,
+                  name: &str) -> Option<Vec<u8>> {
+        sent.borrow()[n].iter()
+            .find(|p| p.0 == name.as_bytes())
+            .map(|p| p.1.clone())
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn post(&self,
+            server: &str,
+            page: &str,
+            _: Option<&[u8]>,
+            params: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+        let (expected_server, expected_page, response) =
+            self.responses.borrow_mut().remove(0);
+
+        self.sent.borrow_mut().push(params.iter()
+                                    .map(|&(n, v)| (n.to_vec(), v.to_vec()))
+                                    .collect());
+
+        assert_eq!(server, expected_server);
+        assert_eq!(page, expected_page);
+
+        Ok(response)
+    }
+}
+
+/// Serializes the tests changing `$LPASS_HOME`, which is process-wide
+#[cfg(test)]
+static TEST_HOME_LOCK: ::std::sync::Mutex<()> = ::std::sync::Mutex::new(());
+
+/// Point `$LPASS_HOME` to an empty temporary directory until dropped,
+/// then remove it and restore the previous value.
+#[cfg(test)]
+struct TestHome {
+    path: ::std::path::PathBuf,
+    previous: Option<::std::ffi::OsString>,
+    _lock: ::std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestHome {
+    fn new(name: &str) -> TestHome {
+        use std::env;
+
+        // A failed test poisons the lock, that's no reason to fail the
+        // others
+        let lock = TEST_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = env::temp_dir().join(format!("lpass-test-{}-{}",
+                                                name,
+                                                ::std::process::id()));
+
+        let _ = ::std::fs::remove_dir_all(&path);
+
+        let previous = env::var_os("LPASS_HOME");
+
+        env::set_var("LPASS_HOME", &path);
+
+        TestHome {
+            path: path,
+            previous: previous,
+            _lock: lock,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        use std::env;
+
+        let _ = ::std::fs::remove_dir_all(&self.path);
+
+        match self.previous {
+            Some(ref p) => env::set_var("LPASS_HOME", p),
+            None => env::remove_var("LPASS_HOME"),
+        }
+    }
+}
+
+#[test]
+fn test_mock_login() {
+    let transport = MockTransport::new(&[
+        ("lastpass.com", "login.php",
+         b"<response><error cause=\"unknownpassword\" \
+           message=\"Invalid password\"/></response>"),
+        ("lastpass.com", "login.php",
+         b"<response><redirect url=\"https://lastpass.eu/\"/></response>"),
+        ("lastpass.eu", "login.php",
+         b"<?xml version=\"1.0\"?><response><ok uid=\"42\" \
+           sessionid=\"s3ss10n\" token=\"t0k3n\" privatekeyenc=\"\"/>\
+           </response>"),
+    ]);
+
+    let mut session =
+        Session::with_transport("User@Example.com",
+                                DEFAULT_SERVER,
+                                Box::new(transport));
+
+    let params: &[(&[u8], &[u8])] = &[(b"xml", b"2")];
+
+    assert!(session.try_login(params).is_err());
+    assert!(!session.is_authenticated());
+
+    session.try_login(params).unwrap();
+
+    assert!(session.is_authenticated());
+    assert_eq!(session.username(), "user@example.com");
+    assert_eq!(session.server(), "lastpass.eu");
+    assert_eq!(session.uid, Some(42));
+    assert!(session.session_id.as_ref().unwrap().ct_eq(b"s3ss10n"));
+    assert!(session.session_token.as_ref().unwrap().ct_eq(b"t0k3n"));
+    assert!(session.private_key_enc.is_none());
+}
+
+#[test]
+fn test_out_of_band_timeout() {
+    let pending =
+        b"<response><error cause=\"outofbandrequired\"/></response>";
+
+    // The server never sees the approval
+    let transport = MockTransport::new(&[
+        ("lastpass.com", "login.php", pending),
+        ("lastpass.com", "login.php", pending),
+    ]);
+
+    let sent = transport.sent.clone();
+
+    let mut session =
+        Session::with_transport("user@example.com",
+                                DEFAULT_SERVER,
+                                Box::new(transport));
+
+    let params: &[(&[u8], &[u8])] = &[(b"xml", b"2")];
+
+    match session.out_of_band_login(params, Duration::from_secs(1)) {
+        Err(Error::ApprovalTimeout) => (),
+        r => panic!("Unexpected out-of-band result: {:?}", r.err()),
+    }
+
+    assert_eq!(sent.borrow().len(), 2);
+    assert_eq!(MockTransport::sent_param(&sent, 1, "outofbandrequest"),
+               Some(b"1".to_vec()));
+    assert!(!session.is_authenticated());
+}
+
+#[test]
+fn test_mock_blob() {
+    let key = SecureStorage::from_slice(&[0x42; 32]).unwrap();
+
+    let name = crypto::encrypt(&key, None, b"GMail").unwrap();
+
+    let mut blob = b"LPAV\x00\x00\x00\x0227\
+                     ACCT\x00\x00\x00\x19\x00\x00\x00\x011\x00\x00\x00\x10"
+        .to_vec();
+
+    blob.extend_from_slice(&name);
+    blob.extend_from_slice(b"ENDM\x00\x00\x00\x02OK");
+
+    let login = b"<response><ok uid=\"42\" sessionid=\"s3ss10n\" \
+                  token=\"t0k3n\" privatekeyenc=\"\"/></response>";
+
+    let transport = MockTransport::new(&[
+        ("lastpass.com", "login.php", login),
+        ("lastpass.com", "getaccts.php", base64::encode(&blob).as_bytes()),
+    ]);
+
+    let mut session =
+        Session::with_transport("user@example.com",
+                                DEFAULT_SERVER,
+                                Box::new(transport));
+
+    session.try_login(&[]).unwrap();
+    session.set_crypto_key(key);
+
+    let blob = session.get_blob().unwrap();
+
+    let accounts: Vec<_> =
+        Accounts::new(&session, blob).unwrap()
+        .collect::<Result<_>>()
+        .unwrap();
+
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].id, "1");
+    assert_eq!(accounts[0].name, "GMail");
 }
@@ -0,0 +1,324 @@
+//! Platform specific operations
+//!
+//! Everything that needs to talk to the OS directly (memory locking,
+//! terminal detection, file permissions...) goes through this module
+//! so that the rest of the crate builds on both Unix and Windows.
+
+pub use self::imp::{lock_memory, unlock_memory, exclude_from_core_dump,
+                    is_resource_limit, stdin_is_a_tty, stdout_is_a_tty,
+                    restrict_file_creation_mask, hostname, home_dir,
+                    create_private_dir, open_private_file, make_private,
+                    is_private, is_writable_by_others};
+
+#[cfg(unix)]
+mod imp {
+    use libc;
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt, DirBuilderExt};
+
+    /// Lock the memory pages containing `s` to prevent them from
+    /// being swapped to disk
+    pub fn lock_memory(s: &[u8]) -> io::Result<()> {
+        let ret =
+            unsafe {
+                libc::mlock(s.as_ptr() as *const _, s.len() as _)
+            };
+
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Unlock the memory pages locked by `lock_memory`. Errors are
+    /// ignored since there isn't much we can do about them.
+    pub fn unlock_memory(s: &[u8]) {
+        let _ =
+            unsafe {
+                libc::munlock(s.as_ptr() as *const _, s.len() as _)
+            };
+    }
+
+    /// Exclude the pages containing `s` from the core dumps.
+    /// `madvise` works on whole pages so the data sharing the first
+    /// and last pages is excluded as well, which is harmless. The
+    /// pages are never marked dumpable again since they could still
+    /// contain other secrets.
+    #[cfg(target_os = "linux")]
+    pub fn exclude_from_core_dump(s: &[u8]) {
+        if s.is_empty() {
+            return;
+        }
+
+        let page_size =
+            unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+        let start = s.as_ptr() as usize & !(page_size - 1);
+        let end = s.as_ptr() as usize + s.len();
+
+        let ret =
+            unsafe {
+                libc::madvise(start as *mut _,
+                              end - start,
+                              libc::MADV_DONTDUMP)
+            };
+
+        // Not fatal, the secrets are still locked in memory
+        if ret < 0 {
+            debug!("madvise failed: {}", io::Error::last_os_error());
+        }
+    }
+
+    /// Exclude the pages containing `s` from the core dumps, only
+    /// supported on Linux
+    #[cfg(not(target_os = "linux"))]
+    pub fn exclude_from_core_dump(_: &[u8]) {
+    }
+
+    /// Return true if `err`, returned by `lock_memory`, means that we
+    /// hit the limit of locked memory
+    pub fn is_resource_limit(err: &io::Error) -> bool {
+        match err.raw_os_error() {
+            Some(libc::EPERM) | Some(libc::ENOMEM) => true,
+            _ => false,
+        }
+    }
+
+    /// Return true if the standard input is a terminal
+    pub fn stdin_is_a_tty() -> bool {
+        unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+    }
+
+    /// Return true if the standard output is a terminal
+    pub fn stdout_is_a_tty() -> bool {
+        unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+    }
+
+    /// Make the files and directories created by this process only
+    /// accessible by the user
+    pub fn restrict_file_creation_mask() {
+        // Returns the previous mask, can't fail
+        unsafe {
+            libc::umask(0o077);
+        }
+    }
+
+    /// Return the name of this host, if it can be retrieved
+    pub fn hostname() -> Option<String> {
+        let mut hostname = [0u8; 256];
+
+        let ret =
+            unsafe {
+                libc::gethostname(hostname.as_mut_ptr() as *mut _,
+                                  hostname.len() as _)
+            };
+
+        let len = hostname.iter().position(|&b| b == 0).unwrap_or(0);
+
+        if ret < 0 || len == 0 {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(&hostname[..len]).into_owned())
+    }
+
+    /// Return the user's home directory
+    pub fn home_dir() -> Option<PathBuf> {
+        env::var_os("HOME").map(PathBuf::from)
+    }
+
+    /// Create the directory `path` and its parents, only accessible
+    /// by the user
+    pub fn create_private_dir(path: &Path) -> io::Result<()> {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(path)
+    }
+
+    /// Make the files created with `options` only accessible by the
+    /// user
+    pub fn open_private_file(options: &mut fs::OpenOptions)
+                             -> &mut fs::OpenOptions {
+        options.mode(0o600)
+    }
+
+    /// Make the existing `file` only accessible by the user
+    pub fn make_private(file: &fs::File) -> io::Result<()> {
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+    }
+
+    /// Return true if the file described by `metadata` is only
+    /// accessible by its owner
+    pub fn is_private(metadata: &fs::Metadata) -> bool {
+        metadata.permissions().mode() & 0o077 == 0
+    }
+
+    /// Return true if the file described by `metadata` can be
+    /// modified by other users than its owner
+    pub fn is_writable_by_others(metadata: &fs::Metadata) -> bool {
+        metadata.permissions().mode() & 0o022 != 0
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use libc::c_void;
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Once, ONCE_INIT};
+
+    type Handle = *mut c_void;
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+
+    const ERROR_NOT_ENOUGH_MEMORY: i32 = 8;
+    const ERROR_WORKING_SET_QUOTA: i32 = 1453;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualLock(address: *mut c_void, size: usize) -> i32;
+        fn VirtualUnlock(address: *mut c_void, size: usize) -> i32;
+        fn GetStdHandle(std_handle: u32) -> Handle;
+        fn GetConsoleMode(console: Handle, mode: *mut u32) -> i32;
+        fn GetComputerNameW(buffer: *mut u16, size: *mut u32) -> i32;
+    }
+
+    /// Lock the memory pages containing `s` to prevent them from
+    /// being swapped to disk
+    pub fn lock_memory(s: &[u8]) -> io::Result<()> {
+        let ret = unsafe { VirtualLock(s.as_ptr() as *mut _, s.len()) };
+
+        if ret == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Unlock the memory pages locked by `lock_memory`. Errors are
+    /// ignored since there isn't much we can do about them.
+    pub fn unlock_memory(s: &[u8]) {
+        let _ = unsafe { VirtualUnlock(s.as_ptr() as *mut _, s.len()) };
+    }
+
+    /// Exclude the pages containing `s` from the core dumps, only
+    /// supported on Linux
+    pub fn exclude_from_core_dump(_: &[u8]) {
+    }
+
+    /// Return true if `err`, returned by `lock_memory`, means that we
+    /// hit the limit of locked memory (the working set size)
+    pub fn is_resource_limit(err: &io::Error) -> bool {
+        match err.raw_os_error() {
+            Some(ERROR_NOT_ENOUGH_MEMORY) |
+            Some(ERROR_WORKING_SET_QUOTA) => true,
+            _ => false,
+        }
+    }
+
+    fn is_console(std_handle: u32) -> bool {
+        let mut mode = 0;
+
+        unsafe {
+            let handle = GetStdHandle(std_handle);
+
+            !handle.is_null() && GetConsoleMode(handle, &mut mode) != 0
+        }
+    }
+
+    /// Return true if the standard input is a terminal
+    pub fn stdin_is_a_tty() -> bool {
+        is_console(STD_INPUT_HANDLE)
+    }
+
+    /// Return true if the standard output is a terminal
+    pub fn stdout_is_a_tty() -> bool {
+        is_console(STD_OUTPUT_HANDLE)
+    }
+
+    /// Make the files and directories created by this process only
+    /// accessible by the user. There's no umask on Windows, the files
+    /// inherit the permissions of the user's profile directory.
+    pub fn restrict_file_creation_mask() {
+    }
+
+    /// Return the name of this host, if it can be retrieved
+    pub fn hostname() -> Option<String> {
+        let mut hostname = [0u16; 256];
+        let mut len = hostname.len() as u32;
+
+        let ret =
+            unsafe { GetComputerNameW(hostname.as_mut_ptr(), &mut len) };
+
+        if ret == 0 || len == 0 {
+            return None;
+        }
+
+        Some(String::from_utf16_lossy(&hostname[..len as usize]))
+    }
+
+    /// Return the user's home directory
+    pub fn home_dir() -> Option<PathBuf> {
+        env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+    }
+
+    /// Create the directory `path` and its parents. It inherits the
+    /// permissions of the parent which, in the user's profile, only
+    /// give access to the user.
+    pub fn create_private_dir(path: &Path) -> io::Result<()> {
+        fs::DirBuilder::new().recursive(true).create(path)
+    }
+
+    /// Make the files created with `options` only accessible by the
+    /// user, see `create_private_dir`
+    pub fn open_private_file(options: &mut fs::OpenOptions)
+                             -> &mut fs::OpenOptions {
+        options
+    }
+
+    /// Make the existing `file` only accessible by the user, see
+    /// `create_private_dir`
+    pub fn make_private(_: &fs::File) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Warn the user, once, that the permissions of the
+    /// configuration files can't be verified
+    fn warn_unchecked_permissions() {
+        static WARNING: Once = ONCE_INIT;
+
+        WARNING.call_once(|| {
+            warn!("The permissions of the configuration files are not \
+                   checked on Windows, make sure that the configuration \
+                   directory is only accessible by you");
+        });
+    }
+
+    /// Return true if the file described by `metadata` is only
+    /// accessible by its owner. The ACLs aren't checked so we can't
+    /// tell, the file is accepted after a warning.
+    pub fn is_private(_: &fs::Metadata) -> bool {
+        warn_unchecked_permissions();
+
+        true
+    }
+
+    /// Return true if the file described by `metadata` can be
+    /// modified by other users than its owner. The ACLs aren't
+    /// checked so we can't tell, the file is accepted after a
+    /// warning.
+    pub fn is_writable_by_others(_: &fs::Metadata) -> bool {
+        warn_unchecked_permissions();
+
+        false
+    }
+}
@@ -0,0 +1,115 @@
+//! Random password generation
+
+use Result;
+use SecureStorage;
+
+use openssl::rand::rand_bytes;
+
+const LETTERS: &'static [u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &'static [u8] = b"0123456789";
+const SYMBOLS: &'static [u8] = b"`~!@#$%^&*()-_=+[{]}\\|;:'\",<.>/?";
+
+/// Generate a random password of `len` characters using OpenSSL's
+/// CSPRNG. The alphabet always contains ASCII letters, digits and
+/// symbols can be excluded by setting `digits` and `symbols` to
+/// `false`.
+pub fn generate(len: usize,
+                digits: bool,
+                symbols: bool) -> Result<SecureStorage> {
+    let alphabet = alphabet(digits, symbols);
+
+    let mut password = try!(SecureStorage::with_capacity(len));
+    let mut random = try!(SecureStorage::from_vec(vec![0; len]));
+
+    while password.len() < len {
+        try!(rand_bytes(&mut random));
+
+        for &b in random.iter() {
+            if password.len() == len {
+                break;
+            }
+
+            if let Some(i) = pick(alphabet.len(), b) {
+                try!(password.push(alphabet[i]));
+            }
+        }
+    }
+
+    Ok(password)
+}
+
+fn alphabet(digits: bool, symbols: bool) -> Vec<u8> {
+    let mut alphabet = LETTERS.to_vec();
+
+    if digits {
+        alphabet.extend_from_slice(DIGITS);
+    }
+
+    if symbols {
+        alphabet.extend_from_slice(SYMBOLS);
+    }
+
+    alphabet
+}
+
+/// Map the random byte `b` into an index in `0..n`. In order to avoid
+/// the modulo bias we reject the bytes that fall in the last partial
+/// run of `n` values, in which case `None` is returned and a new byte
+/// must be drawn.
+fn pick(n: usize, b: u8) -> Option<usize> {
+    assert!(n > 0 && n <= 256);
+
+    let limit = 256 - 256 % n;
+
+    let b = b as usize;
+
+    if b < limit {
+        Some(b % n)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_pick() {
+    // 256 % 62 == 8 so the last 8 byte values must be rejected
+    assert_eq!(pick(62, 0), Some(0));
+    assert_eq!(pick(62, 61), Some(61));
+    assert_eq!(pick(62, 62), Some(0));
+    assert_eq!(pick(62, 247), Some(61));
+    assert_eq!(pick(62, 248), None);
+    assert_eq!(pick(62, 255), None);
+
+    // Powers of two never reject
+    assert_eq!(pick(64, 255), Some(63));
+    assert_eq!(pick(256, 255), Some(255));
+
+    // Every index must be hit the same number of times
+    for &n in &[52, 62, 94] {
+        let mut counts = vec![0; n];
+
+        for b in 0..256 {
+            if let Some(i) = pick(n, b as u8) {
+                counts[i] += 1;
+            }
+        }
+
+        assert!(counts.iter().all(|&c| c == counts[0]));
+    }
+}
+
+#[test]
+fn test_generate() {
+    let password = generate(100, false, false).unwrap();
+
+    assert_eq!(password.len(), 100);
+    assert!(password.iter().all(|b| LETTERS.contains(b)));
+
+    let password = generate(100, true, true).unwrap();
+
+    assert_eq!(password.len(), 100);
+    assert!(password.iter().all(|b| alphabet(true, true).contains(b)));
+
+    assert_eq!(generate(0, true, true).unwrap().len(), 0);
+}
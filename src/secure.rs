@@ -1,10 +1,32 @@
+#[cfg(test)]
 use libc;
 
 use std::ops::{Deref, DerefMut, Drop};
-use std::cmp::{PartialEq, Eq};
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use std::cmp::{self, PartialEq, Eq};
 use std::io;
-
-use error::Result;
+use std::io::Write;
+use std::fmt;
+use std::convert::TryFrom;
+
+use error::{Result, Error};
+use platform;
+
+/// If true a failure to lock the memory because of the resource
+/// limits is not fatal
+static ALLOW_UNLOCKED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Set when the warning about unlocked memory has been displayed
+static UNLOCKED_WARNING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Allow `Storage` to use memory that couldn't be locked if `mlock`
+/// fails with `EPERM` or `ENOMEM`, which is common in containers with
+/// a low `RLIMIT_MEMLOCK`. The secrets may then be swapped but they
+/// are still zeroed when the `Storage` is dropped. By default these
+/// failures are fatal.
+pub fn set_allow_unlocked_memory(allow: bool) {
+    ALLOW_UNLOCKED.store(allow, Ordering::Relaxed);
+}
 
 /// Secure storage using `mlock` to avoid sensitive data being
 /// swapped.
@@ -54,6 +76,7 @@ impl Storage {
     /// can't lock the memory.
     pub fn from_buf(buf: Box<[u8]>) -> Result<Storage> {
         try!(mlock(&*buf));
+        platform::exclude_from_core_dump(&*buf);
 
         Ok(Storage{
             len: buf.len(),
@@ -82,12 +105,101 @@ impl Storage {
         Ok(())
     }
 
+    /// Append the contents of `data` to the `Storage`. The buffer is
+    /// reallocated at most once.
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<()> {
+        let needed = self.len + data.len();
+
+        if needed > self.storage.len() {
+            let new_capacity =
+                match self.storage.len() {
+                    0 => 32,
+                    n => n * 2,
+                };
+
+            try!(self.reallocate(cmp::max(new_capacity, needed)));
+        }
+
+        for (i, &b) in data.iter().enumerate() {
+            self.storage[self.len + i] = b;
+        }
+
+        self.len = needed;
+
+        Ok(())
+    }
+
+    /// Return the number of bytes the `Storage` can hold without
+    /// reallocating
+    pub fn capacity(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Shorten the `Storage` to `len` bytes, zeroing the discarded
+    /// bytes. Does nothing if `len` is greater than or equal to the
+    /// current length. The capacity is unchanged.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        for b in &mut self.storage[len..self.len] {
+            *b = 0;
+        }
+
+        self.len = len;
+    }
+
+    /// Zero the contents of the `Storage` and set its length to
+    /// 0. The allocation is kept and remains locked so that the
+    /// buffer can be reused.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Compare the contents of the storage with `other` in constant
+    /// time in order not to leak the position of the first
+    /// difference. The length is not considered secret however, if
+    /// the lengths differ we return early.
+    pub fn ct_eq(&self, other: &[u8]) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        let diff =
+            self.iter()
+            .zip(other.iter())
+            .fold(0, |acc, (&a, &b)| acc | (a ^ b));
+
+        diff == 0
+    }
+
+    /// Copy the contents into a regular `Vec`, for APIs that don't
+    /// accept a `Storage`.
+    ///
+    /// **The copy is not protected**: it's not locked in memory and
+    /// it's not zeroed when dropped.
+    pub fn to_vec(&self) -> Vec<u8> {
+        (**self).to_vec()
+    }
+
+    /// Same as `to_vec` but consumes the `Storage`, which is zeroed.
+    ///
+    /// **The returned `Vec` is not protected**, see `to_vec`.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.to_vec()
+    }
+
     fn reallocate(&mut self, new_capacity: usize) -> Result<()> {
         assert!(new_capacity > self.storage.len());
 
         let mut new = vec![0; new_capacity].into_boxed_slice();
 
+        // If we can't lock the new buffer we bail out before copying
+        // anything into it, `new` is then freed and `self` is left
+        // untouched (and still locked).
         try!(mlock(&*new));
+        platform::exclude_from_core_dump(&*new);
 
         for (i, &b) in self.storage.iter().enumerate() {
             new[i] = b;
@@ -123,32 +235,88 @@ impl DerefMut for Storage {
 
 impl PartialEq for Storage {
     fn eq(&self, other: &Storage) -> bool {
-        self.len() == other.len() &&
-            self.iter()
-            .zip(other.iter())
-            .all(|(&a, &b)| a == b)
+        self.ct_eq(other)
     }
 }
 
 impl Eq for Storage {}
 
+impl<'a> TryFrom<&'a str> for Storage {
+    type Error = Error;
+
+    fn try_from(s: &'a str) -> Result<Storage> {
+        Storage::from_slice(s.as_bytes())
+    }
+}
+
+/// The `String` is zeroed after being copied, even if the copy fails
+impl TryFrom<String> for Storage {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Storage> {
+        let mut bytes = s.into_bytes();
+
+        let storage = Storage::from_slice(&bytes);
+
+        for b in bytes.iter_mut() {
+            *b = 0;
+        }
+
+        storage
+    }
+}
+
+/// Only the length is displayed, never the contents
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecureStorage {{ len: {}, .. }}", self.len)
+    }
+}
+
+impl Write for Storage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.extend_from_slice(buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(Error::IoError(e)) => Err(e),
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other,
+                                         "Can't grow secure storage")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 fn mlock(s: &[u8]) -> Result<()> {
     if s.is_empty() {
         return Ok(());
     }
 
     let ret =
-        unsafe {
-            libc::mlock(s.as_ptr() as *const _,
-                        s.len() as _)
+        match mlock_should_fail() {
+            Some(errno) => Err(io::Error::from_raw_os_error(errno)),
+            None => platform::lock_memory(s),
         };
 
-    if ret < 0 {
-        error!("mlock failed, can't lock memory pages!");
-        Err(io::Error::last_os_error().into())
-    } else {
-        Ok(())
+    let err =
+        match ret {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+    let resource_limit = platform::is_resource_limit(&err);
+
+    if resource_limit && ALLOW_UNLOCKED.load(Ordering::Relaxed) {
+        if !UNLOCKED_WARNING.swap(true, Ordering::Relaxed) {
+            warn!("mlock failed ({}), secrets may be swapped to disk", err);
+        }
+
+        return Ok(());
     }
+
+    error!("mlock failed, can't lock memory pages!");
+    Err(err.into())
 }
 
 fn munlock(s: &mut [u8]) {
@@ -162,11 +330,102 @@ fn munlock(s: &mut [u8]) {
         *b = 0;
     }
 
-    // We can't do much if this call fails so let's just ignore
-    // errors.
-    let _ =
-        unsafe {
-            libc::munlock(s.as_ptr() as *const _,
-                          s.len() as _)
-        };
+    platform::unlock_memory(s);
+}
+
+// Test seam used to simulate `mlock` failures: if set `mlock` fails
+// with this errno
+#[cfg(test)]
+thread_local!(static MLOCK_FAIL: ::std::cell::Cell<Option<i32>> =
+              ::std::cell::Cell::new(None));
+
+#[cfg(test)]
+fn mlock_should_fail() -> Option<i32> {
+    MLOCK_FAIL.with(|f| f.get())
+}
+
+#[cfg(not(test))]
+fn mlock_should_fail() -> Option<i32> {
+    None
+}
+
+#[test]
+fn test_extend_from_slice() {
+    let mut s = Storage::from_slice(b"lpass").unwrap();
+
+    let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+
+    s.extend_from_slice(&data).unwrap();
+
+    // We only had room for 5 bytes, the storage should have been
+    // reallocated once to fit exactly the new contents
+    assert_eq!(s.capacity(), 5 + data.len());
+    assert_eq!(&s[..5], b"lpass");
+    assert_eq!(&s[5..], &data[..]);
+
+    s.extend_from_slice(b"").unwrap();
+    assert_eq!(s.len(), 5 + data.len());
+}
+
+#[test]
+fn test_debug() {
+    let s = Storage::from_slice(b"hunter2").unwrap();
+
+    assert_eq!(format!("{:?}", s), "SecureStorage { len: 7, .. }");
+    assert_eq!(format!("{:?}", Some(Storage::empty())),
+               "Some(SecureStorage { len: 0, .. })");
+}
+
+#[test]
+fn test_conversions() {
+    let s = Storage::try_from("hunter2").unwrap();
+
+    assert_eq!(&s[..], b"hunter2");
+    assert_eq!(s.to_vec(), b"hunter2".to_vec());
+
+    let s = Storage::try_from("correct horse".to_owned()).unwrap();
+
+    assert_eq!(s.into_vec(), b"correct horse".to_vec());
+}
+
+#[test]
+fn test_reallocate_mlock_failure() {
+    let mut s = Storage::from_slice(b"lpass").unwrap();
+
+    MLOCK_FAIL.with(|f| f.set(Some(libc::EINVAL)));
+
+    assert!(s.push(b'!').is_err());
+    assert!(s.extend_from_slice(b"rs").is_err());
+
+    MLOCK_FAIL.with(|f| f.set(None));
+
+    assert_eq!(s.capacity(), 5);
+    assert_eq!(&s[..], b"lpass");
+
+    s.push(b'!').unwrap();
+
+    assert_eq!(&s[..], b"lpass!");
+}
+
+#[test]
+fn test_allow_unlocked() {
+    MLOCK_FAIL.with(|f| f.set(Some(libc::ENOMEM)));
+
+    // Strict by default
+    assert!(Storage::from_slice(b"lpass").is_err());
+
+    set_allow_unlocked_memory(true);
+
+    let s = Storage::from_slice(b"lpass");
+
+    // Only the resource limit errors are ignored
+    MLOCK_FAIL.with(|f| f.set(Some(libc::EINVAL)));
+
+    let invalid = Storage::from_slice(b"lpass");
+
+    set_allow_unlocked_memory(false);
+    MLOCK_FAIL.with(|f| f.set(None));
+
+    assert_eq!(&s.unwrap()[..], b"lpass");
+    assert!(invalid.is_err());
 }
@@ -0,0 +1,431 @@
+//! Folder hierarchy of the accounts
+//!
+//! Folders are not stored explicitly in the blob, they're derived
+//! from the slash-separated `group` of the accounts. Accounts with an
+//! empty group live at the root.
+
+use Account;
+
+/// Decrypted accounts organized in folders
+pub struct Vault {
+    accounts: Vec<Account>,
+}
+
+impl Vault {
+    /// Build a vault from `accounts`
+    pub fn new(accounts: Vec<Account>) -> Vault {
+        Vault {
+            accounts: accounts,
+        }
+    }
+
+    /// Return all the entries of the vault, including groups
+    pub fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    /// Consume the vault and return its entries
+    pub fn into_accounts(self) -> Vec<Account> {
+        self.accounts
+    }
+
+    /// Return the sorted list of all the folders in the vault. The
+    /// parents of nested folders are included, the root folder is
+    /// not.
+    pub fn folders(&self) -> Vec<&str> {
+        let mut folders = Vec::new();
+
+        for a in &self.accounts {
+            let group = &*a.group;
+
+            for (i, c) in group.char_indices() {
+                if c == '/' && i > 0 {
+                    folders.push(&group[..i]);
+                }
+            }
+
+            if !group.is_empty() {
+                folders.push(group);
+            }
+        }
+
+        folders.sort();
+        folders.dedup();
+
+        folders
+    }
+
+    /// Return the accounts directly contained in `folder`. Use an
+    /// empty string for the root folder.
+    pub fn accounts_in(&self, folder: &str) -> Vec<&Account> {
+        self.accounts.iter()
+            .filter(|a| !a.is_group())
+            .filter(|a| a.group == folder)
+            .collect()
+    }
+
+    /// Return the accounts matching `query`: the account with this ID
+    /// if there's one, otherwise all the accounts whose full path or
+    /// name is `query`.
+    pub fn matches(&self, query: &str) -> Vec<&Account> {
+        // An exact ID match takes precedence
+        if let Some(a) = self.accounts.iter().find(|a| a.id == query) {
+            return vec![a];
+        }
+
+        let by_path: Vec<_> =
+            self.accounts.iter()
+            .filter(|a| !a.is_group())
+            .filter(|a| a.fullname == query)
+            .collect();
+
+        // The name alone is only used if the full path doesn't match
+        // anything, otherwise "a/b" would be ambiguous with an
+        // account named "a/b" at the root and "b" in folder "a".
+        if !by_path.is_empty() {
+            return by_path;
+        }
+
+        self.accounts.iter()
+            .filter(|a| !a.is_group())
+            .filter(|a| a.name == query)
+            .collect()
+    }
+
+    /// Return the accounts whose URL has the same registered domain
+    /// (e.g. "example.co.uk" for "https://www.mail.example.co.uk")
+    /// as `url`. The scheme, port, path and "www." prefix are
+    /// ignored. Groups and secure notes are never returned.
+    ///
+    /// The registered domain is determined with a simple heuristic
+    /// instead of the full public suffix list, see
+    /// `registered_domain`.
+    pub fn find_by_url(&self, url: &str) -> Vec<&Account> {
+        let domain =
+            match url_host(url) {
+                Some(h) => registered_domain(&h).to_owned(),
+                None => return Vec::new(),
+            };
+
+        self.accounts.iter()
+            .filter(|a| !a.is_group() && !a.is_secure_note())
+            .filter(|a| {
+                match url_host(&a.url) {
+                    Some(h) => registered_domain(&h) == domain,
+                    None => false,
+                }
+            })
+            .collect()
+    }
+
+    /// Return the account at `path` (or with the ID `path`), `None`
+    /// if there's no such account or if the path is ambiguous.
+    pub fn find(&self, path: &str) -> Option<&Account> {
+        let matches = self.matches(path);
+
+        if matches.len() == 1 {
+            Some(matches[0])
+        } else {
+            None
+        }
+    }
+}
+
+/// Return the lowercase hostname of `url` without the "www." prefix,
+/// `None` if there's none. The scheme is optional.
+fn url_host(url: &str) -> Option<String> {
+    let url = url.trim();
+
+    let rest =
+        match url.find("://") {
+            Some(p) => &url[p + 3..],
+            None => url,
+        };
+
+    let authority =
+        match rest.find(|c| c == '/' || c == '?' || c == '#') {
+            Some(p) => &rest[..p],
+            None => rest,
+        };
+
+    // Strip the credentials
+    let host_port =
+        match authority.rfind('@') {
+            Some(p) => &authority[p + 1..],
+            None => authority,
+        };
+
+    let host =
+        if host_port.starts_with('[') {
+            // IPv6 address
+            match host_port.find(']') {
+                Some(p) => &host_port[..p + 1],
+                None => return None,
+            }
+        } else {
+            match host_port.find(':') {
+                Some(p) => &host_port[..p],
+                None => host_port,
+            }
+        };
+
+    let host = host.trim_right_matches('.').to_lowercase();
+
+    let host =
+        if host.starts_with("www.") {
+            host[4..].to_owned()
+        } else {
+            host
+        };
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// Second level domains used under country code TLDs like the "co"
+/// in "example.co.uk"
+const SECOND_LEVEL_DOMAINS: &'static [&'static str] = &[
+    "ac", "co", "com", "edu", "gov", "ltd", "ne", "net", "or", "org",
+    "plc",
+];
+
+/// Generic TLDs under which the domains are registered directly,
+/// taken from the ICANN section of the public suffix list
+/// (https://publicsuffix.org/list/public_suffix_list.dat)
+const GENERIC_TLDS: &'static [&'static str] = &[
+    "app", "biz", "com", "dev", "edu", "gov", "info", "int", "io", "me",
+    "mil", "net", "org",
+];
+
+/// Public suffixes owned by hosting services which give a subdomain
+/// to each of their users, taken from the private section of the
+/// public suffix list. "a.github.io" and "b.github.io" belong to
+/// different people and must never match each other.
+const SHARED_SUFFIXES: &'static [&'static str] = &[
+    "appspot.com", "azurewebsites.net", "blogspot.com",
+    "cloudfront.net", "firebaseapp.com", "github.io", "gitlab.io",
+    "herokuapp.com", "netlify.app", "netlify.com", "pages.dev",
+    "s3.amazonaws.com", "vercel.app", "web.app", "workers.dev",
+];
+
+/// Return the registered domain of `host` (the domain one level
+/// below the public suffix): "example.com" for "mail.example.com",
+/// "example.co.uk" for "mail.example.co.uk", "user.github.io" for
+/// "www.user.github.io". This is an approximation of the public
+/// suffix list: it only knows about the common generic TLDs, the
+/// common second level domains under two-letter TLDs and the
+/// shared hosting suffixes listed above. When the suffix isn't
+/// known, including an unknown second level under a two-letter TLD
+/// (which might be a public suffix like "gr.jp"), the whole host is
+/// returned so that only the exact same host matches. IP addresses
+/// are returned unchanged.
+fn registered_domain(host: &str) -> &str {
+    let is_ip =
+        host.starts_with('[') ||
+        host.chars().all(|c| c.is_digit(10) || c == '.');
+
+    if is_ip {
+        return host;
+    }
+
+    let labels: Vec<_> = host.split('.').collect();
+    let n = labels.len();
+
+    let shared =
+        SHARED_SUFFIXES.iter().find(|s| {
+            host.ends_with(*s) &&
+                host.len() > s.len() &&
+                host.as_bytes()[host.len() - s.len() - 1] == b'.'
+        });
+
+    let tld = labels[n - 1];
+
+    let keep =
+        if let Some(s) = shared {
+            s.split('.').count() + 1
+        } else if tld.len() == 2 {
+            if n < 3 {
+                2
+            } else if SECOND_LEVEL_DOMAINS.contains(&labels[n - 2]) {
+                3
+            } else {
+                // "example.de" or a public suffix like "gr.jp", we
+                // can't tell
+                return host;
+            }
+        } else if GENERIC_TLDS.contains(&tld) {
+            2
+        } else {
+            // Unknown suffix, only match the exact host
+            return host;
+        };
+
+    if n <= keep {
+        return host;
+    }
+
+    // Byte offset of the first label we keep
+    let skip: usize = labels[..n - keep].iter().map(|l| l.len() + 1).sum();
+
+    &host[skip..]
+}
+
+#[test]
+fn test_vault() {
+    let mut accounts = Vec::new();
+
+    let entries = [
+        ("1", "root"),
+        ("2", "web/mail"),
+        ("3", "web/social/forum"),
+        ("4", "work/mail"),
+        ("5", "mail"),
+    ];
+
+    for &(id, fullname) in &entries {
+        let mut a = Account::new(fullname);
+        a.id = id.to_owned();
+        accounts.push(a);
+    }
+
+    // Name containing a slash at the root
+    let mut a = Account::new("");
+    a.id = "6".to_owned();
+    a.name = "a/b".to_owned();
+    a.fullname = "a/b".to_owned();
+    accounts.push(a);
+
+    // Empty folder
+    let mut a = Account::new("empty/");
+    a.id = "7".to_owned();
+    a.url = "http://group".to_owned();
+    accounts.push(a);
+
+    let vault = Vault::new(accounts);
+
+    assert_eq!(vault.folders(),
+               vec!["empty", "web", "web/social", "work"]);
+
+    let ids = |accounts: Vec<&Account>| -> Vec<String> {
+        accounts.iter().map(|a| a.id.clone()).collect()
+    };
+
+    assert_eq!(ids(vault.accounts_in("")), vec!["1", "5", "6"]);
+    assert_eq!(ids(vault.accounts_in("web")), vec!["2"]);
+    assert_eq!(ids(vault.accounts_in("web/social")), vec!["3"]);
+    assert!(vault.accounts_in("empty").is_empty());
+
+    assert_eq!(vault.find("web/social/forum").unwrap().id, "3");
+    assert_eq!(vault.find("forum").unwrap().id, "3");
+    assert_eq!(vault.find("a/b").unwrap().id, "6");
+    assert_eq!(vault.find("4").unwrap().id, "4");
+    // Full path takes precedence over the name
+    assert_eq!(vault.find("mail").unwrap().id, "5");
+    assert!(vault.find("nothing").is_none());
+    assert!(vault.find("empty").is_none());
+
+    assert_eq!(ids(vault.matches("web/mail")), vec!["2"]);
+}
+
+#[test]
+fn test_find_by_url() {
+    let entries = [
+        ("1", "https://accounts.example.com/login"),
+        ("2", "http://www.example.com:8080"),
+        ("3", "example.com"),
+        ("4", "https://example.org"),
+        ("5", "https://mail.example.co.uk/"),
+        ("6", "https://other.co.uk/"),
+        ("7", "http://sn"),
+        ("8", "http://group"),
+        ("9", "http://192.168.1.1/admin"),
+        ("10", ""),
+    ];
+
+    let mut accounts = Vec::new();
+
+    for &(id, url) in &entries {
+        let mut a = Account::new(&format!("account{}", id));
+        a.id = id.to_owned();
+        a.url = url.to_owned();
+        accounts.push(a);
+    }
+
+    let vault = Vault::new(accounts);
+
+    let ids = |url| -> Vec<String> {
+        vault.find_by_url(url).iter().map(|a| a.id.clone()).collect()
+    };
+
+    assert_eq!(ids("https://www.example.com/index.html"),
+               vec!["1", "2", "3"]);
+    assert_eq!(ids("http://user:pw@shop.EXAMPLE.com:443/?q=1"),
+               vec!["1", "2", "3"]);
+    assert_eq!(ids("https://example.co.uk"), vec!["5"]);
+    assert_eq!(ids("https://192.168.1.1:8443"), vec!["9"]);
+    assert_eq!(ids("https://1.168.1.1"), Vec::<String>::new());
+    // The pseudo-URLs of the secure notes and groups never match
+    assert_eq!(ids("http://sn"), Vec::<String>::new());
+    assert_eq!(ids("http://group"), Vec::<String>::new());
+    assert_eq!(ids(""), Vec::<String>::new());
+
+    // Shared hosting: each subdomain belongs to a different user
+    let entries = [
+        ("1", "https://alice.github.io/"),
+        ("2", "https://www.alice.github.io/blog"),
+        ("3", "https://bob.github.io/"),
+        ("4", "https://app1.herokuapp.com"),
+        ("5", "https://app2.herokuapp.com"),
+        ("6", "http://alice.blogspot.com"),
+        ("7", "http://bob.blogspot.com"),
+        ("8", "https://github.io"),
+        ("9", "https://intranet.corp.example"),
+        ("10", "https://wiki.corp.example"),
+        ("11", "https://alice.ne.jp"),
+        ("12", "https://bob.ne.jp"),
+        ("13", "https://shop.or.jp"),
+        ("14", "https://alice.ltd.uk"),
+        ("15", "https://bob.ltd.uk"),
+        ("16", "https://alice.gr.jp"),
+        ("17", "https://example.de"),
+        ("18", "https://mail.example.de"),
+    ];
+
+    let mut accounts = Vec::new();
+
+    for &(id, url) in &entries {
+        let mut a = Account::new(&format!("account{}", id));
+        a.id = id.to_owned();
+        a.url = url.to_owned();
+        accounts.push(a);
+    }
+
+    let vault = Vault::new(accounts);
+
+    let ids = |url| -> Vec<String> {
+        vault.find_by_url(url).iter().map(|a| a.id.clone()).collect()
+    };
+
+    assert_eq!(ids("https://alice.github.io/project"), vec!["1", "2"]);
+    assert_eq!(ids("https://bob.github.io"), vec!["3"]);
+    assert_eq!(ids("https://eve.github.io"), Vec::<String>::new());
+    assert_eq!(ids("https://app2.herokuapp.com"), vec!["5"]);
+    assert_eq!(ids("https://bob.blogspot.com"), vec!["7"]);
+    assert_eq!(ids("https://github.io"), vec!["8"]);
+    // Unknown suffix, exact host only
+    assert_eq!(ids("https://wiki.corp.example/page"), vec!["10"]);
+    // Second level domains under two-letter TLDs
+    assert_eq!(ids("https://www.alice.ne.jp/"), vec!["11"]);
+    assert_eq!(ids("https://mail.bob.ne.jp/"), vec!["12"]);
+    assert_eq!(ids("https://eve.or.jp/"), Vec::<String>::new());
+    assert_eq!(ids("https://alice.ltd.uk"), vec!["14"]);
+    assert_eq!(ids("https://eve.ltd.uk"), Vec::<String>::new());
+    // Unknown second level, exact host only
+    assert_eq!(ids("https://alice.gr.jp"), vec!["16"]);
+    assert_eq!(ids("https://bob.gr.jp"), Vec::<String>::new());
+    assert_eq!(ids("https://example.de"), vec!["17"]);
+    assert_eq!(ids("https://mail.example.de"), vec!["18"]);
+}
@@ -1,11 +1,21 @@
 use std::io::Read;
 use std::fmt;
+use std::slice;
 
 pub use xml_sax::name::OwnedName;
 pub use xml_sax::attribute::OwnedAttribute;
 pub use xml_sax::namespace::Namespace;
 
-use xml_sax::reader::{EventReader, XmlEvent, Error};
+use xml_sax::reader::{EventReader, XmlEvent};
+
+use Result;
+use Error;
+
+/// Default maximum nesting depth of the elements
+pub const MAX_DEPTH: usize = 256;
+
+/// Default maximum number of elements in a document
+pub const MAX_ELEMENTS: usize = 100_000;
 
 /// Struct representing a single XML element with its attributes and
 /// children
@@ -14,19 +24,35 @@ pub struct Element {
     attributes: Vec<OwnedAttribute>,
     namespace: Namespace,
     children: Vec<Element>,
+    text: String,
 }
 
 impl Element {
     /// Return the child element named `name` or `None` if it doesn't
     /// exist.
     pub fn child(&self, name: &str) -> Option<&Element> {
-        for c in &self.children {
-            if c.name.local_name == name {
-                return Some(c);
-            }
-        }
+        self.children_named(name).next()
+    }
+
+    /// Namespace-aware version of `child`: return the first child
+    /// named `name` in the namespace `ns` (a URI, `None` for no
+    /// namespace).
+    ///
+    /// LastPass responses don't use namespaces so `child` is
+    /// sufficient for them, this is meant for generic documents.
+    #[allow(dead_code)]
+    pub fn child_ns(&self, ns: Option<&str>, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| name_matches(&c.name, ns, name))
+    }
 
-        None
+    /// Return an iterator over all the children named `name` in
+    /// document order
+    pub fn children_named<'a, 'n>(&'a self,
+                                  name: &'n str) -> ChildrenNamed<'a, 'n> {
+        ChildrenNamed {
+            children: self.children.iter(),
+            name: name,
+        }
     }
 
     /// Return the attribute named `name` or `None` if it doesn't
@@ -34,6 +60,49 @@ impl Element {
     pub fn attribute(&self, name: &str) -> Option<&OwnedAttribute> {
         self.attributes.iter().find(|a| a.name.local_name == name)
     }
+
+    /// Namespace-aware version of `attribute`, see `child_ns`
+    #[allow(dead_code)]
+    pub fn attribute_ns(&self,
+                        ns: Option<&str>,
+                        name: &str) -> Option<&OwnedAttribute> {
+        self.attributes.iter().find(|a| name_matches(&a.name, ns, name))
+    }
+
+    /// Return the text content of the element. If the text is
+    /// interleaved with child elements all the pieces are
+    /// concatenated. Whitespace-only text between elements is
+    /// ignored.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+fn unbalanced() -> Error {
+    Error::BadProtocol("Unbalanced XML tags".to_owned())
+}
+
+/// Return `true` if `name` has the local name `local_name` and the
+/// namespace URI `ns`
+fn name_matches(name: &OwnedName, ns: Option<&str>, local_name: &str) -> bool {
+    name.local_name == local_name &&
+        name.namespace.as_ref().map(|n| &**n) == ns
+}
+
+/// Iterator over the children of an element with a given name
+pub struct ChildrenNamed<'a, 'n> {
+    children: slice::Iter<'a, Element>,
+    name: &'n str,
+}
+
+impl<'a, 'n> Iterator for ChildrenNamed<'a, 'n> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        let name = self.name;
+
+        self.children.find(|c| c.name.local_name == name)
+    }
 }
 
 /// DOM-style XML parser
@@ -67,18 +136,34 @@ fn print_recursive(f: &mut fmt::Formatter,
 }
 
 impl Dom {
-    /// Parse the XML file in `reader`
-    pub fn parse<R: Read>(reader: R) -> Result<Dom, Error> {
+    /// Parse the XML file in `reader` using the default
+    /// `MAX_DEPTH` and `MAX_ELEMENTS` limits
+    pub fn parse<R: Read>(reader: R) -> Result<Dom> {
+        Dom::parse_with_limits(reader, MAX_DEPTH, MAX_ELEMENTS)
+    }
+
+    /// Parse the XML file in `reader`. Fails with
+    /// `Error::BadProtocol` if elements are nested deeper than
+    /// `max_depth` or if there are more than `max_elements` elements
+    /// in total.
+    ///
+    /// The `xml` crate doesn't expand custom entities so there's no
+    /// need to bound entity expansion.
+    pub fn parse_with_limits<R: Read>(reader: R,
+                                      max_depth: usize,
+                                      max_elements: usize) -> Result<Dom> {
         let parser = EventReader::new(reader);
 
-        let root = try!(Dom::do_parse(parser));
+        let root = try!(Dom::do_parse(parser, max_depth, max_elements));
 
         Ok(Dom {
             root: root,
         })
     }
 
-    fn do_parse<R: Read>(parser: EventReader<R>) -> Result<Element, Error> {
+    fn do_parse<R: Read>(parser: EventReader<R>,
+                         max_depth: usize,
+                         max_elements: usize) -> Result<Element> {
         let root = Element {
             name: OwnedName {
                 local_name: "[root]".to_owned(),
@@ -88,41 +173,73 @@ impl Dom {
             attributes: Vec::new(),
             namespace: Namespace::empty(),
             children: Vec::new(),
+            text: String::new(),
         };
 
         let mut element_stack = vec![root];
+        let mut element_count = 0;
 
         for e in parser {
             let e = try!(e);
 
             match e {
                 XmlEvent::StartElement { name, attributes, namespace } => {
+                    // The root node doesn't count
+                    if element_stack.len() > max_depth {
+                        let err = "XML document nested too deeply";
+                        return Err(Error::BadProtocol(err.to_owned()));
+                    }
+
+                    element_count += 1;
+
+                    if element_count > max_elements {
+                        let err = "XML document has too many elements";
+                        return Err(Error::BadProtocol(err.to_owned()));
+                    }
+
                     let child = Element {
                         name: name,
                         attributes: attributes,
                         namespace: namespace,
                         children: Vec::new(),
+                        text: String::new(),
                     };
 
                     element_stack.push(child);
                 }
                 XmlEvent::EndElement { name } => {
+                    // The XML parser should raise an error before we
+                    // get there but we don't want to panic if it
+                    // doesn't.
+                    if element_stack.len() < 2 {
+                        return Err(unbalanced());
+                    }
+
                     let elem = element_stack.pop().unwrap();
 
-                    // This shouldn't happen as the XML parser should
-                    // raise an error in this situation.
-                    assert!(name == elem.name);
+                    if name != elem.name {
+                        return Err(unbalanced());
+                    }
 
                     let parent = element_stack.last_mut().unwrap();
 
                     parent.children.push(elem);
                 }
+                // Whitespace-only text is reported as
+                // `XmlEvent::Whitespace` and ignored
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    let elem = element_stack.last_mut().unwrap();
+
+                    elem.text.push_str(&text);
+                }
                 _ => (),
             }
         }
 
         // We should only be left with the root node
-        assert!(element_stack.len() == 1);
+        if element_stack.len() != 1 {
+            return Err(unbalanced());
+        }
 
         Ok(element_stack.pop().unwrap())
     }
@@ -147,3 +264,124 @@ impl fmt::Debug for Dom {
         write!(f, "{:?}", self.root)
     }
 }
+
+#[test]
+fn test_text() {
+    let xml = b"<response>\n\
+                  <ok uid=\"42\">  hello <b>bold</b> world</ok>\n\
+                  <msg><![CDATA[<raw>]]></msg>\n\
+                  <empty/>\n\
+                </response>";
+
+    let dom = Dom::parse(&xml[..]).unwrap();
+
+    let ok = dom.element(&["response", "ok"]).unwrap();
+
+    assert_eq!(ok.text(), "  hello  world");
+    assert_eq!(ok.child("b").unwrap().text(), "bold");
+    assert_eq!(dom.element(&["response", "msg"]).unwrap().text(), "<raw>");
+    assert_eq!(dom.element(&["response", "empty"]).unwrap().text(), "");
+    assert_eq!(dom.element(&["response"]).unwrap().text(), "");
+}
+
+#[test]
+fn test_children_named() {
+    let xml = b"<accounts>\
+                  <account id=\"1\"/>\
+                  <group/>\
+                  <account id=\"2\"/>\
+                  <account id=\"3\"/>\
+                </accounts>";
+
+    let dom = Dom::parse(&xml[..]).unwrap();
+
+    let accounts = dom.element(&["accounts"]).unwrap();
+
+    let ids: Vec<_> =
+        accounts.children_named("account")
+        .map(|a| &*a.attribute("id").unwrap().value)
+        .collect();
+
+    assert_eq!(ids, vec!["1", "2", "3"]);
+
+    assert_eq!(accounts.child("account").unwrap()
+               .attribute("id").unwrap().value, "1");
+    assert_eq!(accounts.children_named("nothing").count(), 0);
+}
+
+#[test]
+fn test_namespaces() {
+    let xml = b"<root xmlns:a=\"urn:a\" xmlns:b=\"urn:b\">\
+                  <a:item a:id=\"1\" b:id=\"2\"/>\
+                  <b:item id=\"3\"/>\
+                  <item/>\
+                </root>";
+
+    let dom = Dom::parse(&xml[..]).unwrap();
+
+    let root = dom.element(&["root"]).unwrap();
+
+    let a = root.child_ns(Some("urn:a"), "item").unwrap();
+    let b = root.child_ns(Some("urn:b"), "item").unwrap();
+
+    assert_eq!(a.attribute_ns(Some("urn:a"), "id").unwrap().value, "1");
+    assert_eq!(a.attribute_ns(Some("urn:b"), "id").unwrap().value, "2");
+    assert!(a.attribute_ns(None, "id").is_none());
+    assert_eq!(b.attribute_ns(None, "id").unwrap().value, "3");
+
+    assert!(root.child_ns(None, "item").unwrap().attributes.is_empty());
+    assert!(root.child_ns(Some("urn:c"), "item").is_none());
+
+    // The namespace-agnostic lookup returns the first match
+    assert_eq!(root.child("item").unwrap().attribute("id").unwrap().value,
+               "1");
+}
+
+#[test]
+fn test_limits() {
+    let nested = |depth: usize| {
+        let mut xml = String::new();
+
+        for _ in 0..depth {
+            xml.push_str("<a>");
+        }
+
+        for _ in 0..depth {
+            xml.push_str("</a>");
+        }
+
+        xml
+    };
+
+    assert!(Dom::parse(nested(MAX_DEPTH).as_bytes()).is_ok());
+
+    match Dom::parse(nested(MAX_DEPTH + 1).as_bytes()) {
+        Err(Error::BadProtocol(_)) => (),
+        _ => panic!("Deeply nested XML was accepted"),
+    }
+
+    match Dom::parse(nested(100_000).as_bytes()) {
+        Err(Error::BadProtocol(_)) => (),
+        _ => panic!("Deeply nested XML was accepted"),
+    }
+
+    let xml = b"<a><b/><b/><b/></a>";
+
+    assert!(Dom::parse_with_limits(&xml[..], 2, 4).is_ok());
+    assert!(Dom::parse_with_limits(&xml[..], 1, 4).is_err());
+    assert!(Dom::parse_with_limits(&xml[..], 2, 3).is_err());
+}
+
+#[test]
+fn test_unbalanced() {
+    let invalid: &[&[u8]] = &[
+        b"<a></b>",
+        b"<a><b></a></b>",
+        b"<a><b></b>",
+        b"</a>",
+    ];
+
+    for &xml in invalid {
+        assert!(Dom::parse(xml).is_err());
+    }
+}